@@ -11,12 +11,14 @@ use std::time::{Duration, SystemTime};
 use anyhow::{anyhow, Result};
 use filetime::FileTime;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use symbolic::common::ByteView;
 use tempfile::NamedTempFile;
 use tokio::fs::File;
 use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 
-use crate::config::{CacheConfig, Config};
+use crate::config::{CacheConfig, Config, InMemoryCacheConfig, ObjectCompressionConfig};
+use crate::utils::checksum::{self, Checksum};
 
 /// Starting content of cache items whose writing failed.
 ///
@@ -40,6 +42,12 @@ pub const MALFORMED_MARKER: &[u8] = b"malformed";
 /// are covered by the negative cache state.
 pub const CACHE_SPECIFIC_ERROR_MARKER: &[u8] = b"cachespecificerror";
 
+/// Name of the directory inside a cache holding content-addressed items.
+///
+/// Cache keys never start with a `.`, so this can not clash with any cache item.
+/// See [`Cache::persist_item`] for details.
+const CONTENT_DIR: &str = ".content";
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum CacheStatus {
     /// A cache item that represents the presence of something. E.g. we succeeded in downloading an
@@ -171,6 +179,16 @@ pub struct SharedCacheConfig {
     #[serde(default = "default_max_upload_queue_size")]
     pub max_upload_queue_size: usize,
 
+    /// Enables sharing of negative and malformed cache items, which expire after this time.
+    ///
+    /// Normally only positive items are shared.  When this is set, the fact that an item is
+    /// missing or malformed is shared as well, so that other symbolicators do not need to
+    /// rediscover this from the upstream sources.  These items are shared for at most this
+    /// duration, after which they are recomputed.  This should be kept short since unlike
+    /// positive items they are expected to change.
+    #[serde(default, with = "humantime_serde")]
+    pub negative_ttl: Option<Duration>,
+
     /// The backend to use for the shared cache.
     #[serde(flatten)]
     pub backend: SharedCacheBackendConfig,
@@ -246,6 +264,12 @@ pub struct Cache {
 
     /// The maximum number of lazy refreshes of this cache.
     max_lazy_refreshes: Arc<AtomicIsize>,
+
+    /// Configuration of the in-memory tier in front of this cache.
+    in_memory: InMemoryCacheConfig,
+
+    /// Configuration of on-disk compression, only used by the objects cache.
+    compression: ObjectCompressionConfig,
 }
 
 impl Cache {
@@ -266,6 +290,8 @@ impl Cache {
             start_time: SystemTime::now(),
             cache_config,
             max_lazy_refreshes,
+            in_memory: InMemoryCacheConfig::default(),
+            compression: ObjectCompressionConfig::default(),
         })
     }
 
@@ -281,12 +307,32 @@ impl Cache {
         self.max_lazy_refreshes.clone()
     }
 
+    pub fn in_memory(&self) -> InMemoryCacheConfig {
+        self.in_memory
+    }
+
+    pub fn compression(&self) -> ObjectCompressionConfig {
+        self.compression
+    }
+
+    /// Whether an item with the given status is small enough for the in-memory tier.
+    ///
+    /// Non-positive items only consist of a status marker.  Positive items are only kept for
+    /// caches whose items are known to be small.
+    pub fn fits_in_memory(&self, status: &CacheStatus) -> bool {
+        match status {
+            CacheStatus::Positive => matches!(self.name, CacheName::ObjectMeta),
+            _ => true,
+        }
+    }
+
     pub fn cleanup(&self) -> Result<()> {
         tracing::info!("Cleaning up cache: {}", self.name);
         let cache_dir = self.cache_dir.clone().ok_or_else(|| {
             anyhow!("no caching configured! Did you provide a path to your config file?")
         })?;
 
+        let content_dir = cache_dir.join(CONTENT_DIR);
         let mut directories = vec![cache_dir];
         while !directories.is_empty() {
             let directory = directories.pop().unwrap();
@@ -302,7 +348,10 @@ impl Cache {
             for entry in entries {
                 let entry = entry?;
                 let path = entry.path();
-                if path.is_dir() {
+                if path == content_dir {
+                    // Content is only removed once the items referencing it are gone.
+                    continue;
+                } else if path.is_dir() {
                     directories.push(path.to_owned());
                 } else if let Err(e) = self.try_cleanup_path(&path) {
                     sentry::with_scope(
@@ -313,7 +362,55 @@ impl Cache {
             }
         }
 
-        Ok(())
+        self.cleanup_content(&content_dir)
+    }
+
+    /// Verifies the checksums of all items of the given cache `versions`.
+    ///
+    /// All items of these versions must have been written with a checksum, items with a
+    /// missing or mismatching checksum are corrupt.  Corrupt items are removed unless
+    /// `dry_run` is set, so they are recomputed the next time they are requested.
+    pub fn verify(&self, versions: &[u32], dry_run: bool) -> Result<VerifyStats> {
+        tracing::info!("Verifying cache: {}", self.name);
+        let cache_dir = self.cache_dir.clone().ok_or_else(|| {
+            anyhow!("no caching configured! Did you provide a path to your config file?")
+        })?;
+
+        let mut stats = VerifyStats::default();
+        let mut directories: Vec<_> = versions
+            .iter()
+            .map(|version| cache_dir.join(version.to_string()))
+            .collect();
+        while let Some(directory) = directories.pop() {
+            let entries = match catch_not_found(|| read_dir(directory))? {
+                Some(x) => x,
+                None => continue,
+            };
+
+            for entry in entries {
+                let path = entry?.path();
+                if path.is_dir() {
+                    directories.push(path);
+                    continue;
+                }
+                let data = match catch_not_found(|| ByteView::open(&path))? {
+                    Some(data) => data,
+                    None => continue,
+                };
+                match checksum::verify(&data) {
+                    Checksum::Valid => stats.valid += 1,
+                    Checksum::Missing | Checksum::Invalid => {
+                        tracing::warn!("Corrupt cache item {}", path.display());
+                        stats.corrupt += 1;
+                        if !dry_run {
+                            catch_not_found(|| remove_file(&path))?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(stats)
     }
 
     fn try_cleanup_path(&self, path: &Path) -> Result<()> {
@@ -415,6 +512,114 @@ impl Cache {
         })
     }
 
+    /// Whether positive items of this cache are stored content-addressed.
+    ///
+    /// This relies on hardlinks and their link count, so it is only supported on unix.
+    fn is_content_addressed(&self) -> bool {
+        cfg!(unix) && matches!(self.name, CacheName::Objects)
+    }
+
+    /// Moves a freshly computed item from `temp_file` to its final `cache_path`.
+    ///
+    /// For content-addressed caches, positive items are stored only once per content in the
+    /// [`CONTENT_DIR`] and hardlinked to their `cache_path`.  This way identical files from
+    /// different sources or scopes share their disk space, and [`Cache::cleanup`] removes the
+    /// content once no cache item links to it anymore.
+    ///
+    /// Since hardlinks share their inode, all items with the same content also share their
+    /// mtime.  A hit on any of them thus keeps all of them from expiring, which is fine since
+    /// they are identical downloads.
+    ///
+    /// This hashes the whole file and must not be called on the async runtime.
+    pub fn persist_item(
+        &self,
+        temp_file: NamedTempFile,
+        cache_path: &Path,
+        status: &CacheStatus,
+    ) -> io::Result<()> {
+        let cache_dir = match self.cache_dir {
+            Some(ref cache_dir) if self.is_content_addressed() => cache_dir,
+            _ => {
+                temp_file.persist(cache_path).map_err(|x| x.error)?;
+                return Ok(());
+            }
+        };
+        if *status != CacheStatus::Positive {
+            temp_file.persist(cache_path).map_err(|x| x.error)?;
+            return Ok(());
+        }
+
+        let hash = format!("{:x}", Sha256::digest(&ByteView::open(temp_file.path())?));
+        let mut content_path = cache_dir.join(CONTENT_DIR);
+        content_path.push(&hash[..2]);
+        content_path.push(&hash);
+
+        // Link next to the final location first, so an existing item is replaced atomically.
+        let parent = cache_path.parent().unwrap_or(cache_dir);
+        let link_path = parent.join(format!(".{}.tmp", uuid::Uuid::new_v4()));
+
+        // Linking to existing content must not be preceded by a separate check for its
+        // existence, since cleanup may remove unreferenced content at any time.  Content only
+        // ever exists with a link count of at least two while it is being referenced.
+        match std::fs::hard_link(&content_path, &link_path) {
+            Ok(()) => {
+                metric!(counter(&format!("caches.{}.file.deduplicated", self.name)) += 1);
+                // The content might have been unused for a while, make sure it does not expire
+                // right away now that it is referenced again.
+                filetime::set_file_mtime(&link_path, FileTime::now())?;
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                temp_file.persist(&link_path).map_err(|x| x.error)?;
+                std::fs::create_dir_all(content_path.parent().unwrap_or(cache_dir))?;
+                match std::fs::hard_link(&link_path, &content_path) {
+                    Ok(()) => (),
+                    // Another item with the same content was persisted concurrently.  This
+                    // item keeps its own copy, which is still a valid cache item.
+                    Err(err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+                    Err(err) => {
+                        remove_file(&link_path).ok();
+                        return Err(err);
+                    }
+                }
+            }
+            Err(err) => return Err(err),
+        }
+
+        std::fs::rename(&link_path, cache_path).map_err(|err| {
+            remove_file(&link_path).ok();
+            err
+        })
+    }
+
+    /// Removes content-addressed items which are no longer linked to by any cache item.
+    fn cleanup_content(&self, content_dir: &Path) -> Result<()> {
+        let mut directories = vec![content_dir.to_owned()];
+        while let Some(directory) = directories.pop() {
+            let entries = match catch_not_found(|| read_dir(directory))? {
+                Some(x) => x,
+                None => continue,
+            };
+
+            for entry in entries {
+                let path = entry?.path();
+                if path.is_dir() {
+                    directories.push(path);
+                    continue;
+                }
+                let metadata = match catch_not_found(|| path.metadata())? {
+                    Some(metadata) => metadata,
+                    None => continue,
+                };
+                if link_count(&metadata) == Some(1) {
+                    tracing::debug!("Removing unreferenced {}", path.display());
+                    catch_not_found(|| remove_file(&path))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Create a new temporary file to use in the cache.
     pub fn tempfile(&self) -> io::Result<NamedTempFile> {
         match self.tmp_dir {
@@ -472,6 +677,19 @@ fn expiration_strategy(cache_config: &CacheConfig, path: &Path) -> io::Result<Ex
     Ok(strategy)
 }
 
+/// Returns the number of hardlinks to a file, if supported on this platform.
+#[cfg(unix)]
+fn link_count(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.nlink())
+}
+
+/// Returns the number of hardlinks to a file, if supported on this platform.
+#[cfg(not(unix))]
+fn link_count(_metadata: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
 fn catch_not_found<F, R>(f: F) -> io::Result<Option<R>>
 where
     F: FnOnce() -> io::Result<R>,
@@ -485,6 +703,15 @@ where
     }
 }
 
+/// The outcome of verifying a cache with [`Cache::verify`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VerifyStats {
+    /// Number of items with a valid checksum.
+    pub valid: usize,
+    /// Number of corrupt items.
+    pub corrupt: usize,
+}
+
 pub struct Caches {
     /// Caches for object files, used by [`crate::services::objects::ObjectsActor`].
     pub objects: Cache,
@@ -515,7 +742,7 @@ impl Caches {
         ));
 
         let tmp_dir = config.cache_dir("tmp");
-        Ok(Self {
+        let mut caches = Self {
             objects: {
                 let path = config.cache_dir("objects");
                 Cache::from_config(
@@ -576,7 +803,20 @@ impl Caches {
                     Default::default(),
                 )?
             },
-        })
+        };
+
+        for cache in [
+            &mut caches.objects,
+            &mut caches.object_meta,
+            &mut caches.auxdifs,
+            &mut caches.symcaches,
+            &mut caches.cficaches,
+        ] {
+            cache.in_memory = config.caches.in_memory;
+        }
+        caches.objects.compression = config.caches.object_compression;
+
+        Ok(caches)
     }
 
     /// Clear the temporary files.
@@ -639,6 +879,28 @@ pub fn cleanup(config: Config) -> Result<()> {
     Caches::from_config(&config)?.cleanup()
 }
 
+/// Entry function for the verify-cache command.
+///
+/// This will verify all caches and remove any corrupt items, unless `dry_run` is set.
+pub fn verify(config: Config, dry_run: bool) -> Result<()> {
+    let Caches { symcaches, .. } = Caches::from_config(&config)?;
+
+    // Only symcaches are written with checksums, all other caches can not be verified.
+    let versions: Vec<_> = crate::services::symcaches::checksummed_versions().collect();
+    let stats = symcaches.verify(&versions, dry_run)?;
+    tracing::info!(
+        "Verified cache {}: {} valid, {} corrupt",
+        symcaches.name(),
+        stats.valid,
+        stats.corrupt,
+    );
+
+    if dry_run && stats.corrupt > 0 {
+        anyhow::bail!("found {} corrupt cache items", stats.corrupt);
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1203,6 +1465,7 @@ mod tests {
                 diagnostics: DiagnosticsCacheConfig {
                     retention: Some(Duration::from_secs(60)),
                 },
+                ..Default::default()
             },
             ..Default::default()
         })
@@ -1226,6 +1489,105 @@ mod tests {
         assert!(!diagnostics_entry.is_file());
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_content_addressed_objects() -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let tempdir = tempdir()?;
+        let cache_dir = tempdir.path().join("objects");
+        let cache = Cache::from_config(
+            CacheName::Objects,
+            Some(cache_dir.clone()),
+            Some(tempdir.path().join("tmp")),
+            CacheConfig::from(CacheConfigs::default().downloaded),
+            Default::default(),
+        )?;
+
+        let persist = |path: &Path| -> Result<()> {
+            create_dir_all(path.parent().unwrap())?;
+            let mut temp_file = cache.tempfile()?;
+            temp_file.write_all(b"some object")?;
+            cache.persist_item(temp_file, path, &CacheStatus::Positive)?;
+            Ok(())
+        };
+
+        let global_entry = cache_dir.join("global/some_object");
+        let scoped_entry = cache_dir.join("scoped/some_object");
+        persist(&global_entry)?;
+        persist(&scoped_entry)?;
+
+        // Both entries share the same content, which is linked from the content directory.
+        assert_eq!(fs::read(&global_entry)?, b"some object");
+        assert_eq!(global_entry.metadata()?.ino(), scoped_entry.metadata()?.ino());
+        assert_eq!(global_entry.metadata()?.nlink(), 3);
+
+        // Content stays around as long as it is referenced.
+        fs::remove_file(&global_entry)?;
+        cache.cleanup()?;
+        assert_eq!(fs::read(&scoped_entry)?, b"some object");
+        assert_eq!(scoped_entry.metadata()?.nlink(), 2);
+
+        fs::remove_file(&scoped_entry)?;
+        cache.cleanup()?;
+        let content_files = fs::read_dir(cache_dir.join(CONTENT_DIR))?
+            .map(|entry| fs::read_dir(entry?.path()).map(|entries| entries.count()))
+            .sum::<io::Result<usize>>()?;
+        assert_eq!(content_files, 0);
+
+        // Persisting again after the content was cleaned up stores it anew.
+        persist(&global_entry)?;
+        assert_eq!(fs::read(&global_entry)?, b"some object");
+        assert_eq!(global_entry.metadata()?.nlink(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify() -> Result<()> {
+        let tempdir = tempdir()?;
+        let cache_dir = tempdir.path().join("symcaches");
+        let cache = Cache::from_config(
+            CacheName::Symcaches,
+            Some(cache_dir.clone()),
+            None,
+            CacheConfig::from(CacheConfigs::default().derived),
+            Default::default(),
+        )?;
+
+        create_dir_all(cache_dir.join("2/global"))?;
+        let valid = cache_dir.join("2/global/valid");
+        fs::write(&valid, "contents")?;
+        checksum::append_to(&valid)?;
+        let truncated = cache_dir.join("2/global/truncated");
+        fs::write(&truncated, "contents")?;
+        checksum::append_to(&truncated)?;
+        let data = fs::read(&truncated)?;
+        fs::write(&truncated, &data[..data.len() - 4])?;
+        let corrupt = cache_dir.join("2/global/corrupt");
+        fs::write(&corrupt, "contents")?;
+        checksum::append_to(&corrupt)?;
+        fs::write(&corrupt, &[b"C", &fs::read(&corrupt)?[1..]].concat())?;
+        // Items of versions written without checksums are not verified.
+        create_dir_all(cache_dir.join("1/global"))?;
+        let unchecked = cache_dir.join("1/global/unchecked");
+        fs::write(&unchecked, "contents")?;
+
+        let stats = cache.verify(&[2], true)?;
+        assert_eq!((stats.valid, stats.corrupt), (1, 2));
+        assert!(truncated.is_file());
+        assert!(corrupt.is_file());
+
+        let stats = cache.verify(&[2], false)?;
+        assert_eq!((stats.valid, stats.corrupt), (1, 2));
+        assert!(valid.is_file());
+        assert!(!truncated.is_file());
+        assert!(!corrupt.is_file());
+        assert!(unchecked.is_file());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_cache_status_write_positive() -> Result<()> {
         let dir = tempdir()?;
@@ -1448,6 +1810,7 @@ mod tests {
 
         assert_eq!(cfg.max_upload_queue_size, 400);
         assert_eq!(cfg.max_concurrent_uploads, 20);
+        assert_eq!(cfg.negative_ttl, None);
         match cfg.backend {
             SharedCacheBackendConfig::Gcs(_) => panic!("wrong backend"),
             SharedCacheBackendConfig::Filesystem(cfg) => {
@@ -1461,6 +1824,7 @@ mod tests {
         let yaml = r#"
             max_upload_queue_size: 50
             max_concurrent_uploads: 50
+            negative_ttl: 5m
             filesystem:
               path: "/path/to/somewhere"
         "#;
@@ -1468,6 +1832,7 @@ mod tests {
 
         assert_eq!(cfg.max_upload_queue_size, 50);
         assert_eq!(cfg.max_concurrent_uploads, 50);
+        assert_eq!(cfg.negative_ttl, Some(Duration::from_secs(300)));
         assert!(matches!(
             cfg.backend,
             SharedCacheBackendConfig::Filesystem(_)
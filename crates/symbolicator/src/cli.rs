@@ -9,6 +9,7 @@ use crate::config::Config;
 use crate::logging;
 use crate::metrics;
 use crate::server;
+use crate::services::symbolication;
 
 fn get_crate_version() -> &'static str {
     env!("CARGO_PKG_VERSION")
@@ -34,6 +35,26 @@ enum Command {
     /// Clean local caches.
     #[structopt(name = "cleanup")]
     Cleanup,
+
+    /// Verify local caches and remove corrupt items.
+    #[structopt(name = "verify-cache")]
+    VerifyCache {
+        /// Only report corrupt items without removing them.
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+    },
+
+    /// Internal. Run a stackwalk worker subprocess, reading requests from stdin.
+    #[structopt(name = "stackwalk-worker", setting = structopt::clap::AppSettings::Hidden)]
+    StackwalkWorker {
+        /// Maximum address space of the worker in bytes.
+        #[structopt(long = "memory-limit")]
+        memory_limit: Option<u64>,
+
+        /// Maximum CPU time in seconds to spend on a single request.
+        #[structopt(long = "cpu-limit")]
+        cpu_limit: Option<u64>,
+    },
 }
 
 /// Command line interface parser.
@@ -61,6 +82,16 @@ impl Cli {
 /// Runs the main application.
 pub fn execute() -> Result<()> {
     let cli = Cli::from_args();
+
+    // Workers communicate over stdout, so they must not initialize logging or anything else.
+    if let Command::StackwalkWorker {
+        memory_limit,
+        cpu_limit,
+    } = cli.command
+    {
+        return symbolication::run_stackwalk_worker(memory_limit, cpu_limit);
+    }
+
     let config = Config::get(cli.config()).context("failed loading config")?;
 
     #[cfg(feature = "symbolicator-crash")]
@@ -119,6 +150,10 @@ pub fn execute() -> Result<()> {
     match cli.command {
         Command::Run => server::run(config).context("failed to start the server")?,
         Command::Cleanup => cache::cleanup(config).context("failed to clean up caches")?,
+        Command::VerifyCache { dry_run } => {
+            cache::verify(config, dry_run).context("failed to verify caches")?
+        }
+        Command::StackwalkWorker { .. } => unreachable!("handled above"),
     }
 
     Ok(())
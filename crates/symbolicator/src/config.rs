@@ -13,6 +13,7 @@ use tracing::level_filters::LevelFilter;
 
 use crate::cache::SharedCacheConfig;
 use crate::sources::SourceConfig;
+use crate::types::Glob;
 
 /// Controls the log format
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize)]
@@ -167,6 +168,53 @@ impl Default for DiagnosticsCacheConfig {
     }
 }
 
+/// Fine-tuning the in-memory tier in front of the filesystem caches.
+///
+/// The filesystem cache remains the source of truth, the in-memory tier only saves the
+/// syscalls needed to look up small items again.
+#[derive(Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct InMemoryCacheConfig {
+    /// Maximum number of items kept in memory per cache, `0` disables the in-memory tier.
+    pub max_entries: usize,
+
+    /// Maximum duration an item is served from memory before the filesystem is consulted again.
+    #[serde(with = "humantime_serde")]
+    pub max_age: Duration,
+}
+
+impl Default for InMemoryCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 0,
+            max_age: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Fine-tuning compression of the objects cache.
+///
+/// Compressed objects are transparently decompressed into a temporary file when they are
+/// used, trading CPU time for disk space.
+#[derive(Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct ObjectCompressionConfig {
+    /// Minimum size in bytes for objects to be stored compressed, `None` disables compression.
+    pub min_size: Option<u64>,
+
+    /// The zstd compression level.
+    pub level: i32,
+}
+
+impl Default for ObjectCompressionConfig {
+    fn default() -> Self {
+        Self {
+            min_size: None,
+            level: 3,
+        }
+    }
+}
+
 /// Struct to treat all cache configs identical in cache code.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum CacheConfig {
@@ -230,6 +278,110 @@ pub struct CacheConfigs {
     ///
     /// E.g. minidumps which caused a crash in symbolicator will be stored here.
     pub diagnostics: DiagnosticsCacheConfig,
+    /// Configure the in-memory tier for small cache items.
+    pub in_memory: InMemoryCacheConfig,
+    /// Configure on-disk compression of downloaded objects.
+    pub object_compression: ObjectCompressionConfig,
+}
+
+/// Limits for stackwalking minidumps.
+///
+/// Requests can override these limits via their `stackwalk` options.  Overrides can raise a
+/// limit up to its `max_requested_*` counterpart, which defaults to the limit itself, so that
+/// requests can only lower it.
+#[derive(Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct StackwalkConfig {
+    /// Maximum number of stackwalking iterations.
+    ///
+    /// Every iteration after the first fetches the CFI of modules which were newly found by
+    /// the previous iteration.
+    pub max_iterations: usize,
+
+    /// Timeout for a single stackwalking iteration.
+    #[serde(with = "humantime_serde")]
+    pub iteration_timeout: Duration,
+
+    /// Timeout for stackwalking a minidump, including all iterations and CFI downloads.
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+
+    /// Maximum number of frames returned per thread.
+    pub max_frames: usize,
+
+    /// Maximum `max_iterations` that requests may ask for.
+    pub max_requested_iterations: Option<usize>,
+
+    /// Maximum `iteration_timeout` that requests may ask for.
+    #[serde(with = "humantime_serde")]
+    pub max_requested_iteration_timeout: Option<Duration>,
+
+    /// Maximum `timeout` that requests may ask for.
+    #[serde(with = "humantime_serde")]
+    pub max_requested_timeout: Option<Duration>,
+
+    /// Maximum `max_frames` that requests may ask for.
+    pub max_requested_frames: Option<usize>,
+
+    /// Whether to stackwalk minidumps in a pool of isolated worker subprocesses.
+    ///
+    /// This protects the server from minidumps which crash the stackwalker or exhaust its
+    /// memory.  The size of the pool is given by `processing_pool_size`.
+    pub isolate: bool,
+
+    /// Maximum address space of a worker subprocess in bytes.
+    pub worker_memory_limit: Option<u64>,
+
+    /// Maximum CPU time a worker subprocess may spend on a single minidump.
+    #[serde(with = "humantime_serde")]
+    pub worker_cpu_limit: Option<Duration>,
+}
+
+impl Default for StackwalkConfig {
+    fn default() -> Self {
+        Self {
+            max_iterations: 5,
+            iteration_timeout: Duration::from_secs(60),
+            timeout: Duration::from_secs(3600),
+            max_frames: 20000,
+            max_requested_iterations: None,
+            max_requested_iteration_timeout: None,
+            max_requested_timeout: None,
+            max_requested_frames: None,
+            isolate: false,
+            worker_memory_limit: None,
+            worker_cpu_limit: None,
+        }
+    }
+}
+
+/// Scrubbing of personal data from minidumps.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MinidumpSanitizerConfig {
+    /// Whether to sanitize minidumps before persisting them in the diagnostics cache.
+    pub diagnostics: bool,
+
+    /// Whether to discard minidumps which cannot be sanitized instead of persisting them
+    /// unmodified in the diagnostics cache.
+    pub discard_unsanitized: bool,
+
+    /// Number of bytes of each thread's stack memory to keep, starting at the stack pointer.
+    pub stack_window: u64,
+
+    /// Module paths matching any of these patterns are reduced to their file name.
+    pub module_paths: Vec<Glob>,
+}
+
+impl Default for MinidumpSanitizerConfig {
+    fn default() -> Self {
+        Self {
+            diagnostics: false,
+            discard_unsanitized: false,
+            stack_window: 8 * 1024,
+            module_paths: Vec::new(),
+        }
+    }
 }
 
 /// See docs/index.md for more information on config values.
@@ -306,6 +458,12 @@ pub struct Config {
     /// caches from already running symbolicators.
     pub shared_cache: Option<SharedCacheConfig>,
 
+    /// Limits for stackwalking minidumps.
+    pub stackwalk: StackwalkConfig,
+
+    /// Scrubbing of personal data from persisted and sanitized minidumps.
+    pub minidump_sanitizer: MinidumpSanitizerConfig,
+
     /// Internal. Enables crash handling and sets the absolute path to where minidumps should be
     /// cached on disk. The path is created if it doesn't exist. Path must be UTF-8.
     #[serde(default)]
@@ -380,6 +538,8 @@ impl Default for Config {
             streaming_timeout: Duration::from_secs(250),
             max_concurrent_requests: Some(120),
             shared_cache: None,
+            stackwalk: StackwalkConfig::default(),
+            minidump_sanitizer: MinidumpSanitizerConfig::default(),
             _crash_db: None,
         }
     }
@@ -488,6 +648,84 @@ mod tests {
         assert_eq!(cfg.caches.diagnostics, DiagnosticsCacheConfig::default());
     }
 
+    #[test]
+    fn test_in_memory_cache_config() {
+        let cfg = Config::get(None).unwrap();
+        assert_eq!(cfg.caches.in_memory.max_entries, 0);
+
+        let yaml = r#"
+            caches:
+              in_memory:
+                max_entries: 10000
+        "#;
+        let cfg = Config::from_reader(yaml.as_bytes()).unwrap();
+        assert_eq!(cfg.caches.in_memory.max_entries, 10000);
+        assert_eq!(
+            cfg.caches.in_memory.max_age,
+            InMemoryCacheConfig::default().max_age
+        );
+        assert_eq!(cfg.caches.derived, DerivedCacheConfig::default());
+    }
+
+    #[test]
+    fn test_object_compression_config() {
+        let cfg = Config::get(None).unwrap();
+        assert_eq!(cfg.caches.object_compression.min_size, None);
+
+        let yaml = r#"
+            caches:
+              object_compression:
+                min_size: 1048576
+        "#;
+        let cfg = Config::from_reader(yaml.as_bytes()).unwrap();
+        assert_eq!(cfg.caches.object_compression.min_size, Some(1048576));
+        assert_eq!(
+            cfg.caches.object_compression.level,
+            ObjectCompressionConfig::default().level
+        );
+    }
+
+    #[test]
+    fn test_stackwalk_config() {
+        let cfg = Config::get(None).unwrap();
+        assert_eq!(cfg.stackwalk, StackwalkConfig::default());
+
+        let yaml = r#"
+            stackwalk:
+              max_iterations: 2
+              timeout: 5m
+              max_requested_timeout: 1h
+        "#;
+        let cfg = Config::from_reader(yaml.as_bytes()).unwrap();
+        assert_eq!(cfg.stackwalk.max_iterations, 2);
+        assert_eq!(cfg.stackwalk.timeout, Duration::from_secs(300));
+        assert_eq!(
+            cfg.stackwalk.max_requested_timeout,
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(cfg.stackwalk.max_requested_iterations, None);
+        assert_eq!(
+            cfg.stackwalk.max_frames,
+            StackwalkConfig::default().max_frames
+        );
+    }
+
+    #[test]
+    fn test_minidump_sanitizer_config() {
+        let yaml = r#"
+            minidump_sanitizer:
+              stack_window: 1024
+              module_paths:
+                - "/home/*"
+        "#;
+        let cfg = Config::from_reader(yaml.as_bytes()).unwrap();
+        let sanitizer = &cfg.minidump_sanitizer;
+        assert!(!sanitizer.diagnostics);
+        assert!(!sanitizer.discard_unsanitized);
+        assert_eq!(sanitizer.stack_window, 1024);
+        assert!(sanitizer.module_paths[0].matches("/home/user/libfoo.so"));
+    }
+
     #[test]
     fn test_disabling_expiry() {
         // It should be possible to set a cache value to `None` meaning "do not expire".
@@ -1,11 +1,17 @@
+use std::sync::Arc;
+
+use axum::body::Body;
 use axum::extract;
-use axum::http::StatusCode;
+use axum::extract::multipart::Field;
+use axum::http::{Response, StatusCode};
 use axum::response::Json;
 use symbolic::common::ByteView;
+use tempfile::TempPath;
 use tokio::fs::File;
 
 use crate::endpoints::symbolicate::SymbolicationRequestQueryParams;
 use crate::services::Service;
+use crate::sources::SourceConfig;
 use crate::types::{RequestOptions, SymbolicationResponse};
 use crate::utils::sentry::ConfigureScope;
 
@@ -15,31 +21,73 @@ use super::ResponseError;
 pub async fn handle_minidump_request(
     extract::Extension(state): extract::Extension<Service>,
     extract::Query(params): extract::Query<SymbolicationRequestQueryParams>,
-    mut multipart: extract::Multipart,
+    multipart: extract::Multipart,
 ) -> Result<Json<SymbolicationResponse>, ResponseError> {
     sentry::start_session();
 
     params.configure_scope();
 
+    let (minidump_file, sources, options) = read_minidump_request(&state, multipart).await?;
+    let symbolication = state.symbolication();
+    let request_id =
+        symbolication.process_minidump(params.scope, minidump_file, sources, options)?;
+
+    match symbolication.get_response(request_id, params.timeout).await {
+        Some(response) => Ok(Json(response)),
+        None => Err("symbolication request did not start".into()),
+    }
+}
+
+pub async fn handle_symbolicated_minidump_request(
+    extract::Extension(state): extract::Extension<Service>,
+    extract::Query(params): extract::Query<SymbolicationRequestQueryParams>,
+    multipart: extract::Multipart,
+) -> Result<Response<Body>, ResponseError> {
+    sentry::start_session();
+
+    params.configure_scope();
+
+    let (minidump_file, sources, options) = read_minidump_request(&state, multipart).await?;
+    let symbolication = state.symbolication();
+    let request_id =
+        symbolication.symbolicate_minidump(params.scope, minidump_file, sources, options)?;
+
+    // The binary response cannot be polled for, so pending requests time out instead.
+    let response = match symbolication.get_response(request_id, params.timeout).await {
+        Some(SymbolicationResponse::Completed(response)) => response,
+        Some(SymbolicationResponse::Pending { .. } | SymbolicationResponse::Timeout) => {
+            return Err((StatusCode::GATEWAY_TIMEOUT, "symbolication timed out").into());
+        }
+        Some(SymbolicationResponse::Failed { message }) => {
+            return Err((StatusCode::BAD_REQUEST, anyhow::anyhow!(message)).into());
+        }
+        Some(SymbolicationResponse::InternalError) => {
+            return Err("symbolication request failed".into());
+        }
+        None => return Err("symbolication request did not start".into()),
+    };
+    let minidump = response
+        .symbolicated_minidump
+        .ok_or("symbolicated minidump is missing")?;
+
+    Ok(Response::builder()
+        .header("content-length", minidump.len())
+        .header("content-type", "application/octet-stream")
+        .body(Body::from(minidump.to_vec()))?)
+}
+
+/// Reads the minidump, sources and options of a minidump request.
+async fn read_minidump_request(
+    state: &Service,
+    mut multipart: extract::Multipart,
+) -> Result<(TempPath, Arc<[SourceConfig]>, RequestOptions), ResponseError> {
     let mut minidump = None;
     let mut sources = state.config().default_sources();
     let mut options = RequestOptions::default();
 
     while let Some(field) = multipart.next_field().await? {
         match field.name() {
-            Some("upload_file_minidump") => {
-                let mut minidump_file = tempfile::Builder::new();
-                minidump_file.prefix("minidump").suffix(".dmp");
-                let minidump_file = if let Some(tmp_dir) = state.config().cache_dir("tmp") {
-                    minidump_file.tempfile_in(tmp_dir)
-                } else {
-                    minidump_file.tempfile()
-                }?;
-                let (file, temp_path) = minidump_file.into_parts();
-                let mut file = File::from_std(file);
-                stream_multipart_file(field, &mut file).await?;
-                minidump = Some(temp_path)
-            }
+            Some("upload_file_minidump") => minidump = Some(save_minidump(state, field).await?),
             Some("sources") => {
                 let data = read_multipart_data(field, 1024 * 1024).await?; // 1Mb
                 sources = serde_json::from_slice(&data)?;
@@ -65,20 +113,56 @@ pub async fn handle_minidump_request(
         )
             .into());
     }
-    let symbolication = state.symbolication();
-    let request_id =
-        symbolication.process_minidump(params.scope, minidump_file, sources, options)?;
 
-    match symbolication.get_response(request_id, params.timeout).await {
-        Some(response) => Ok(Json(response)),
-        None => Err("symbolication request did not start".into()),
+    Ok((minidump_file, sources, options))
+}
+
+pub async fn handle_sanitize_minidump_request(
+    extract::Extension(state): extract::Extension<Service>,
+    mut multipart: extract::Multipart,
+) -> Result<Response<Body>, ResponseError> {
+    let mut minidump = None;
+
+    while let Some(field) = multipart.next_field().await? {
+        if field.name() == Some("upload_file_minidump") {
+            minidump = Some(save_minidump(&state, field).await?);
+        }
     }
+
+    let minidump_file = minidump.ok_or((StatusCode::BAD_REQUEST, "missing minidump"))?;
+    let sanitized = state
+        .symbolication()
+        .sanitize_minidump(minidump_file)
+        .await
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+
+    Ok(Response::builder()
+        .header("content-length", sanitized.len())
+        .header("content-type", "application/octet-stream")
+        .body(Body::from(sanitized))?)
+}
+
+/// Streams an uploaded minidump into a temporary file.
+async fn save_minidump(state: &Service, field: Field<'_>) -> Result<TempPath, ResponseError> {
+    let mut minidump_file = tempfile::Builder::new();
+    minidump_file.prefix("minidump").suffix(".dmp");
+    let minidump_file = if let Some(tmp_dir) = state.config().cache_dir("tmp") {
+        minidump_file.tempfile_in(tmp_dir)
+    } else {
+        minidump_file.tempfile()
+    }?;
+    let (file, temp_path) = minidump_file.into_parts();
+    let mut file = File::from_std(file);
+    stream_multipart_file(field, &mut file).await?;
+    Ok(temp_path)
 }
 
 #[cfg(test)]
 mod tests {
     use reqwest::{multipart, Client, StatusCode};
 
+    use crate::config::Config;
+    use crate::services::Service;
     use crate::test;
     use crate::types::SymbolicationResponse;
 
@@ -143,6 +227,113 @@ mod tests {
     //     insta::assert_yaml_snapshot!(response);
     // }
 
+    #[tokio::test]
+    async fn test_sanitize() {
+        test::setup();
+
+        let service = test::default_service().await;
+        let server = test::Server::with_service(service);
+
+        let file_contents = test::read_fixture("linux.dmp");
+        let file_part = multipart::Part::bytes(file_contents.clone()).file_name("linux.dmp");
+        let form = multipart::Form::new().part("upload_file_minidump", file_part);
+
+        let response = Client::new()
+            .post(server.url("/minidump/sanitize"))
+            .multipart(form)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.bytes().await.unwrap();
+        assert_eq!(body.len(), file_contents.len());
+        assert_ne!(body.as_ref(), file_contents.as_slice());
+    }
+
+    #[tokio::test]
+    async fn test_sanitize_invalid() {
+        test::setup();
+
+        let service = test::default_service().await;
+        let server = test::Server::with_service(service);
+
+        let file_part = multipart::Part::bytes(b"not a minidump".to_vec()).file_name("invalid.dmp");
+        let form = multipart::Form::new().part("upload_file_minidump", file_part);
+
+        let response = Client::new()
+            .post(server.url("/minidump/sanitize"))
+            .multipart(form)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_symbolicated() {
+        test::setup();
+
+        let service = test::default_service().await;
+        let server = test::Server::with_service(service);
+
+        let file_contents = test::read_fixture("linux.dmp");
+        let file_part = multipart::Part::bytes(file_contents).file_name("linux.dmp");
+        let form = multipart::Form::new()
+            .part("upload_file_minidump", file_part)
+            .text("sources", "[]");
+
+        let response = Client::new()
+            .post(server.url("/minidump/symbolicated"))
+            .multipart(form)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/octet-stream"
+        );
+
+        // The symbolicated stacktraces are written to the Sentry stackwalk extension stream.
+        let body = response.bytes().await.unwrap();
+        let minidump = minidump::Minidump::read(body.as_ref()).unwrap();
+        assert!(minidump.get_raw_stream(0x5379_0001).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_symbolicated_max_requests() {
+        test::setup();
+
+        let config = Config {
+            max_concurrent_requests: Some(0),
+            ..Config::default()
+        };
+        let handle = tokio::runtime::Handle::current();
+        let service = Service::create(config, handle.clone(), handle)
+            .await
+            .unwrap();
+        let server = test::Server::with_service(service);
+
+        let file_contents = test::read_fixture("linux.dmp");
+        let file_part = multipart::Part::bytes(file_contents).file_name("linux.dmp");
+        let form = multipart::Form::new()
+            .part("upload_file_minidump", file_part)
+            .text("sources", "[]");
+
+        let response = Client::new()
+            .post(server.url("/minidump/symbolicated"))
+            .multipart(form)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
     #[tokio::test]
     async fn test_unknown_field() {
         test::setup();
@@ -7,6 +7,7 @@ use crate::metrics::MetricsLayer;
 use crate::services::Service;
 
 mod applecrashreport;
+mod coredump;
 mod error;
 mod minidump;
 mod multipart;
@@ -17,7 +18,10 @@ mod symbolicate;
 pub use error::ResponseError;
 
 use self::minidump::handle_minidump_request as minidump;
+use self::minidump::handle_sanitize_minidump_request as sanitize_minidump;
+use self::minidump::handle_symbolicated_minidump_request as symbolicated_minidump;
 use applecrashreport::handle_apple_crash_report_request as applecrashreport;
+use coredump::handle_coredump_request as coredump;
 use proxy::proxy_symstore_request as proxy;
 use requests::poll_request as requests;
 use symbolicate::symbolicate_frames as symbolicate;
@@ -38,7 +42,10 @@ pub fn create_app(service: Service) -> Router {
         .route("/proxy/*path", get(proxy).head(proxy))
         .route("/requests/:request_id", get(requests))
         .route("/applecrashreport", post(applecrashreport))
+        .route("/coredump", post(coredump))
         .route("/minidump", post(minidump))
+        .route("/minidump/sanitize", post(sanitize_minidump))
+        .route("/minidump/symbolicated", post(symbolicated_minidump))
         .route("/symbolicate", post(symbolicate))
         .layer(layer)
         // the healthcheck is last, as it will bypass all the middlewares
@@ -6,7 +6,7 @@ use crate::services::symbolication::{StacktraceOrigin, SymbolicateStacktraces};
 use crate::services::Service;
 use crate::sources::SourceConfig;
 use crate::types::{
-    RawObjectInfo, RawStacktrace, RequestOptions, Scope, Signal, SymbolicationResponse,
+    HexValue, RawObjectInfo, RawStacktrace, RequestOptions, Scope, Signal, SymbolicationResponse,
 };
 use crate::utils::sentry::ConfigureScope;
 
@@ -44,6 +44,8 @@ pub struct SymbolicationRequestBody {
     #[serde(default)]
     pub modules: Vec<RawObjectInfo>,
     #[serde(default)]
+    pub crash_address: Option<HexValue>,
+    #[serde(default)]
     pub options: RequestOptions,
 }
 
@@ -72,6 +74,7 @@ pub async fn symbolicate_frames(
         origin: StacktraceOrigin::Symbolicate,
         stacktraces: body.stacktraces,
         modules: body.modules.into_iter().map(From::from).collect(),
+        crash_address: body.crash_address.map(|addr| addr.0),
         options: body.options,
     })?;
 
@@ -80,3 +83,204 @@ pub async fn symbolicate_frames(
         None => Err("symbolication request did not start".into()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Arc;
+
+    use reqwest::{Client, StatusCode};
+    use serde_json::json;
+
+    use crate::sources::{FilesystemSourceConfig, SourceConfig, SourceId};
+    use crate::test;
+
+    #[tokio::test]
+    async fn test_il_frame() {
+        test::setup();
+
+        let service = test::default_service().await;
+        let server = test::Server::with_service(service);
+
+        let request = json!({
+            "sources": [test::local_source()],
+            "modules": [{
+                "type": "pe_dotnet",
+                "debug_id": "04030201-0605-0807-090a-0b0c0d0e0f10-5eb63a04",
+                "debug_file": "Program.pdb",
+                "code_file": "Program.dll",
+            }],
+            "stacktraces": [{
+                "frames": [{
+                    "addr_mode": "il:0",
+                    "instruction_addr": "0x6",
+                    "function_id": "0x6000001",
+                }],
+            }],
+        });
+
+        let response = Client::new()
+            .post(server.url("/symbolicate"))
+            .header("content-type", "application/json")
+            .body(request.to_string())
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.text().await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            response["modules"][0]["debug_status"], "found",
+            "{:#}",
+            response
+        );
+
+        let frame = &response["stacktraces"][0]["frames"][0];
+        assert_eq!(frame["status"], "symbolicated", "{:#}", response);
+        assert_eq!(frame["package"], "Program.dll");
+        assert_eq!(frame["abs_path"], "/src/Program.cs");
+        assert_eq!(frame["filename"], "Program.cs");
+        assert_eq!(frame["lineno"], 12);
+        assert_eq!(frame["function_id"], "0x6000001");
+    }
+
+    #[tokio::test]
+    async fn test_mixed_js_frames() {
+        test::setup();
+
+        let service = test::default_service().await;
+        let server = test::Server::with_service(service);
+
+        let source_map = json!({
+            "version": 3,
+            "sources": ["src/app.js"],
+            "sourcesContent": ["function explode() {\n  throw new Error(\"boom\");\n}\n"],
+            "names": ["explode"],
+            "mappings": "AAAAA",
+        })
+        .to_string();
+
+        // One script embeds its source map, the other one is referenced by the module.
+        let js_dir = test::tempdir();
+        fs::create_dir_all(js_dir.path().join("static")).unwrap();
+        fs::write(
+            js_dir.path().join("static/app.min.js"),
+            format!(
+                "function a(){{throw new Error(\"boom\")}}\n\
+                 //# sourceMappingURL=data:application/json;base64,{}\n",
+                base64::encode(&source_map)
+            ),
+        )
+        .unwrap();
+        fs::write(js_dir.path().join("static/lib.min.js.map"), &source_map).unwrap();
+
+        let js_source = SourceConfig::Filesystem(Arc::new(FilesystemSourceConfig {
+            id: SourceId::new("js"),
+            path: js_dir.path().to_owned(),
+            files: Default::default(),
+        }));
+
+        let request = json!({
+            "sources": [test::local_source(), js_source],
+            "modules": [{
+                "type": "sourcemap",
+                "code_file": "http://example.com/static/app.min.js",
+            }, {
+                "type": "macho",
+                "debug_id": "502fc0a5-1ec1-3e47-9998-684fa139dca7",
+                "code_id": "502fc0a51ec13e479998684fa139dca7",
+                "image_addr": "0x100000000",
+                "image_size": 4096,
+            }, {
+                "type": "sourcemap",
+                "code_file": "http://example.com/static/lib.min.js",
+                "debug_file": "http://example.com/static/lib.min.js.map",
+            }],
+            "stacktraces": [{
+                "frames": [{
+                    "addr_mode": "js:0",
+                    "lineno": 1,
+                    "colno": 10,
+                }, {
+                    "instruction_addr": "0x100000fa0",
+                }, {
+                    "addr_mode": "js:2",
+                    "lineno": 1,
+                    "colno": 1,
+                }],
+            }],
+        });
+
+        let response = Client::new()
+            .post(server.url("/symbolicate"))
+            .header("content-type", "application/json")
+            .body(request.to_string())
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.text().await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&body).unwrap();
+        for module in response["modules"].as_array().unwrap() {
+            assert_eq!(module["debug_status"], "found", "{:#}", response);
+        }
+
+        let frames = &response["stacktraces"][0]["frames"];
+        for frame in frames.as_array().unwrap() {
+            assert_eq!(frame["status"], "symbolicated", "{:#}", response);
+        }
+
+        let frame = &frames[0];
+        assert_eq!(frame["addr_mode"], "js:0");
+        assert_eq!(frame["package"], "http://example.com/static/app.min.js");
+        assert_eq!(frame["function"], "explode");
+        assert_eq!(frame["abs_path"], "src/app.js");
+        assert_eq!(frame["filename"], "app.js");
+        assert_eq!(frame["lineno"], 1);
+        assert_eq!(frame["colno"], 1);
+        assert_eq!(frame["context_line"], "function explode() {");
+
+        let frame = &frames[1];
+        assert_eq!(frame["function"], "main");
+        assert_eq!(frame["abs_path"], "/tmp/hello.c");
+
+        let frame = &frames[2];
+        assert_eq!(frame["addr_mode"], "js:2");
+        assert_eq!(frame["package"], "http://example.com/static/lib.min.js");
+        assert_eq!(frame["function"], "explode");
+        assert_eq!(frame["lineno"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_missing_instruction_addr() {
+        test::setup();
+
+        let service = test::default_service().await;
+        let server = test::Server::with_service(service);
+
+        let request = json!({
+            "sources": [],
+            "modules": [],
+            "stacktraces": [{
+                "frames": [{
+                    "addr_mode": "rel:0",
+                    "lineno": 1,
+                }],
+            }],
+        });
+
+        let response = Client::new()
+            .post(server.url("/symbolicate"))
+            .header("content-type", "application/json")
+            .body(request.to_string())
+            .send()
+            .await
+            .unwrap();
+
+        assert!(response.status().is_client_error(), "{}", response.status());
+    }
+}
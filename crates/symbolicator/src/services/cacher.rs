@@ -4,6 +4,7 @@ use std::io::{Error, ErrorKind};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::Context;
 use futures::channel::oneshot;
@@ -15,8 +16,10 @@ use tempfile::NamedTempFile;
 use tokio::fs;
 
 use crate::cache::{Cache, CacheStatus};
+use crate::config::ObjectCompressionConfig;
 use crate::services::shared_cache::{CacheStoreReason, SharedCacheKey, SharedCacheService};
 use crate::types::Scope;
+use crate::utils::checksum::{self, Checksum};
 use crate::utils::futures::CallOnDrop;
 
 type ComputationResult<T, E> = Result<Arc<T>, Arc<E>>;
@@ -28,6 +31,20 @@ type CacheResultFuture<T, E> = BoxFuture<'static, ComputationResult<T, E>>;
 
 type ComputationMap<T, E> = Arc<Mutex<BTreeMap<CacheKey, ComputationChannel<T, E>>>>;
 
+/// An item kept in the in-memory tier, see [`MemoryTier`].
+#[derive(Debug, Clone)]
+struct MemoryItem {
+    inserted_at: Instant,
+    status: CacheStatus,
+    data: ByteView<'static>,
+    path: PathBuf,
+}
+
+/// An LRU cache of small cache items keyed by their cache key and version.
+///
+/// This sits in front of the filesystem cache, which remains the source of truth.
+type MemoryTier = Arc<Mutex<lru::LruCache<(CacheKey, u32), MemoryItem>>>;
+
 /// Manages a filesystem cache of any kind of data that can be serialized into bytes and read from
 /// it:
 ///
@@ -38,7 +55,8 @@ type ComputationMap<T, E> = Arc<Mutex<BTreeMap<CacheKey, ComputationChannel<T, E
 /// Transparently performs cache lookups, downloads and cache stores via the [`CacheItemRequest`]
 /// trait and associated types.
 ///
-/// Internally deduplicates concurrent cache lookups (in-memory).
+/// Internally deduplicates concurrent cache lookups (in-memory).  Small items can optionally be
+/// served from an in-memory LRU tier, see [`InMemoryCacheConfig`](crate::config::InMemoryCacheConfig).
 #[derive(Debug)]
 pub struct Cacher<T: CacheItemRequest> {
     config: Cache,
@@ -46,6 +64,9 @@ pub struct Cacher<T: CacheItemRequest> {
     /// Used for deduplicating cache lookups.
     current_computations: ComputationMap<T::Item, T::Error>,
 
+    /// Small recently used items, `None` if the in-memory tier is disabled.
+    memory_tier: Option<MemoryTier>,
+
     /// A service used to communicate with the shared cache.
     shared_cache_service: Arc<SharedCacheService>,
 }
@@ -56,6 +77,7 @@ impl<T: CacheItemRequest> Clone for Cacher<T> {
         Cacher {
             config: self.config.clone(),
             current_computations: self.current_computations.clone(),
+            memory_tier: self.memory_tier.clone(),
             shared_cache_service: Arc::clone(&self.shared_cache_service),
         }
     }
@@ -63,19 +85,27 @@ impl<T: CacheItemRequest> Clone for Cacher<T> {
 
 impl<T: CacheItemRequest> Cacher<T> {
     pub fn new(config: Cache, shared_cache_service: Arc<SharedCacheService>) -> Self {
+        let max_entries = config.in_memory().max_entries;
+        let memory_tier = (max_entries > 0)
+            .then(|| Arc::new(Mutex::new(lru::LruCache::new(max_entries))));
         Cacher {
             config,
             shared_cache_service,
             current_computations: Arc::new(Mutex::new(BTreeMap::new())),
+            memory_tier,
         }
     }
 
     pub fn tempfile(&self) -> std::io::Result<NamedTempFile> {
         self.config.tempfile()
     }
+
+    pub fn compression(&self) -> ObjectCompressionConfig {
+        self.config.compression()
+    }
 }
 
-#[derive(Debug, Clone, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct CacheKey {
     pub cache_key: String,
     pub scope: Scope,
@@ -190,6 +220,12 @@ pub trait CacheItemRequest: 'static + Send + Sync + Clone {
         fallbacks: &[],
     };
 
+    /// The first cache version whose items are always written with a checksum trailer.
+    ///
+    /// Positive items of this or a later version without a valid checksum are discarded as
+    /// corrupt.  Defaults to `None` for caches which are written without checksums.
+    const CHECKSUM_VERSION: Option<u32> = None;
+
     /// Returns the key by which this item is cached.
     fn get_cache_key(&self) -> CacheKey;
 
@@ -216,6 +252,66 @@ pub trait CacheItemRequest: 'static + Send + Sync + Clone {
 }
 
 impl<T: CacheItemRequest> Cacher<T> {
+    /// Whether the cache item `data` of the given `version` fails checksum verification.
+    ///
+    /// A missing checksum on a version that is written with checksums means that the item
+    /// was truncated.  This hashes the whole item, which is done on the blocking thread pool so
+    /// that it does not block the runtime.
+    async fn is_corrupt(data: &ByteView<'static>, version: u32) -> bool {
+        match T::CHECKSUM_VERSION {
+            Some(checksum_version) if version >= checksum_version => {
+                let data = data.clone();
+                tokio::task::spawn_blocking(move || checksum::verify(&data) != Checksum::Valid)
+                    .await
+                    .unwrap_or(true)
+            }
+            _ => false,
+        }
+    }
+
+    /// Looks up an item in the in-memory tier.
+    ///
+    /// Items which exceed the configured `max_age` are evicted so that the next lookup goes
+    /// to the filesystem again.
+    fn lookup_memory_tier(&self, key: &CacheKey, version: u32) -> Option<MemoryItem> {
+        let memory_tier = self.memory_tier.as_ref()?;
+        let max_age = self.config.in_memory().max_age;
+
+        let mut memory_tier = memory_tier.lock();
+        let memory_key = (key.clone(), version);
+        if let Some(item) = memory_tier.get(&memory_key) {
+            if item.inserted_at.elapsed() < max_age {
+                return Some(item.clone());
+            }
+        }
+        memory_tier.pop(&memory_key);
+        None
+    }
+
+    /// Puts an item into the in-memory tier if it is small enough to be kept there.
+    fn insert_memory_tier(
+        &self,
+        key: &CacheKey,
+        version: u32,
+        status: &CacheStatus,
+        data: &[u8],
+        path: &Path,
+    ) {
+        if let Some(ref memory_tier) = self.memory_tier {
+            if !self.config.fits_in_memory(status) {
+                return;
+            }
+            // Copy the data so we do not keep the file mapped after it was cleaned up.
+            let item = MemoryItem {
+                inserted_at: Instant::now(),
+                status: status.clone(),
+                data: ByteView::from_vec(data.to_vec()),
+                path: path.to_owned(),
+            };
+            memory_tier.lock().put((key.clone(), version), item);
+        }
+    }
+
     /// Look up an item in the file system cache and load it if available.
     ///
     /// Returns `Ok(None)` if the cache item needs to be re-computed, otherwise reads the
@@ -236,6 +332,20 @@ impl<T: CacheItemRequest> Cacher<T> {
         match self.config.cache_dir() {
             Some(cache_dir) => {
                 let name = self.config.name();
+
+                if let Some(item) = self.lookup_memory_tier(key, version) {
+                    if item.status != CacheStatus::Positive || request.should_load(&item.data) {
+                        metric!(counter(&format!("caches.{}.memory.hit", name)) += 1);
+                        let item = request.load(
+                            key.scope.clone(),
+                            item.status,
+                            item.data,
+                            CachePath::Cached(item.path),
+                        );
+                        return Ok(Some(item));
+                    }
+                }
+
                 let item_path = key.cache_path(cache_dir, version);
                 tracing::trace!("Trying {} cache at path {}", name, item_path.display());
                 let _scope = Hub::current().push_scope();
@@ -250,6 +360,13 @@ impl<T: CacheItemRequest> Cacher<T> {
                     None => return Ok(None),
                 };
                 let status = CacheStatus::from_content(&byteview);
+                // Items are verified whenever they are opened from the file system.  Hits in the
+                // in-memory tier are not, since they were verified when they were opened.
+                if status == CacheStatus::Positive && Self::is_corrupt(&byteview, version).await {
+                    tracing::warn!("Discarding corrupt {} at path {}", name, item_path.display());
+                    metric!(counter(&format!("caches.{}.file.corrupt", name)) += 1);
+                    return Ok(None);
+                }
                 if status == CacheStatus::Positive && !request.should_load(&byteview) {
                     tracing::trace!("Discarding {} at path {}", name, item_path.display());
                     metric!(counter(&format!("caches.{}.file.discarded", name)) += 1);
@@ -283,6 +400,8 @@ impl<T: CacheItemRequest> Cacher<T> {
                     "hit" => "true"
                 );
 
+                self.insert_memory_tier(key, version, &status, &byteview, &item_path);
+
                 tracing::trace!("Loading {} at path {}", name, item_path.display());
                 let item = request.load(
                     key.scope.clone(),
@@ -333,18 +452,27 @@ impl<T: CacheItemRequest> Cacher<T> {
             .shared_cache_service
             .fetch(&shared_cache_key, &mut temp_fd)
             .await;
+        let shared_negative_hit = !shared_cache_hit
+            && self
+                .shared_cache_service
+                .fetch_negative(&shared_cache_key, &mut temp_fd)
+                .await;
 
-        let status = match shared_cache_hit {
-            true => {
-                // Waste an mmap call on a cold path, oh well.
-                let bv = ByteView::map_file_ref(temp_file.as_file())?;
-                request
-                    .should_load(&bv)
-                    .then(|| CacheStatus::from_content(&bv))
+        let status = if shared_cache_hit {
+            // Waste an mmap call on a cold path, oh well.
+            let bv = ByteView::map_file_ref(temp_file.as_file())?;
+            (!Self::is_corrupt(&bv, T::VERSIONS.current).await && request.should_load(&bv))
+                .then(|| CacheStatus::from_content(&bv))
+        } else if shared_negative_hit {
+            let bv = ByteView::map_file_ref(temp_file.as_file())?;
+            match CacheStatus::from_content(&bv) {
+                status @ (CacheStatus::Negative | CacheStatus::Malformed(_)) => Some(status),
+                _ => None,
             }
-            false => None,
+        } else {
+            None
         };
-        if shared_cache_hit && status.is_none() {
+        if (shared_cache_hit || shared_negative_hit) && status.is_none() {
             tracing::trace!("Discarding item from shared cache {}", key);
             metric!(counter("shared_cache.file.discarded") += 1);
         }
@@ -379,7 +507,23 @@ impl<T: CacheItemRequest> Cacher<T> {
                     )
                 })?;
                 fs::create_dir_all(parent).await?;
-                temp_file.persist(&cache_path).map_err(|x| x.error)?;
+
+                // Persisting may hash the whole file, which must not block the runtime.
+                let config = self.config.clone();
+                let persist_path = cache_path.clone();
+                let persist_status = status.clone();
+                tokio::task::spawn_blocking(move || {
+                    config.persist_item(temp_file, &persist_path, &persist_status)
+                })
+                .await
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))??;
+                self.insert_memory_tier(
+                    &key,
+                    T::VERSIONS.current,
+                    &status,
+                    &byte_view,
+                    &cache_path,
+                );
 
                 metric!(
                     counter(&format!("caches.{}.file.write", self.config.name())) += 1,
@@ -403,14 +547,23 @@ impl<T: CacheItemRequest> Cacher<T> {
             None => CachePath::Temp(temp_file.into_temp_path()),
         };
 
-        // TODO: Not handling negative caches probably has a huge perf impact.  Need to
-        // figure out negative caches.  Maybe put them in redis with a TTL?
         // NOTE: temp_fd is still a valid filedescriptor to the file's data, even after we
         // persisted the file.
-        if !shared_cache_hit && status == CacheStatus::Positive {
-            self.shared_cache_service
-                .store(shared_cache_key, temp_fd, CacheStoreReason::New)
-                .await;
+        if !shared_cache_hit && !shared_negative_hit {
+            match status {
+                CacheStatus::Positive => {
+                    self.shared_cache_service
+                        .store(shared_cache_key, temp_fd, CacheStoreReason::New)
+                        .await;
+                }
+                // Cache-specific errors are usually transient and not worth sharing.
+                CacheStatus::Negative | CacheStatus::Malformed(_) => {
+                    self.shared_cache_service
+                        .store_negative(shared_cache_key, temp_fd)
+                        .await;
+                }
+                CacheStatus::CacheSpecificError(_) => (),
+            }
         }
 
         Ok(request.load(key.scope.clone(), status, byte_view, path))
@@ -603,8 +756,8 @@ mod tests {
     use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
     use std::time::Duration;
 
-    use crate::cache::CacheName;
-    use crate::config::{CacheConfig, CacheConfigs};
+    use crate::cache::{CacheName, Caches};
+    use crate::config::{CacheConfig, CacheConfigs, Config, InMemoryCacheConfig};
     use crate::test;
 
     use super::*;
@@ -763,4 +916,49 @@ mod tests {
 
         assert_eq!(num_outdated, 2);
     }
+
+    /// This test asserts that small items are served from the in-memory tier without touching
+    /// the filesystem again, and that other caches do not keep positive items in memory.
+    #[tokio::test]
+    async fn test_memory_tier() {
+        test::setup();
+
+        let cache_dir = test::tempdir().path().join("test");
+        let config = Config {
+            cache_dir: Some(cache_dir.clone()),
+            caches: CacheConfigs {
+                in_memory: InMemoryCacheConfig {
+                    max_entries: 10,
+                    max_age: Duration::from_secs(60),
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let caches = Caches::from_config(&config).unwrap();
+
+        for (cache, expected) in [
+            (caches.object_meta, "some cached contents"),
+            (caches.objects, "some new cached contents"),
+        ] {
+            let item_path = cache_dir
+                .join(cache.name().as_ref())
+                .join("1/global/some_cache_key");
+            std::fs::create_dir_all(item_path.parent().unwrap()).unwrap();
+            std::fs::write(&item_path, "some cached contents").unwrap();
+
+            let shared_cache = Arc::new(SharedCacheService::new(None).await);
+            let cacher = Cacher::new(cache, shared_cache);
+
+            let request = TestCacheItem::new("some_cache_key");
+
+            let first_result = cacher.compute_memoized(request.clone()).await;
+            assert_eq!(first_result.unwrap().as_str(), "some cached contents");
+
+            std::fs::remove_file(&item_path).unwrap();
+
+            let second_result = cacher.compute_memoized(request.clone()).await;
+            assert_eq!(second_result.unwrap().as_str(), expected);
+        }
+    }
 }
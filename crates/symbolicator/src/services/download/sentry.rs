@@ -203,6 +203,30 @@ impl SentryDownloader {
             return Ok(Vec::new());
         }
 
+        // See <sentry-repo>/src/sentry/constants.py KNOWN_DIF_FORMATS for these query strings.
+        let file_formats: Vec<_> = file_types
+            .iter()
+            .filter_map(|file_type| match file_type {
+                FileType::UuidMap => Some("uuidmap"),
+                FileType::BcSymbolMap => Some("bcsymbolmap"),
+                FileType::Pe => Some("pe"),
+                FileType::Pdb => Some("pdb"),
+                FileType::MachDebug | FileType::MachCode => Some("macho"),
+                FileType::ElfDebug | FileType::ElfCode => Some("elf"),
+                FileType::WasmDebug | FileType::WasmCode => Some("wasm"),
+                FileType::Breakpad => Some("breakpad"),
+                FileType::SourceBundle => Some("sourcebundle"),
+                FileType::Usym => None,
+                FileType::PortablePdb => Some("portablepdb"),
+                FileType::MinifiedSource | FileType::SourceMap => None,
+            })
+            .collect();
+
+        // Without a format filter, the query would return all files matching the identifiers.
+        if file_formats.is_empty() && !file_types.is_empty() {
+            return Ok(Vec::new());
+        }
+
         let mut index_url = source.url.clone();
         if let Some(ref debug_id) = object_id.debug_id {
             index_url
@@ -210,23 +234,10 @@ impl SentryDownloader {
                 .append_pair("debug_id", &debug_id.to_string());
         }
 
-        // See <sentry-repo>/src/sentry/constants.py KNOWN_DIF_FORMATS for these query strings.
         index_url.query_pairs_mut().extend_pairs(
-            file_types
-                .iter()
-                .filter_map(|file_type| match file_type {
-                    FileType::UuidMap => Some("uuidmap"),
-                    FileType::BcSymbolMap => Some("bcsymbolmap"),
-                    FileType::Pe => Some("pe"),
-                    FileType::Pdb => Some("pdb"),
-                    FileType::MachDebug | FileType::MachCode => Some("macho"),
-                    FileType::ElfDebug | FileType::ElfCode => Some("elf"),
-                    FileType::WasmDebug | FileType::WasmCode => Some("wasm"),
-                    FileType::Breakpad => Some("breakpad"),
-                    FileType::SourceBundle => Some("sourcebundle"),
-                    FileType::Usym => None,
-                })
-                .map(|val| ("file_formats", val)),
+            file_formats
+                .into_iter()
+                .map(|file_format| ("file_formats", file_format)),
         );
 
         if let Some(ref code_id) = object_id.code_id {
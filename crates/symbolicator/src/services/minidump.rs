@@ -1,4 +1,4 @@
-//! Code for extracting our custom minidump extension for client-side stack traces.
+//! Code for extracting and writing our custom minidump extension for client-side stack traces.
 //!
 //! The extension is a minidump stream with the ID `0x53790001`.
 //!
@@ -93,7 +93,8 @@
 //!          +-----------+
 //! ```
 
-use std::convert::TryFrom;
+use std::collections::{HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
 use std::fmt;
 
 use thiserror::Error;
@@ -124,6 +125,89 @@ pub fn parse_stacktraces_from_minidump(
     Ok(Some(stacktraces))
 }
 
+/// Embeds symbolicated stacktraces into a minidump file.
+///
+/// Returns a copy of the minidump in `buf` with the stacktraces written to the extension
+/// stream, replacing an existing extension stream.  The symbol of each frame is its function
+/// name, falling back to the raw symbol.
+pub fn write_stacktraces_to_minidump(
+    buf: &[u8],
+    stacktraces: &[types::CompleteStacktrace],
+) -> Result<Vec<u8>, ExtractStacktraceError> {
+    // Make sure the stream directory is valid before rewriting it.
+    minidump::Minidump::read(buf)?;
+
+    // Stacktraces may contain both the server-side and client-side trace of a thread, only
+    // write the first one.
+    let mut thread_ids = HashSet::new();
+    let threads: Vec<_> = stacktraces
+        .iter()
+        .filter(|stacktrace| thread_ids.insert(stacktrace.thread_id))
+        .map(|stacktrace| {
+            let frames = stacktrace
+                .frames
+                .iter()
+                .map(|frame| {
+                    let symbol = frame
+                        .raw
+                        .function
+                        .as_deref()
+                        .or(frame.raw.symbol.as_deref());
+                    (frame.raw.instruction_addr.0, symbol.unwrap_or_default())
+                })
+                .collect();
+            (stacktrace.thread_id.unwrap_or_default() as u32, frames)
+        })
+        .collect();
+
+    let extension = format::Format::write(&threads);
+    replace_stream(buf, MINIDUMP_EXTENSION_TYPE, &extension)
+        .ok_or_else(|| format::Error::TooLarge.into())
+}
+
+/// Replaces or adds a stream in the minidump in `buf`.
+///
+/// The stream data and a new stream directory are appended to the minidump, so all other
+/// streams remain at their original offsets.  Returns `None` if the resulting minidump
+/// exceeds the 4GiB addressable by minidump RVAs.
+fn replace_stream(buf: &[u8], stream_type: u32, data: &[u8]) -> Option<Vec<u8>> {
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let bytes = buf.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    };
+
+    let stream_count = read_u32(8)? as usize;
+    let directory_rva = read_u32(12)? as usize;
+    let directory = buf.get(directory_rva..directory_rva + stream_count * 12)?;
+
+    let mut out = buf.to_vec();
+    out.resize(out.len() + align_to_eight(out.len()), 0);
+    let stream_rva = u32::try_from(out.len()).ok()?;
+    out.extend_from_slice(data);
+    out.resize(out.len() + align_to_eight(out.len()), 0);
+    let new_directory_rva = u32::try_from(out.len()).ok()?;
+
+    let mut new_count = 0u32;
+    for entry in directory.chunks_exact(12) {
+        if entry[..4] != stream_type.to_le_bytes() {
+            out.extend_from_slice(entry);
+            new_count += 1;
+        }
+    }
+    out.extend_from_slice(&stream_type.to_le_bytes());
+    out.extend_from_slice(&u32::try_from(data.len()).ok()?.to_le_bytes());
+    out.extend_from_slice(&stream_rva.to_le_bytes());
+    new_count += 1;
+
+    if u32::try_from(out.len()).is_err() {
+        return None;
+    }
+
+    out[8..12].copy_from_slice(&new_count.to_le_bytes());
+    out[12..16].copy_from_slice(&new_directory_rva.to_le_bytes());
+    Some(out)
+}
+
 fn parse_stacktraces_from_raw_extension(
     buf: &[u8],
 ) -> Result<format::Format, ExtractStacktraceError> {
@@ -206,6 +290,9 @@ mod format {
         /// with.
         #[error("symbol index out of bounds for instruction address {0}")]
         SymbolIndexOutOfBounds(u64),
+        /// The minidump with the extension exceeds the maximum size of a minidump.
+        #[error("minidump too large for the extension")]
+        TooLarge,
     }
 
     #[derive(Debug)]
@@ -276,6 +363,49 @@ mod format {
             })
         }
 
+        /// Writes our custom minidump extension binary format.
+        ///
+        /// Each thread is given as its thread id and its frames, consisting of the instruction
+        /// address and symbol.  Identical symbols are only written once.
+        pub fn write(threads: &[(u32, Vec<(u64, &str)>)]) -> Vec<u8> {
+            let num_frames: usize = threads.iter().map(|(_, frames)| frames.len()).sum();
+
+            let mut thread_bytes = Vec::with_capacity(mem::size_of::<RawThread>() * threads.len());
+            let mut frame_bytes = Vec::with_capacity(mem::size_of::<RawFrame>() * num_frames);
+            let mut symbol_bytes = Vec::new();
+            let mut symbols = HashMap::new();
+
+            for (thread_id, frames) in threads {
+                let start_frame = (frame_bytes.len() / mem::size_of::<RawFrame>()) as u32;
+                thread_bytes.extend_from_slice(&thread_id.to_ne_bytes());
+                thread_bytes.extend_from_slice(&start_frame.to_ne_bytes());
+                thread_bytes.extend_from_slice(&(frames.len() as u32).to_ne_bytes());
+
+                for (instruction_addr, symbol) in frames {
+                    let symbol_offset = *symbols.entry(*symbol).or_insert_with(|| {
+                        let offset = symbol_bytes.len() as u32;
+                        symbol_bytes.extend_from_slice(symbol.as_bytes());
+                        offset
+                    });
+                    frame_bytes.extend_from_slice(&instruction_addr.to_ne_bytes());
+                    frame_bytes.extend_from_slice(&symbol_offset.to_ne_bytes());
+                    frame_bytes.extend_from_slice(&(symbol.len() as u32).to_ne_bytes());
+                }
+            }
+
+            let mut buf = Vec::new();
+            buf.extend_from_slice(&MINIDUMP_FORMAT_VERSION.to_ne_bytes());
+            buf.extend_from_slice(&(threads.len() as u32).to_ne_bytes());
+            buf.extend_from_slice(&(num_frames as u32).to_ne_bytes());
+            buf.extend_from_slice(&(symbol_bytes.len() as u32).to_ne_bytes());
+            for section in [thread_bytes, frame_bytes] {
+                buf.extend_from_slice(&section);
+                buf.resize(buf.len() + align_to_eight(section.len()), 0);
+            }
+            buf.extend_from_slice(&symbol_bytes);
+            buf
+        }
+
         /// An [`Iterator`] of [`Thread`] objects that are part of the extension.
         pub fn threads(&self) -> impl Iterator<Item = Thread> {
             self.threads.iter().map(move |raw_thread| Thread {
@@ -874,4 +1004,87 @@ mod tests {
         println!("{:#?}", parsed);
         assert!(parsed.is_ok());
     }
+
+    fn complete_stacktrace(thread_id: u64, frames: &[(u64, &str)]) -> types::CompleteStacktrace {
+        let frames = frames
+            .iter()
+            .map(|(instruction_addr, function)| types::SymbolicatedFrame {
+                status: types::FrameStatus::Symbolicated,
+                original_index: None,
+                raw: types::RawFrame {
+                    instruction_addr: hex::HexValue(*instruction_addr),
+                    function: Some(function.to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .collect();
+
+        types::CompleteStacktrace {
+            thread_id: Some(thread_id),
+            frames,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_write_extension() {
+        let threads = vec![
+            (1234, vec![(0xfffff7001, "main"), (0xfffff7002, "start")]),
+            (2345, vec![]),
+            (3456, vec![(0xfffff7003, "main")]),
+        ];
+        let buf = format::Format::write(&threads);
+
+        let parsed = parse_stacktraces_from_raw_extension(&buf).unwrap();
+        let parsed: Vec<_> = parsed
+            .threads()
+            .map(|thread| {
+                let frames: Vec<_> = thread
+                    .frames()
+                    .unwrap()
+                    .map(|frame| {
+                        let symbol = std::str::from_utf8(frame.symbol().unwrap()).unwrap();
+                        (frame.instruction_addr(), symbol)
+                    })
+                    .collect();
+                (thread.thread_id(), frames)
+            })
+            .collect();
+
+        assert_eq!(parsed, threads);
+    }
+
+    #[test]
+    fn test_write_stacktraces_to_minidump() {
+        let buf = crate::test::read_fixture("linux.dmp");
+        assert!(parse_stacktraces_from_minidump(&buf).unwrap().is_none());
+
+        let stacktraces = [complete_stacktrace(
+            1,
+            &[(0x1000, "crash"), (0x2000, "main")],
+        )];
+        let rewritten = write_stacktraces_to_minidump(&buf, &stacktraces).unwrap();
+
+        // Writing again replaces the existing extension.
+        let stacktraces = [complete_stacktrace(2, &[(0x3000, "abort")])];
+        let rewritten = write_stacktraces_to_minidump(&rewritten, &stacktraces).unwrap();
+
+        let parsed = parse_stacktraces_from_minidump(&rewritten)
+            .unwrap()
+            .unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].thread_id, Some(2));
+        assert_eq!(parsed[0].frames[0].instruction_addr, hex::HexValue(0x3000));
+        assert_eq!(parsed[0].frames[0].function.as_deref(), Some("abort"));
+
+        // All original streams are preserved.
+        let original = minidump::Minidump::read(buf.as_slice()).unwrap();
+        let dump = minidump::Minidump::read(rewritten.as_slice()).unwrap();
+        let threads = dump.get_stream::<minidump::MinidumpThreadList>().unwrap();
+        let original_threads = original
+            .get_stream::<minidump::MinidumpThreadList>()
+            .unwrap();
+        assert_eq!(threads.threads.len(), original_threads.threads.len());
+    }
 }
@@ -0,0 +1,468 @@
+//! Scrubbing of personal data from minidumps.
+//!
+//! Minidumps can contain heap memory, environment variables, command lines and file system
+//! paths of the crashing process.  The sanitizer rewrites a copy of a minidump in place: it
+//! only zeroes out or shortens data and never moves it, so that all offsets remain valid and
+//! the sanitized minidump can still be stackwalked.
+//!
+//! The sanitizer works on the raw minidump data and does not rely on the minidump being
+//! otherwise well-formed, since minidumps which failed to process are the ones persisted for
+//! diagnostics.  All multi-byte values in minidumps are little-endian.
+
+use std::convert::TryInto;
+use std::ops::Range;
+
+use thiserror::Error;
+
+use crate::config::MinidumpSanitizerConfig;
+
+const MINIDUMP_SIGNATURE: u32 = 0x504d_444d;
+const UNUSED_STREAM: u32 = 0;
+const THREAD_LIST_STREAM: u32 = 3;
+const MODULE_LIST_STREAM: u32 = 4;
+const MEMORY_LIST_STREAM: u32 = 5;
+const MEMORY_64_LIST_STREAM: u32 = 9;
+const UNLOADED_MODULE_LIST_STREAM: u32 = 14;
+const LINUX_CMD_LINE: u32 = 0x4767_0006;
+const LINUX_ENVIRON: u32 = 0x4767_0007;
+const LINUX_MAPS: u32 = 0x4767_0009;
+
+/// The signature of a PDB 7.0 CodeView record, which contains the path to the PDB file.
+const CV_SIGNATURE_PDB70: u32 = 0x5344_5352;
+
+/// An error sanitizing a minidump.
+#[derive(Debug, Error)]
+pub enum SanitizeError {
+    #[error("not a minidump")]
+    InvalidHeader,
+
+    #[error("malformed minidump stream {0:#x}")]
+    MalformedStream(u32),
+}
+
+/// An entry in the stream directory of a minidump.
+#[derive(Debug, Clone, Copy)]
+struct Stream {
+    /// Offset of the directory entry in the minidump.
+    entry: usize,
+    stream_type: u32,
+    data: (usize, usize),
+}
+
+impl Stream {
+    fn range(&self) -> Range<usize> {
+        self.data.0..self.data.0 + self.data.1
+    }
+}
+
+/// Returns a sanitized copy of the minidump in `buf`.
+///
+/// This scrubs:
+///  - the contents of the memory lists, except for the first `stack_window` bytes of each
+///    thread's stack memory, which starts at the stack pointer,
+///  - the Linux command line and environment streams, which are removed from the directory,
+///  - the directories of module paths matching one of the `module_paths` patterns, in the
+///    module lists, PDB paths and the Linux maps stream.
+pub fn sanitize_minidump(
+    buf: &[u8],
+    config: &MinidumpSanitizerConfig,
+) -> Result<Vec<u8>, SanitizeError> {
+    if read_u32(buf, 0) != Some(MINIDUMP_SIGNATURE) {
+        return Err(SanitizeError::InvalidHeader);
+    }
+
+    let streams = read_directory(buf).ok_or(SanitizeError::InvalidHeader)?;
+    let mut out = buf.to_vec();
+
+    let mut keep = Vec::new();
+    let mut scrub = Vec::new();
+    for stream in &streams {
+        let data = &buf[stream.range()];
+        let malformed = || SanitizeError::MalformedStream(stream.stream_type);
+
+        match stream.stream_type {
+            THREAD_LIST_STREAM => {
+                for (start, size) in thread_stacks(data).ok_or_else(malformed)? {
+                    let window = size.min(config.stack_window as usize);
+                    keep.push(start..start + window);
+                    scrub.push(start..start + size);
+                }
+            }
+            MEMORY_LIST_STREAM => scrub.extend(memory_list(data).ok_or_else(malformed)?),
+            MEMORY_64_LIST_STREAM => scrub.extend(memory_64_list(data).ok_or_else(malformed)?),
+            LINUX_CMD_LINE | LINUX_ENVIRON => {
+                out[stream.range()].fill(0);
+                out[stream.entry..stream.entry + 4].copy_from_slice(&UNUSED_STREAM.to_le_bytes());
+            }
+            _ => (),
+        }
+    }
+
+    keep.sort_by_key(|range| range.start);
+    for range in scrub {
+        if range.end > out.len() {
+            return Err(SanitizeError::MalformedStream(MEMORY_LIST_STREAM));
+        }
+        zero_except(&mut out, range, &keep);
+    }
+
+    if !config.module_paths.is_empty() {
+        let scrub_path = |path: &str| {
+            config
+                .module_paths
+                .iter()
+                .any(|pattern| pattern.matches(path))
+                .then(|| file_name(path).to_owned())
+        };
+
+        for stream in &streams {
+            let data = &buf[stream.range()];
+            let malformed = || SanitizeError::MalformedStream(stream.stream_type);
+
+            match stream.stream_type {
+                MODULE_LIST_STREAM => {
+                    for (name_rva, cv_record) in modules(data).ok_or_else(malformed)? {
+                        scrub_string(&mut out, name_rva, scrub_path).ok_or_else(malformed)?;
+                        if let Some(cv_record) = cv_record {
+                            scrub_pdb_path(&mut out, cv_record, scrub_path);
+                        }
+                    }
+                }
+                UNLOADED_MODULE_LIST_STREAM => {
+                    for name_rva in unloaded_modules(data).ok_or_else(malformed)? {
+                        scrub_string(&mut out, name_rva, scrub_path).ok_or_else(malformed)?;
+                    }
+                }
+                LINUX_MAPS => {
+                    let maps = scrub_maps(data, scrub_path);
+                    let range = stream.range();
+                    out[range.clone()].fill(0);
+                    out[range.start..range.start + maps.len()].copy_from_slice(&maps);
+                    let size_offset = stream.entry + 4;
+                    out[size_offset..size_offset + 4]
+                        .copy_from_slice(&(maps.len() as u32).to_le_bytes());
+                }
+                _ => (),
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Reads the stream directory, validating that all streams are within `buf`.
+fn read_directory(buf: &[u8]) -> Option<Vec<Stream>> {
+    let count = read_u32(buf, 8)? as usize;
+    let directory = read_u32(buf, 12)? as usize;
+
+    (0..count)
+        .map(|index| {
+            let entry = directory.checked_add(index.checked_mul(12)?)?;
+            let stream_type = read_u32(buf, entry)?;
+            let size = read_u32(buf, entry + 4)? as usize;
+            let rva = read_u32(buf, entry + 8)? as usize;
+            buf.get(rva..rva.checked_add(size)?)?;
+
+            Some(Stream {
+                entry,
+                stream_type,
+                data: (rva, size),
+            })
+        })
+        .collect()
+}
+
+/// Returns the location of each thread's stack memory as `(rva, size)`.
+fn thread_stacks(data: &[u8]) -> Option<Vec<(usize, usize)>> {
+    let count = entry_count(data, 4, 48, read_u32(data, 0)? as usize);
+    (0..count)
+        .map(|index| {
+            let entry = 4 + index * 48;
+            let size = read_u32(data, entry + 32)? as usize;
+            let rva = read_u32(data, entry + 36)? as usize;
+            Some((rva, size))
+        })
+        .collect()
+}
+
+/// Returns the ranges of memory contents in a `MINIDUMP_MEMORY_LIST`.
+fn memory_list(data: &[u8]) -> Option<Vec<Range<usize>>> {
+    let count = entry_count(data, 4, 16, read_u32(data, 0)? as usize);
+    (0..count)
+        .map(|index| {
+            let entry = 4 + index * 16;
+            let size = read_u32(data, entry + 8)? as usize;
+            let rva = read_u32(data, entry + 12)? as usize;
+            Some(rva..rva.checked_add(size)?)
+        })
+        .collect()
+}
+
+/// Returns the ranges of memory contents in a `MINIDUMP_MEMORY64_LIST`.
+///
+/// The memory of all ranges is stored consecutively, starting at the base RVA.
+fn memory_64_list(data: &[u8]) -> Option<Vec<Range<usize>>> {
+    let count = entry_count(data, 16, 16, read_u64(data, 0)? as usize);
+    let mut rva = read_u64(data, 8)? as usize;
+    (0..count)
+        .map(|index| {
+            let size = read_u64(data, 16 + index * 16 + 8)? as usize;
+            let range = rva..rva.checked_add(size)?;
+            rva = range.end;
+            Some(range)
+        })
+        .collect()
+}
+
+/// Returns the name RVA and CodeView record location of each module in a module list.
+fn modules(data: &[u8]) -> Option<Vec<(usize, Option<(usize, usize)>)>> {
+    let count = entry_count(data, 4, 108, read_u32(data, 0)? as usize);
+    (0..count)
+        .map(|index| {
+            let entry = 4 + index * 108;
+            let name_rva = read_u32(data, entry + 20)? as usize;
+            let cv_size = read_u32(data, entry + 76)? as usize;
+            let cv_rva = read_u32(data, entry + 80)? as usize;
+            let cv_record = (cv_size > 0).then(|| (cv_rva, cv_size));
+            Some((name_rva, cv_record))
+        })
+        .collect()
+}
+
+/// Returns the name RVA of each module in an unloaded module list.
+fn unloaded_modules(data: &[u8]) -> Option<Vec<usize>> {
+    let header_size = read_u32(data, 0)? as usize;
+    let entry_size = read_u32(data, 4)? as usize;
+    // A `MINIDUMP_UNLOADED_MODULE` is 24 bytes, newer versions may append fields.
+    if entry_size < 24 {
+        return None;
+    }
+
+    let count = entry_count(data, header_size, entry_size, read_u32(data, 8)? as usize);
+    (0..count)
+        .map(|index| Some(read_u32(data, header_size + index * entry_size + 20)? as usize))
+        .collect()
+}
+
+/// Caps the `count` of a list stream at the number of entries that fit into `data`.
+///
+/// The count is read from the minidump and could otherwise make the sanitizer iterate over
+/// billions of entries.
+fn entry_count(data: &[u8], header_size: usize, entry_size: usize, count: usize) -> usize {
+    count.min(data.len().saturating_sub(header_size) / entry_size)
+}
+
+/// Zeroes `range` in `buf`, except for the parts covered by the sorted `keep` ranges.
+fn zero_except(buf: &mut [u8], range: Range<usize>, keep: &[Range<usize>]) {
+    let mut start = range.start;
+    for kept in keep {
+        if kept.end <= start || kept.start >= range.end {
+            continue;
+        }
+        if kept.start > start {
+            buf[start..kept.start].fill(0);
+        }
+        start = start.max(kept.end);
+    }
+
+    if start < range.end {
+        buf[start..range.end].fill(0);
+    }
+}
+
+/// Replaces the `MINIDUMP_STRING` at `rva` if `scrub_path` returns a replacement.
+///
+/// The replacement must not be longer than the original string.  Returns `None` if the
+/// string is out of bounds.
+fn scrub_string(
+    buf: &mut [u8],
+    rva: usize,
+    scrub_path: impl Fn(&str) -> Option<String>,
+) -> Option<()> {
+    let len = read_u32(buf, rva)? as usize;
+    let data = rva.checked_add(4)?..rva.checked_add(4)?.checked_add(len)?;
+    let units: Vec<u16> = buf
+        .get(data.clone())?
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect();
+
+    if let Some(replacement) = scrub_path(&String::from_utf16_lossy(&units)) {
+        let replacement: Vec<u16> = replacement.encode_utf16().take(units.len()).collect();
+        buf[data.clone()].fill(0);
+        for (index, unit) in replacement.iter().enumerate() {
+            let offset = data.start + index * 2;
+            buf[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        buf[rva..rva + 4].copy_from_slice(&(replacement.len() as u32 * 2).to_le_bytes());
+    }
+
+    Some(())
+}
+
+/// Replaces the PDB path of a PDB 7.0 CodeView record if `scrub_path` returns a replacement.
+fn scrub_pdb_path(
+    buf: &mut [u8],
+    (rva, size): (usize, usize),
+    scrub_path: impl Fn(&str) -> Option<String>,
+) {
+    // The path follows the signature, GUID and age and is NUL-terminated.
+    let path_start = rva + 24;
+    let record_end = rva + size;
+    if size <= 24 || read_u32(buf, rva) != Some(CV_SIGNATURE_PDB70) || record_end > buf.len() {
+        return;
+    }
+
+    let path = &buf[path_start..record_end];
+    let path = path.split(|byte| *byte == 0).next().unwrap_or_default();
+    if let Some(replacement) = scrub_path(&String::from_utf8_lossy(path)) {
+        let len = replacement.len().min(path.len());
+        buf[path_start..record_end].fill(0);
+        buf[path_start..path_start + len].copy_from_slice(&replacement.as_bytes()[..len]);
+    }
+}
+
+/// Replaces the paths in a Linux maps stream for which `scrub_path` returns a replacement.
+fn scrub_maps(data: &[u8], scrub_path: impl Fn(&str) -> Option<String>) -> Vec<u8> {
+    let maps = String::from_utf8_lossy(data);
+    let mut out = String::with_capacity(maps.len());
+    for line in maps.split_inclusive('\n') {
+        // The path is the last field and is the only one starting with a slash.
+        match line.find('/') {
+            Some(index) => {
+                let (prefix, path) = line.split_at(index);
+                let newline = if path.ends_with('\n') { "\n" } else { "" };
+                let path = path.trim_end_matches('\n');
+                out.push_str(prefix);
+                out.push_str(&scrub_path(path).unwrap_or_else(|| path.to_owned()));
+                out.push_str(newline);
+            }
+            None => out.push_str(line),
+        }
+    }
+
+    // Lossy conversion of invalid UTF-8 could make the stream longer.
+    match out.len() <= data.len() {
+        true => out.into_bytes(),
+        false => data.to_vec(),
+    }
+}
+
+/// Returns the file name of a Unix or Windows path.
+fn file_name(path: &str) -> &str {
+    path.rsplit(&['/', '\\'][..]).next().unwrap_or(path)
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> Option<u64> {
+    let bytes = buf.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use minidump::{Minidump, MinidumpModuleList, MinidumpThreadList, Module};
+
+    use super::*;
+    use crate::test;
+    use crate::types::Glob;
+
+    fn config(module_paths: &[&str]) -> MinidumpSanitizerConfig {
+        MinidumpSanitizerConfig {
+            stack_window: 64,
+            module_paths: module_paths
+                .iter()
+                .map(|pattern| Glob(pattern.parse().unwrap()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sanitize_linux() {
+        let buf = test::read_fixture("linux.dmp");
+        let sanitized = sanitize_minidump(&buf, &config(&["/lib/*"])).unwrap();
+        assert_eq!(sanitized.len(), buf.len());
+
+        let original = Minidump::read(buf.as_slice()).unwrap();
+        let dump = Minidump::read(sanitized.as_slice()).unwrap();
+
+        let original_threads = original.get_stream::<MinidumpThreadList>().unwrap();
+        let threads = dump.get_stream::<MinidumpThreadList>().unwrap();
+        for (original, thread) in original_threads.threads.iter().zip(&threads.threads) {
+            let original_stack = &original.stack.as_ref().unwrap().bytes;
+            let stack = &thread.stack.as_ref().unwrap().bytes;
+            assert_eq!(stack[..64], original_stack[..64]);
+            assert!(stack[64..].iter().all(|byte| *byte == 0));
+        }
+
+        let modules = dump.get_stream::<MinidumpModuleList>().unwrap();
+        for module in modules.iter() {
+            assert!(!module.code_file().starts_with("/lib/"));
+        }
+
+        assert!(dump.get_raw_stream(LINUX_ENVIRON).is_err());
+        assert!(dump.get_raw_stream(LINUX_CMD_LINE).is_err());
+        if let Ok(maps) = dump.get_raw_stream(LINUX_MAPS) {
+            assert!(!String::from_utf8_lossy(maps).contains("/lib/"));
+        }
+    }
+
+    #[test]
+    fn test_sanitize_invalid() {
+        let config = MinidumpSanitizerConfig::default();
+        assert!(matches!(
+            sanitize_minidump(b"MDMP", &config),
+            Err(SanitizeError::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn test_list_count_bounds() {
+        // An unloaded module list with one entry, claiming to contain `u32::MAX` entries.
+        let mut data = Vec::new();
+        for value in [12u32, 24, u32::MAX, 0, 0, 0, 0, 0, 42, 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        assert_eq!(unloaded_modules(&data), Some(vec![42]));
+
+        data[4..8].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(unloaded_modules(&data), None);
+
+        let mut data = u32::MAX.to_le_bytes().to_vec();
+        data.extend_from_slice(&[0; 16]);
+        assert_eq!(memory_list(&data).map(|list| list.len()), Some(1));
+        assert_eq!(thread_stacks(&data), Some(vec![]));
+    }
+
+    #[test]
+    fn test_scrub_maps() {
+        let maps = b"7f00-7f10 r-xp 00000000 08:01 42 /home/user/libfoo.so\n7f10-7f20 rw-p 00000000 00:00 0 \n";
+        let scrub_path = |path: &str| {
+            path.starts_with("/home/")
+                .then(|| file_name(path).to_owned())
+        };
+        assert_eq!(
+            scrub_maps(maps, scrub_path),
+            b"7f00-7f10 r-xp 00000000 08:01 42 libfoo.so\n7f10-7f20 rw-p 00000000 00:00 0 \n"
+        );
+    }
+
+    #[test]
+    fn test_zero_except() {
+        let mut buf = [1u8; 16];
+        zero_except(&mut buf, 2..14, &[0..4, 8..10]);
+        assert_eq!(buf, [1, 1, 1, 1, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("/usr/lib/libc.so.6"), "libc.so.6");
+        assert_eq!(file_name("C:\\Windows\\ntdll.dll"), "ntdll.dll");
+        assert_eq!(file_name("app"), "app");
+    }
+}
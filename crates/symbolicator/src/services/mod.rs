@@ -43,8 +43,11 @@ pub mod cacher;
 pub mod cficaches;
 pub mod download;
 mod minidump;
+mod minidump_sanitizer;
 pub mod objects;
+pub mod ppdb;
 pub mod shared_cache;
+pub mod sourcemaps;
 pub mod symbolication;
 pub mod symcaches;
 
@@ -52,8 +55,10 @@ use self::bitcode::BitcodeService;
 use self::cficaches::CfiCacheActor;
 use self::download::DownloadService;
 use self::objects::ObjectsActor;
+use self::ppdb::PortablePdbService;
 use self::shared_cache::SharedCacheService;
-use self::symbolication::SymbolicationActor;
+use self::sourcemaps::SourceMapService;
+use self::symbolication::{StackwalkPool, SymbolicationActor};
 use self::symcaches::SymCacheActor;
 
 /// The shared state for the service.
@@ -87,6 +92,17 @@ impl Service {
             shared_cache.clone(),
             downloader.clone(),
         );
+        let ppdbs = PortablePdbService::new(
+            caches.auxdifs.clone(),
+            shared_cache.clone(),
+            downloader.clone(),
+        );
+        let sourcemaps = SourceMapService::new(
+            caches.auxdifs.clone(),
+            shared_cache.clone(),
+            downloader.clone(),
+            cpu_pool.clone(),
+        );
         let bitcode = BitcodeService::new(caches.auxdifs, shared_cache.clone(), downloader);
         let symcaches = SymCacheActor::new(
             caches.symcaches,
@@ -102,14 +118,23 @@ impl Service {
             cpu_pool.clone(),
         );
 
+        let stackwalk_pool = config
+            .stackwalk
+            .isolate
+            .then(|| StackwalkPool::new(config.processing_pool_size, config.stackwalk));
         let symbolication = SymbolicationActor::new(
             objects.clone(),
             symcaches,
             cficaches,
+            ppdbs,
+            sourcemaps,
             caches.diagnostics,
             io_pool,
             cpu_pool,
             config.max_concurrent_requests,
+            config.stackwalk,
+            stackwalk_pool,
+            config.minidump_sanitizer.clone(),
         );
         let symbolication_taskmon = symbolication.symbolication_task_monitor();
         tokio::spawn(async move {
@@ -16,6 +16,7 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use futures::future::BoxFuture;
+use parking_lot::Mutex;
 use sentry::{Hub, SentryFutureExt};
 use symbolic::common::ByteView;
 use symbolic::debuginfo::{Archive, Object};
@@ -23,7 +24,8 @@ use tempfile::tempfile_in;
 use tempfile::NamedTempFile;
 
 use crate::cache::CacheStatus;
-use crate::services::cacher::{CacheItemRequest, CacheKey, CachePath};
+use crate::config::ObjectCompressionConfig;
+use crate::services::cacher::{CacheItemRequest, CacheKey, CachePath, Cacher};
 use crate::services::download::DownloadService;
 use crate::services::download::RemoteDif;
 use crate::services::download::{DownloadError, DownloadStatus};
@@ -35,6 +37,12 @@ use crate::utils::sentry::ConfigureScope;
 use super::meta_cache::FetchFileMetaRequest;
 use super::ObjectError;
 
+/// Magic bytes of zstd compressed data, used for objects stored compressed in the cache.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// The number of decompressed copies of compressed objects that are kept around for reuse.
+const DECOMPRESSED_OBJECTS: usize = 16;
+
 /// This requests the file content of a single file at a specific path/url.
 /// The attributes for this are the same as for `FetchFileMetaRequest`, hence the newtype
 #[derive(Clone, Debug)]
@@ -58,6 +66,11 @@ pub struct ObjectHandle {
     /// malformed marker.
     pub(super) data: ByteView<'static>,
     pub(super) status: CacheStatus,
+
+    /// The cache file of an object which is stored compressed, see [`ObjectDecompressor`].
+    ///
+    /// While this is set, [`ObjectHandle::data`] holds the compressed object.
+    pub(super) compressed_path: Option<PathBuf>,
 }
 
 impl ObjectHandle {
@@ -154,6 +167,7 @@ async fn fetch_file(
     file_id: RemoteDif,
     downloader: Arc<DownloadService>,
     tempfile: std::io::Result<NamedTempFile>,
+    compression: ObjectCompressionConfig,
 ) -> Result<CacheStatus, ObjectError> {
     tracing::trace!("Fetching file data for {}", cache_key);
     sentry::configure_scope(|scope| {
@@ -245,9 +259,127 @@ async fn fetch_file(
         io::copy(&mut view.as_ref(), &mut persist_file)?;
     }
 
+    let size = persist_file.metadata()?.len();
+    drop(persist_file);
+    if compression.min_size.map_or(false, |min_size| size >= min_size) {
+        // Compressing whole objects is CPU intensive, so it must not block the runtime.
+        let tmp_dir = download_dir.to_owned();
+        let level = compression.level;
+        tokio::task::spawn_blocking(move || compress_object(&path, &tmp_dir, level))
+            .await
+            .unwrap_or_else(|err| Err(io::Error::new(io::ErrorKind::Other, err)))?;
+    }
+
     Ok(CacheStatus::Positive)
 }
 
+/// Compresses the object at `path` in place.
+///
+/// This writes to the existing file rather than replacing it, as the [`Cacher`] keeps open
+/// handles to it.
+///
+/// [`Cacher`]: crate::services::cacher::Cacher
+fn compress_object(path: &Path, tmp_dir: &Path, level: i32) -> io::Result<()> {
+    let mut compressed = tempfile_in(tmp_dir)?;
+    zstd::stream::copy_encode(fs::File::open(path)?, &mut compressed, level)?;
+
+    compressed.seek(SeekFrom::Start(0))?;
+    io::copy(&mut compressed, &mut fs::File::create(path)?)?;
+    metric!(counter("objects.compressed") += 1);
+    Ok(())
+}
+
+/// Decompresses an object which was stored compressed in the cache into a temporary file.
+fn decompress_object(
+    data: &[u8],
+    tempfile: io::Result<NamedTempFile>,
+) -> io::Result<ByteView<'static>> {
+    // The file is deleted once the temporary file is dropped, the mapping stays valid.
+    let mut file = tempfile?.reopen()?;
+    zstd::stream::copy_decode(data, &mut file)?;
+    ByteView::map_file(file)
+}
+
+/// Decompresses objects which are stored compressed in the cache.
+///
+/// Decompressed copies of recently used objects are kept in unlinked temporary files, so
+/// that repeated loads of the same object do not decompress it again.
+#[derive(Clone)]
+pub(super) struct ObjectDecompressor {
+    recent: Arc<Mutex<lru::LruCache<PathBuf, (usize, ByteView<'static>)>>>,
+}
+
+impl ObjectDecompressor {
+    pub(super) fn new() -> Self {
+        Self {
+            recent: Arc::new(Mutex::new(lru::LruCache::new(DECOMPRESSED_OBJECTS))),
+        }
+    }
+
+    /// Returns a handle to the decompressed object if `handle` holds a compressed object.
+    ///
+    /// Decompression happens on a blocking thread.  If the object can not be decompressed,
+    /// its cache file is removed, so that it is fetched again on the next request, and a
+    /// [`CacheStatus::Malformed`] handle is returned.
+    pub(super) async fn decompress(
+        &self,
+        handle: Arc<ObjectHandle>,
+        data_cache: &Cacher<FetchFileDataRequest>,
+    ) -> Arc<ObjectHandle> {
+        let path = match handle.compressed_path.clone() {
+            Some(path) => path,
+            None => return handle,
+        };
+        let compressed_len = handle.data.len();
+
+        let recent = self.recent.lock().get(&path).cloned();
+        let result = match recent {
+            Some((len, data)) if len == compressed_len => Ok(data),
+            _ => {
+                let compressed = handle.data.clone();
+                let tempfile = data_cache.tempfile();
+                let result =
+                    tokio::task::spawn_blocking(move || decompress_object(&compressed, tempfile))
+                        .await
+                        .unwrap_or_else(|err| Err(io::Error::new(io::ErrorKind::Other, err)));
+                if let Ok(ref data) = result {
+                    let entry = (compressed_len, data.clone());
+                    self.recent.lock().put(path.clone(), entry);
+                }
+                result
+            }
+        };
+
+        let (status, data) = match result {
+            Ok(data) => (CacheStatus::Positive, data),
+            Err(err) => {
+                tracing::warn!("Evicting undecodable object {}: {}", path.display(), err);
+                metric!(counter("objects.decompression_error") += 1);
+                fs::remove_file(&path).ok();
+                (
+                    CacheStatus::Malformed(err.to_string()),
+                    ByteView::from_slice(b""),
+                )
+            }
+        };
+
+        Arc::new(ObjectHandle {
+            data,
+            status,
+            compressed_path: None,
+            ..(*handle).clone()
+        })
+    }
+}
+
+impl fmt::Debug for ObjectDecompressor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ObjectDecompressor")
+            .field("recent", &self.recent.lock().len())
+            .finish()
+    }
+}
+
 impl CacheItemRequest for FetchFileDataRequest {
     type Item = ObjectHandle;
     type Error = ObjectError;
@@ -264,6 +396,7 @@ impl CacheItemRequest for FetchFileDataRequest {
             self.0.file_source.clone(),
             self.0.download_svc.clone(),
             self.0.data_cache.tempfile(),
+            self.0.data_cache.compression(),
         );
 
         let future = async move {
@@ -291,8 +424,20 @@ impl CacheItemRequest for FetchFileDataRequest {
         scope: Scope,
         status: CacheStatus,
         data: ByteView<'static>,
-        _: CachePath,
+        path: CachePath,
     ) -> Self::Item {
+        // Decompression is deferred to the `ObjectDecompressor`, since this runs on the async
+        // runtime and decompressing large objects takes a while.
+        let compressed_path = match (&status, path) {
+            (CacheStatus::Positive, CachePath::Cached(path)) if data.starts_with(&ZSTD_MAGIC) => {
+                Some(path)
+            }
+            (CacheStatus::Positive, CachePath::Temp(path)) if data.starts_with(&ZSTD_MAGIC) => {
+                Some(path.to_path_buf())
+            }
+            _ => None,
+        };
+
         let object_handle = ObjectHandle {
             object_id: self.0.object_id.clone(),
             scope,
@@ -301,6 +446,7 @@ impl CacheItemRequest for FetchFileDataRequest {
 
             status,
             data,
+            compressed_path,
         };
 
         object_handle.configure_scope();
@@ -316,6 +462,7 @@ mod tests {
 
     use crate::cache::{Cache, CacheName, CacheStatus};
     use crate::config::{CacheConfig, CacheConfigs, Config};
+    use crate::services::cacher::{CacheKey, Cacher};
     use crate::services::download::{DownloadError, DownloadService};
     use crate::services::objects::data_cache::Scope;
     use crate::services::objects::{FindObject, ObjectPurpose, ObjectsActor};
@@ -323,8 +470,10 @@ mod tests {
     use crate::sources::FileType;
     use crate::test::{self, tempdir};
 
-    use symbolic::common::DebugId;
-    use tempfile::TempDir;
+    use symbolic::common::{ByteView, DebugId};
+    use tempfile::{NamedTempFile, TempDir};
+
+    use super::{compress_object, decompress_object, ObjectDecompressor, ObjectHandle, ZSTD_MAGIC};
 
     async fn objects_actor(tempdir: &TempDir) -> ObjectsActor {
         let meta_cache = Cache::from_config(
@@ -521,4 +670,54 @@ mod tests {
         );
         assert_eq!(server.accesses(), 0);
     }
+
+    #[test]
+    fn test_compressed_object_roundtrip() {
+        let dir = tempdir();
+        let path = dir.path().join("object");
+        std::fs::write(&path, b"some object data").unwrap();
+
+        compress_object(&path, dir.path(), 3).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.starts_with(&ZSTD_MAGIC));
+
+        let decompressed = decompress_object(&data, NamedTempFile::new_in(dir.path())).unwrap();
+        assert_eq!(decompressed.as_slice(), b"some object data");
+    }
+
+    #[tokio::test]
+    async fn test_undecodable_object_evicted() {
+        let dir = tempdir();
+        let path = dir.path().join("object");
+        std::fs::write(&path, [&ZSTD_MAGIC[..], b"garbage"].concat()).unwrap();
+
+        let cache = Cache::from_config(
+            CacheName::Objects,
+            Some(dir.path().join("data")),
+            None,
+            CacheConfig::from(CacheConfigs::default().downloaded),
+            Default::default(),
+        )
+        .unwrap();
+        let shared_cache_svc = Arc::new(SharedCacheService::new(None).await);
+        let data_cache = Cacher::new(cache, shared_cache_svc);
+
+        let handle = Arc::new(ObjectHandle {
+            object_id: Default::default(),
+            scope: Scope::Global,
+            cache_key: CacheKey {
+                cache_key: "object".into(),
+                scope: Scope::Global,
+            },
+            data: ByteView::open(&path).unwrap(),
+            status: CacheStatus::Positive,
+            compressed_path: Some(path.clone()),
+        });
+
+        let handle = ObjectDecompressor::new()
+            .decompress(handle, &data_cache)
+            .await;
+        assert!(matches!(handle.status, CacheStatus::Malformed(_)));
+        assert!(!path.exists());
+    }
 }
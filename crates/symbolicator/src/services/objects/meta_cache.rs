@@ -13,16 +13,37 @@ use std::sync::Arc;
 
 use anyhow::Context;
 use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
 use symbolic::common::ByteView;
 use symbolic::debuginfo::Object;
 
 use crate::cache::CacheStatus;
-use crate::services::cacher::{CacheItemRequest, CacheKey, CachePath, Cacher};
+use crate::services::cacher::{CacheItemRequest, CacheKey, CachePath, CacheVersions, Cacher};
 use crate::services::download::{RemoteDif, RemoteDifUri};
 use crate::sources::SourceId;
 use crate::types::{ObjectFeatures, ObjectId, Scope};
 
-use super::{FetchFileDataRequest, ObjectError};
+use super::{FetchFileDataRequest, ObjectDecompressor, ObjectError};
+
+/// The object meta cache version.
+///
+/// Version `1` adds the file format of the object, see [`ObjectMeta`].
+const META_CACHE_VERSIONS: CacheVersions = CacheVersions {
+    current: 1,
+    fallbacks: &[0],
+};
+
+/// The metadata of an object file, as stored in the metadata cache.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ObjectMeta {
+    #[serde(flatten)]
+    features: ObjectFeatures,
+    /// The name of the file format, such as `breakpad` or `elf`.
+    ///
+    /// This is missing in metadata written by older versions.
+    #[serde(default)]
+    file_format: Option<String>,
+}
 
 /// This requests metadata of a single file at a specific path/url.
 #[derive(Clone, Debug)]
@@ -37,6 +58,7 @@ pub(super) struct FetchFileMetaRequest {
     // `<FetchFileMetaRequest as CacheItemRequest>::compute`, e.g. make the Cacher hold arbitrary
     // state for computing.
     pub(super) data_cache: Arc<Cacher<FetchFileDataRequest>>,
+    pub(super) decompressor: ObjectDecompressor,
     pub(super) download_svc: Arc<crate::services::download::DownloadService>,
 }
 
@@ -51,6 +73,7 @@ pub struct ObjectMetaHandle {
     pub(super) object_id: ObjectId,
     pub(super) file_source: RemoteDif,
     pub(super) features: ObjectFeatures,
+    pub(super) file_format: Option<String>,
     pub(super) status: CacheStatus,
 }
 
@@ -105,19 +128,24 @@ impl FetchFileMetaRequest {
         tracing::trace!("Fetching file meta for {}", cache_key);
 
         let data_cache = self.data_cache.clone();
+        let decompressor = self.decompressor.clone();
         let object_handle = data_cache
             .compute_memoized(FetchFileDataRequest(self))
             .await
             .map_err(ObjectError::Caching)?;
+        let object_handle = decompressor.decompress(object_handle, &data_cache).await;
         if object_handle.status == CacheStatus::Positive {
             if let Ok(object) = Object::parse(&object_handle.data) {
                 let mut new_cache = fs::File::create(path)?;
 
-                let meta = ObjectFeatures {
-                    has_debug_info: object.has_debug_info(),
-                    has_unwind_info: object.has_unwind_info(),
-                    has_symbols: object.has_symbols(),
-                    has_sources: object.has_sources(),
+                let meta = ObjectMeta {
+                    features: ObjectFeatures {
+                        has_debug_info: object.has_debug_info(),
+                        has_unwind_info: object.has_unwind_info(),
+                        has_symbols: object.has_symbols(),
+                        has_sources: object.has_sources(),
+                    },
+                    file_format: Some(object.file_format().name().to_owned()),
                 };
 
                 tracing::trace!("Persisting object meta for {}: {:?}", cache_key, meta);
@@ -140,6 +168,8 @@ impl CacheItemRequest for FetchFileMetaRequest {
     type Item = ObjectMetaHandle;
     type Error = ObjectError;
 
+    const VERSIONS: CacheVersions = META_CACHE_VERSIONS;
+
     fn get_cache_key(&self) -> CacheKey {
         self.file_source.cache_key(self.scope.clone())
     }
@@ -150,7 +180,7 @@ impl CacheItemRequest for FetchFileMetaRequest {
     }
 
     fn should_load(&self, data: &[u8]) -> bool {
-        serde_json::from_slice::<ObjectFeatures>(data).is_ok()
+        serde_json::from_slice::<ObjectMeta>(data).is_ok()
     }
 
     /// Returns the [`ObjectMetaHandle`] at the given cache key.
@@ -166,7 +196,7 @@ impl CacheItemRequest for FetchFileMetaRequest {
     ) -> Self::Item {
         // When CacheStatus::Negative we get called with an empty ByteView, for Malformed we
         // get the malformed marker.
-        let features = match status {
+        let meta: ObjectMeta = match status {
             CacheStatus::Positive => serde_json::from_slice(&data)
                 .context("Failed to load positive ObjectFileMeta cache")
                 .unwrap_or_else(|err| {
@@ -183,7 +213,8 @@ impl CacheItemRequest for FetchFileMetaRequest {
             scope,
             object_id: self.object_id.clone(),
             file_source: self.file_source.clone(),
-            features,
+            features: meta.features,
+            file_format: meta.file_format,
             status,
         }
     }
@@ -15,7 +15,7 @@ use crate::services::download::{DownloadError, DownloadService, RemoteDif, Remot
 use crate::sources::{FileType, SourceConfig, SourceId};
 use crate::types::{AllObjectCandidates, ObjectCandidate, ObjectDownloadInfo, ObjectId, Scope};
 
-use data_cache::FetchFileDataRequest;
+use data_cache::{FetchFileDataRequest, ObjectDecompressor};
 use meta_cache::FetchFileMetaRequest;
 
 pub use data_cache::ObjectHandle;
@@ -162,12 +162,17 @@ pub struct FoundObject {
     /// This is a list of some meta information on all objects which have been considered
     /// for this object.  It could be populated even if no matching object is found.
     pub candidates: AllObjectCandidates,
+    /// Another object with debug information found alongside `meta`, if any.
+    ///
+    /// This is only provided for [`ObjectPurpose::Debug`], see [`select_companion`].
+    pub companion: Option<Arc<ObjectMetaHandle>>,
 }
 
 #[derive(Clone, Debug)]
 pub struct ObjectsActor {
     meta_cache: Arc<Cacher<FetchFileMetaRequest>>,
     data_cache: Arc<Cacher<FetchFileDataRequest>>,
+    decompressor: ObjectDecompressor,
     download_svc: Arc<DownloadService>,
 }
 
@@ -181,6 +186,7 @@ impl ObjectsActor {
         ObjectsActor {
             meta_cache: Arc::new(Cacher::new(meta_cache, Arc::clone(&shared_cache_svc))),
             data_cache: Arc::new(Cacher::new(data_cache, shared_cache_svc)),
+            decompressor: ObjectDecompressor::new(),
             download_svc,
         }
     }
@@ -198,13 +204,16 @@ impl ObjectsActor {
             file_source: file_handle.file_source.clone(),
             object_id: file_handle.object_id.clone(),
             data_cache: self.data_cache.clone(),
+            decompressor: self.decompressor.clone(),
             download_svc: self.download_svc.clone(),
         });
 
-        self.data_cache
+        let handle = self
+            .data_cache
             .compute_memoized(request)
             .await
-            .map_err(ObjectError::Caching)
+            .map_err(ObjectError::Caching)?;
+        Ok(self.decompressor.decompress(handle, &self.data_cache).await)
     }
 
     /// Fetches matching objects and returns the metadata of the most suitable object.
@@ -228,10 +237,21 @@ impl ObjectsActor {
         let file_metas = self.fetch_file_metas(file_ids, &identifier, scope).await;
 
         let candidates = create_candidates(&sources, &file_metas);
+        let found_metas: Vec<_> = file_metas
+            .iter()
+            .filter_map(|meta_lookup| meta_lookup.as_ref().ok().cloned())
+            .collect();
         let meta = select_meta(file_metas, purpose);
+        let companion = match meta {
+            Some(Ok(ref meta)) => select_companion(&found_metas, meta, purpose),
+            _ => None,
+        };
 
-        meta.transpose()
-            .map(|meta| FoundObject { meta, candidates })
+        meta.transpose().map(|meta| FoundObject {
+            meta,
+            candidates,
+            companion,
+        })
     }
 
     /// Collect the list of files to download from all the sources.
@@ -290,6 +310,7 @@ impl ObjectsActor {
             let object_id = identifier.clone();
             let scope = scope.clone();
             let data_cache = self.data_cache.clone();
+            let decompressor = self.decompressor.clone();
             let download_svc = self.download_svc.clone();
             let meta_cache = self.meta_cache.clone();
 
@@ -304,6 +325,7 @@ impl ObjectsActor {
                     file_source: file_source.clone(),
                     object_id,
                     data_cache,
+                    decompressor,
                     download_svc,
                 };
                 meta_cache
@@ -357,6 +379,34 @@ fn select_meta(
     selected_meta
 }
 
+/// Select a debug companion for the `selected` object chosen by [`select_meta`].
+///
+/// Third-party modules often only come with Breakpad symbol files, which lack inline
+/// information, while full debug information is available from a separate file.  As
+/// [`select_meta`] picks the first object with debug information, this returns the first
+/// other object with debug information in a different file format, such as an ELF or PDB
+/// file for a Breakpad file, so that symcaches can be built from both.
+fn select_companion(
+    metas: &[Arc<ObjectMetaHandle>],
+    selected: &Arc<ObjectMetaHandle>,
+    purpose: ObjectPurpose,
+) -> Option<Arc<ObjectMetaHandle>> {
+    if !matches!(purpose, ObjectPurpose::Debug) {
+        return None;
+    }
+
+    let selected_format = selected.file_format.as_deref()?;
+    metas
+        .iter()
+        .find(|meta_handle| {
+            let file_format = meta_handle.file_format.as_deref();
+            meta_handle.status == CacheStatus::Positive
+                && meta_handle.features.has_debug_info
+                && file_format.map_or(false, |format| format != selected_format)
+        })
+        .cloned()
+}
+
 /// Returns a sortable quality measure of this object for the given purpose.
 ///
 /// Lower quality number is better.
@@ -0,0 +1,561 @@
+//! A minimal reader for Portable PDB files.
+//!
+//! Portable PDBs are the debug files of .NET assemblies and use the ECMA-335 metadata format,
+//! extended with debugging tables as described in
+//! <https://github.com/dotnet/runtime/blob/main/docs/design/specs/PortablePdb-Metadata.md>.
+//! Only the tables needed to map IL offsets within a method to source lines are read.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+use symbolic::common::{ByteView, DebugId};
+use thiserror::Error;
+
+/// The signature at the start of the metadata root, `BSJB`.
+const METADATA_SIGNATURE: u32 = 0x424a_5342;
+
+/// The index of the `Document` table.
+const DOCUMENT_TABLE: usize = 0x30;
+
+/// The index of the `MethodDebugInformation` table.
+const METHOD_DEBUG_INFORMATION_TABLE: usize = 0x31;
+
+/// The table of method tokens, which share their row number with `MethodDebugInformation`.
+const METHOD_DEF_TABLE: u32 = 0x06;
+
+/// Errors happening while parsing a Portable PDB.
+#[derive(Debug, Error)]
+pub enum PortablePdbError {
+    #[error("invalid metadata signature")]
+    InvalidSignature,
+
+    #[error("missing {0} stream")]
+    MissingStream(&'static str),
+
+    #[error("unexpected end of data")]
+    UnexpectedEof,
+
+    #[error("unsupported metadata tables")]
+    UnsupportedTables,
+
+    #[error("invalid sequence points")]
+    InvalidSequencePoints,
+}
+
+/// Applies a signed sequence point delta to a line or column.
+fn apply_delta(value: u32, delta: i32) -> Result<u32, PortablePdbError> {
+    u32::try_from(i64::from(value) + i64::from(delta))
+        .map_err(|_| PortablePdbError::InvalidSequencePoints)
+}
+
+/// A cursor over little-endian metadata.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PortablePdbError> {
+        if len > self.data.len() {
+            return Err(PortablePdbError::UnexpectedEof);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, PortablePdbError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, PortablePdbError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, PortablePdbError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, PortablePdbError> {
+        let low = u64::from(self.u32()?);
+        let high = u64::from(self.u32()?);
+        Ok(low | (high << 32))
+    }
+
+    /// Reads a heap or table index, which is either 2 or 4 bytes wide.
+    fn index(&mut self, large: bool) -> Result<u32, PortablePdbError> {
+        match large {
+            true => self.u32(),
+            false => self.u16().map(u32::from),
+        }
+    }
+
+    /// Reads a compressed unsigned integer, returning the value and its encoded size.
+    fn compressed_with_size(&mut self) -> Result<(u32, usize), PortablePdbError> {
+        let first = u32::from(self.u8()?);
+        if first & 0x80 == 0 {
+            Ok((first, 1))
+        } else if first & 0xc0 == 0x80 {
+            Ok((((first & 0x3f) << 8) | u32::from(self.u8()?), 2))
+        } else if first & 0xe0 == 0xc0 {
+            let rest = self.bytes(3)?;
+            let value = ((first & 0x1f) << 24)
+                | (u32::from(rest[0]) << 16)
+                | (u32::from(rest[1]) << 8)
+                | u32::from(rest[2]);
+            Ok((value, 4))
+        } else {
+            Err(PortablePdbError::UnexpectedEof)
+        }
+    }
+
+    fn compressed_u32(&mut self) -> Result<u32, PortablePdbError> {
+        self.compressed_with_size().map(|(value, _)| value)
+    }
+
+    /// Reads a compressed signed integer, which stores the sign in the lowest bit.
+    fn compressed_i32(&mut self) -> Result<i32, PortablePdbError> {
+        let (value, size) = self.compressed_with_size()?;
+        let magnitude = (value >> 1) as i32;
+        if value & 1 == 0 {
+            return Ok(magnitude);
+        }
+        Ok(match size {
+            1 => magnitude - 0x40,
+            2 => magnitude - 0x2000,
+            _ => magnitude - 0x1000_0000,
+        })
+    }
+}
+
+/// Location and layout of a metadata table.
+#[derive(Clone, Copy, Debug, Default)]
+struct Table {
+    offset: usize,
+    rows: u32,
+    row_size: usize,
+}
+
+impl Table {
+    /// Returns the data of the 1-based `row`.
+    fn row<'a>(&self, data: &'a [u8], row: u32) -> Option<&'a [u8]> {
+        if row == 0 || row > self.rows {
+            return None;
+        }
+        let start = self.offset + (row as usize - 1) * self.row_size;
+        data.get(start..start + self.row_size)
+    }
+}
+
+/// The source location of an IL offset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineInfo {
+    /// The path of the source file as recorded by the compiler.
+    pub path: String,
+    /// The line number, starting at `1`.
+    pub line: u32,
+}
+
+/// A parsed Portable PDB file.
+pub struct PortablePdb {
+    data: ByteView<'static>,
+    debug_id: DebugId,
+    blob_heap: Range<usize>,
+    large_blob_index: bool,
+    large_document_index: bool,
+    documents: Table,
+    methods: Table,
+}
+
+impl fmt::Debug for PortablePdb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PortablePdb")
+            .field("debug_id", &self.debug_id)
+            .finish()
+    }
+}
+
+impl PortablePdb {
+    /// Parses the metadata of a Portable PDB file.
+    pub fn parse(data: ByteView<'static>) -> Result<Self, PortablePdbError> {
+        let streams = parse_streams(&data)?;
+        let stream = |name: &'static str| {
+            streams
+                .get(name)
+                .cloned()
+                .ok_or(PortablePdbError::MissingStream(name))
+        };
+
+        let pdb_stream = stream("#Pdb")?;
+        let mut reader = Reader::new(&data[pdb_stream]);
+        let pdb_id = reader.bytes(20)?;
+        let timestamp = u32::from_le_bytes([pdb_id[16], pdb_id[17], pdb_id[18], pdb_id[19]]);
+        let debug_id = DebugId::from_guid_age(&pdb_id[..16], timestamp)
+            .map_err(|_| PortablePdbError::UnexpectedEof)?;
+
+        let tables_stream = stream("#~")?;
+        let tables_end = tables_stream.end;
+        let mut reader = Reader::new(&data[tables_stream.clone()]);
+        reader.bytes(6)?; // reserved, major and minor version
+        let heap_sizes = reader.u8()?;
+        reader.u8()?; // reserved
+        let valid = reader.u64()?;
+        reader.u64()?; // sorted
+
+        // Portable PDBs only contain debug tables, whose row sizes do not depend on the type
+        // system tables of the assembly.
+        if valid & ((1 << DOCUMENT_TABLE) - 1) != 0 {
+            return Err(PortablePdbError::UnsupportedTables);
+        }
+
+        let mut rows = [0u32; 64];
+        for (table, count) in rows.iter_mut().enumerate() {
+            if valid & (1 << table) != 0 {
+                *count = reader.u32()?;
+            }
+        }
+
+        let large_guid_index = heap_sizes & 0x02 != 0;
+        let large_blob_index = heap_sizes & 0x04 != 0;
+        let large_document_index = rows[DOCUMENT_TABLE] > 0xffff;
+        let blob_size = if large_blob_index { 4 } else { 2 };
+        let guid_size = if large_guid_index { 4 } else { 2 };
+
+        let documents = Table {
+            offset: tables_end - reader.data.len(),
+            rows: rows[DOCUMENT_TABLE],
+            row_size: 2 * blob_size + 2 * guid_size,
+        };
+        let methods = Table {
+            offset: documents.offset + documents.rows as usize * documents.row_size,
+            rows: rows[METHOD_DEBUG_INFORMATION_TABLE],
+            row_size: (if large_document_index { 4 } else { 2 }) + blob_size,
+        };
+        if methods.offset + methods.rows as usize * methods.row_size > tables_end {
+            return Err(PortablePdbError::UnexpectedEof);
+        }
+
+        Ok(Self {
+            blob_heap: stream("#Blob")?,
+            data,
+            debug_id,
+            large_blob_index,
+            large_document_index,
+            documents,
+            methods,
+        })
+    }
+
+    /// The debug ID of this file, made up of its GUID and timestamp.
+    pub fn debug_id(&self) -> DebugId {
+        self.debug_id
+    }
+
+    /// Looks up the source location of `il_offset` within the method with the given token.
+    ///
+    /// Returns `None` if the method is unknown or the offset has no visible sequence point.
+    pub fn lookup(
+        &self,
+        method_token: u32,
+        il_offset: u32,
+    ) -> Result<Option<LineInfo>, PortablePdbError> {
+        if method_token >> 24 != METHOD_DEF_TABLE {
+            return Ok(None);
+        }
+        let row = match self.methods.row(&self.data, method_token & 0x00ff_ffff) {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let mut reader = Reader::new(row);
+        let mut document = reader.index(self.large_document_index)?;
+        let sequence_points = reader.index(self.large_blob_index)?;
+        if sequence_points == 0 {
+            return Ok(None);
+        }
+
+        let mut reader = Reader::new(self.blob(sequence_points)?);
+        reader.compressed_u32()?; // local signature
+        if document == 0 {
+            document = reader.compressed_u32()?;
+        }
+
+        let mut current = None;
+        let mut offset: Option<u32> = None;
+        let mut previous = None;
+
+        while !reader.is_empty() {
+            let delta_offset = reader.compressed_u32()?;
+            let next_offset = match offset {
+                // A zero delta after the first record switches to another document.
+                Some(_) if delta_offset == 0 => {
+                    document = reader.compressed_u32()?;
+                    continue;
+                }
+                Some(offset) => offset
+                    .checked_add(delta_offset)
+                    .ok_or(PortablePdbError::InvalidSequencePoints)?,
+                None => delta_offset,
+            };
+            if next_offset > il_offset {
+                break;
+            }
+            offset = Some(next_offset);
+
+            let delta_lines = reader.compressed_u32()?;
+            let delta_columns = match delta_lines {
+                0 => reader.compressed_u32()? as i32,
+                _ => reader.compressed_i32()?,
+            };
+
+            // Hidden sequence points do not map to any source line.
+            if delta_lines == 0 && delta_columns == 0 {
+                current = None;
+                continue;
+            }
+
+            let (line, column) = match previous {
+                Some((line, column)) => (
+                    apply_delta(line, reader.compressed_i32()?)?,
+                    apply_delta(column, reader.compressed_i32()?)?,
+                ),
+                None => (reader.compressed_u32()?, reader.compressed_u32()?),
+            };
+            previous = Some((line, column));
+            current = Some((document, line));
+        }
+
+        match current {
+            Some((document, line)) => Ok(Some(LineInfo {
+                path: self.document_name(document)?,
+                line,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the blob at `index` in the blob heap.
+    fn blob(&self, index: u32) -> Result<&[u8], PortablePdbError> {
+        let heap = self
+            .data
+            .get(self.blob_heap.clone())
+            .ok_or(PortablePdbError::UnexpectedEof)?;
+        let mut reader = Reader::new(
+            heap.get(index as usize..)
+                .ok_or(PortablePdbError::UnexpectedEof)?,
+        );
+        let len = reader.compressed_u32()?;
+        reader.bytes(len as usize)
+    }
+
+    /// Returns the path of the 1-based `document`.
+    ///
+    /// Document names are stored as a separator followed by blob indices of the path parts.
+    fn document_name(&self, document: u32) -> Result<String, PortablePdbError> {
+        let row = self
+            .documents
+            .row(&self.data, document)
+            .ok_or(PortablePdbError::UnexpectedEof)?;
+        let name = Reader::new(row).index(self.large_blob_index)?;
+
+        let mut reader = Reader::new(self.blob(name)?);
+        let separator = reader.u8()?;
+        let mut path = String::new();
+        let mut first = true;
+        while !reader.is_empty() {
+            if !first && separator != 0 {
+                path.push(char::from(separator));
+            }
+            first = false;
+
+            let part = reader.compressed_u32()?;
+            path.push_str(&String::from_utf8_lossy(self.blob(part)?));
+        }
+
+        Ok(path)
+    }
+}
+
+/// Parses the metadata root and returns the data ranges of all streams by name.
+fn parse_streams(data: &[u8]) -> Result<HashMap<String, Range<usize>>, PortablePdbError> {
+    let mut reader = Reader::new(data);
+    if reader.u32()? != METADATA_SIGNATURE {
+        return Err(PortablePdbError::InvalidSignature);
+    }
+    reader.bytes(8)?; // major version, minor version and reserved
+    let version_len = reader.u32()?;
+    reader.bytes(version_len as usize)?;
+    reader.u16()?; // flags
+    let stream_count = reader.u16()?;
+
+    let mut streams = HashMap::new();
+    for _ in 0..stream_count {
+        let offset = reader.u32()? as usize;
+        let size = reader.u32()? as usize;
+
+        // The name is null-terminated and padded to a multiple of four bytes.
+        let name_len = reader
+            .data
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or(PortablePdbError::UnexpectedEof)?;
+        let name = String::from_utf8_lossy(reader.bytes(name_len)?).into_owned();
+        reader.bytes(4 - name_len % 4)?;
+
+        if offset + size > data.len() {
+            return Err(PortablePdbError::UnexpectedEof);
+        }
+        streams.insert(name, offset..offset + size);
+    }
+
+    Ok(streams)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Appends a stream header with the given name to `root`.
+    fn stream_header(root: &mut Vec<u8>, offset: usize, size: usize, name: &str) {
+        root.extend((offset as u32).to_le_bytes());
+        root.extend((size as u32).to_le_bytes());
+        root.extend(name.as_bytes());
+        root.extend(std::iter::repeat(0).take(4 - name.len() % 4));
+    }
+
+    /// Builds a Portable PDB with a single document `/src/Program.cs` and a single method.
+    fn build_ppdb() -> ByteView<'static> {
+        build_ppdb_with_sequence_points(&[
+            0, // local signature
+            0, 0, 5, 10, 9, // IL 0: line 10
+            6, 0, 3, 4, 0, // IL 6: line 12
+            4, 0, 0, // IL 10: hidden
+            2, 1, 4, 0x7b, 0, // IL 12: line 9
+        ])
+    }
+
+    /// Builds a Portable PDB like [`build_ppdb`] with the given sequence points blob.
+    fn build_ppdb_with_sequence_points(sequence_points: &[u8]) -> ByteView<'static> {
+        let mut pdb = Vec::new();
+        pdb.extend(*b"\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\x10");
+        pdb.extend(0x5eb6_3a04u32.to_le_bytes());
+        pdb.extend([0; 12]); // entry point and referenced type system tables
+
+        let mut blob = vec![
+            0, // empty blob
+            3, b's', b'r', b'c', // 1
+            10, b'P', b'r', b'o', b'g', b'r', b'a', b'm', b'.', b'c', b's', // 5
+            4, b'/', 0, 1, 5, // 16: document name
+        ];
+        // 21: sequence points
+        assert!(sequence_points.len() < 0x80);
+        blob.push(sequence_points.len() as u8);
+        blob.extend(sequence_points);
+
+        let mut tables = Vec::new();
+        tables.extend([0, 0, 0, 0, 2, 0, 0, 1]);
+        tables.extend(((1u64 << 0x30) | (1u64 << 0x31)).to_le_bytes());
+        tables.extend(0u64.to_le_bytes());
+        tables.extend(1u32.to_le_bytes());
+        tables.extend(1u32.to_le_bytes());
+        tables.extend([16, 0, 0, 0, 0, 0, 0, 0]); // document
+        tables.extend([1, 0, 21, 0]); // method debug information
+
+        let mut root = Vec::new();
+        root.extend(METADATA_SIGNATURE.to_le_bytes());
+        root.extend([1, 0, 1, 0, 0, 0, 0, 0]);
+        root.extend(12u32.to_le_bytes());
+        root.extend(*b"PDB v1.0\0\0\0\0");
+        root.extend([0, 0, 3, 0]);
+
+        let headers_size = 3 * 8 + 8 + 4 + 8;
+        let pdb_offset = root.len() + headers_size;
+        let tables_offset = pdb_offset + pdb.len();
+        let blob_offset = tables_offset + tables.len();
+        stream_header(&mut root, pdb_offset, pdb.len(), "#Pdb");
+        stream_header(&mut root, tables_offset, tables.len(), "#~");
+        stream_header(&mut root, blob_offset, blob.len(), "#Blob");
+        assert_eq!(root.len(), pdb_offset);
+
+        root.extend(pdb);
+        root.extend(tables);
+        root.extend(blob);
+        ByteView::from_vec(root)
+    }
+
+    #[test]
+    fn test_compressed_integers() {
+        let mut reader = Reader::new(&[0x03, 0x80, 0x80, 0xc0, 0x00, 0x40, 0x00, 0x7b, 0x06]);
+        assert_eq!(reader.compressed_u32().unwrap(), 0x03);
+        assert_eq!(reader.compressed_u32().unwrap(), 0x80);
+        assert_eq!(reader.compressed_u32().unwrap(), 0x4000);
+        assert_eq!(reader.compressed_i32().unwrap(), -3);
+        assert_eq!(reader.compressed_i32().unwrap(), 3);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_debug_id() {
+        let ppdb = PortablePdb::parse(build_ppdb()).unwrap();
+        assert_eq!(
+            ppdb.debug_id().to_string(),
+            "04030201-0605-0807-090a-0b0c0d0e0f10-5eb63a04"
+        );
+    }
+
+    #[test]
+    fn test_lookup() {
+        let ppdb = PortablePdb::parse(build_ppdb()).unwrap();
+        let lookup = |il_offset| {
+            ppdb.lookup(0x0600_0001, il_offset)
+                .unwrap()
+                .map(|info| (info.path, info.line))
+        };
+        let line = |line| Some(("/src/Program.cs".to_owned(), line));
+
+        assert_eq!(lookup(0), line(10));
+        assert_eq!(lookup(4), line(10));
+        assert_eq!(lookup(6), line(12));
+        assert_eq!(lookup(11), None);
+        assert_eq!(lookup(12), line(9));
+        assert_eq!(lookup(100), line(9));
+
+        assert_eq!(ppdb.lookup(0x0600_0002, 0).unwrap(), None);
+        assert_eq!(ppdb.lookup(0x0200_0001, 0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_lookup_offset_overflow() {
+        // Hidden sequence points whose IL offsets add up to more than `u32::MAX`.
+        let mut sequence_points = vec![0];
+        for _ in 0..9 {
+            sequence_points.extend([0xdf, 0xff, 0xff, 0xff, 0, 0]);
+        }
+
+        let ppdb = PortablePdb::parse(build_ppdb_with_sequence_points(&sequence_points)).unwrap();
+        assert_eq!(ppdb.lookup(0x0600_0001, 0xffff_fff0).unwrap(), None);
+        assert!(matches!(
+            ppdb.lookup(0x0600_0001, u32::MAX),
+            Err(PortablePdbError::InvalidSequencePoints)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_signature() {
+        let data = ByteView::from_slice(b"MZ\x90\x00\x03\x00\x00\x00");
+        assert!(matches!(
+            PortablePdb::parse(data),
+            Err(PortablePdbError::InvalidSignature)
+        ));
+    }
+}
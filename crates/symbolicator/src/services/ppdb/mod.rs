@@ -0,0 +1,260 @@
+//! Service to retrieve Portable PDB files.
+//!
+//! Portable PDBs are the debug files of .NET assemblies.  Managed frames are addressed by a
+//! method token and an IL offset instead of an instruction address, which are mapped to source
+//! locations using the [`PortablePdb`] returned by this service.
+
+use std::fs::File;
+use std::io::{self, Cursor, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Error};
+use futures::future::{self, BoxFuture};
+use sentry::{Hub, SentryFutureExt};
+use symbolic::common::ByteView;
+use tempfile::tempfile_in;
+
+use crate::cache::{Cache, CacheStatus};
+use crate::services::cacher::{CacheItemRequest, CacheKey, CachePath, Cacher};
+use crate::services::download::{DownloadService, DownloadStatus, RemoteDif};
+use crate::sources::{FileType, SourceConfig};
+use crate::types::{ObjectId, Scope};
+use crate::utils::compression::decompress_object_file;
+use crate::utils::futures::{m, measure};
+
+use super::shared_cache::SharedCacheService;
+
+mod format;
+
+pub use format::{LineInfo, PortablePdb, PortablePdbError};
+
+/// The interface to the [`Cacher`] service.
+///
+/// The main work is done by the [`CacheItemRequest`] impl.
+#[derive(Debug, Clone)]
+struct FetchPortablePdb {
+    scope: Scope,
+    file_source: RemoteDif,
+    identifier: ObjectId,
+    download_svc: Arc<DownloadService>,
+    cache: Arc<Cacher<FetchPortablePdb>>,
+}
+
+impl FetchPortablePdb {
+    /// Downloads the file and saves it to `path`.
+    ///
+    /// Actual implementation of [`FetchPortablePdb::compute`].
+    async fn fetch_file(self, path: PathBuf) -> Result<CacheStatus, Error> {
+        let download_file = self.cache.tempfile()?;
+        let cache_key = self.get_cache_key();
+
+        let result = self
+            .download_svc
+            .download(self.file_source, download_file.path())
+            .await;
+
+        match result {
+            Ok(DownloadStatus::NotFound) => {
+                tracing::debug!("No Portable PDB found for {}", cache_key);
+                return Ok(CacheStatus::Negative);
+            }
+            Err(e) => {
+                let stderr: &dyn std::error::Error = &e;
+                tracing::debug!(stderr, "Error while downloading file");
+                return Ok(CacheStatus::CacheSpecificError(e.for_cache()));
+            }
+            Ok(DownloadStatus::Completed) => {
+                // fall through
+            }
+        }
+        let download_dir = download_file
+            .path()
+            .parent()
+            .ok_or_else(|| Error::msg("Parent of download dir not found"))?;
+        let decompressed_path = tempfile_in(download_dir)?;
+        let mut decompressed = match decompress_object_file(&download_file, decompressed_path) {
+            Ok(file) => file,
+            Err(err) => {
+                return Ok(CacheStatus::Malformed(err.to_string()));
+            }
+        };
+
+        // Seek back to the start and parse the file.
+        decompressed.seek(SeekFrom::Start(0))?;
+        let view = ByteView::map_file(decompressed)?;
+
+        match PortablePdb::parse(view.clone()) {
+            Ok(ppdb) => {
+                let requested_id = self.identifier.debug_id.map(|id| id.uuid());
+                if requested_id.map_or(false, |uuid| uuid != ppdb.debug_id().uuid()) {
+                    tracing::debug!("Portable PDB {} has a mismatching debug id", cache_key);
+                    return Ok(CacheStatus::Negative);
+                }
+            }
+            Err(err) => {
+                metric!(counter("services.ppdb.loaderror") += 1);
+                tracing::debug!("Failed to parse Portable PDB: {}", err);
+                return Ok(CacheStatus::Malformed(err.to_string()));
+            }
+        }
+
+        // The file is valid, lets save it.
+        let mut destination = File::create(path)?;
+        let mut cursor = Cursor::new(&view);
+        io::copy(&mut cursor, &mut destination)?;
+
+        Ok(CacheStatus::Positive)
+    }
+}
+
+impl CacheItemRequest for FetchPortablePdb {
+    /// The parsed file, which is only available for positive cache entries.
+    type Item = Option<Arc<PortablePdb>>;
+    type Error = Error;
+
+    fn get_cache_key(&self) -> CacheKey {
+        self.file_source.cache_key(self.scope.clone())
+    }
+
+    /// Downloads a file, writing it to `path`.
+    ///
+    /// Only when [`CacheStatus::Positive`] is returned is the data written to `path` used.
+    fn compute(&self, path: &Path) -> BoxFuture<'static, Result<CacheStatus, Self::Error>> {
+        let fut = self
+            .clone()
+            .fetch_file(path.to_path_buf())
+            .bind_hub(Hub::current());
+
+        let source_name = self.file_source.source_type_name().into();
+
+        let future = tokio::time::timeout(Duration::from_secs(1200), fut);
+        let future = measure(
+            "ppdbs",
+            m::timed_result,
+            Some(("source_type", source_name)),
+            future,
+        );
+        Box::pin(async move {
+            future
+                .await
+                .map_err(|_| Error::msg("Timeout fetching Portable PDB"))?
+        })
+    }
+
+    fn load(
+        &self,
+        _scope: Scope,
+        status: CacheStatus,
+        data: ByteView<'static>,
+        _path: CachePath,
+    ) -> Self::Item {
+        match status {
+            CacheStatus::Positive => PortablePdb::parse(data).ok().map(Arc::new),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PortablePdbService {
+    cache: Arc<Cacher<FetchPortablePdb>>,
+    download_svc: Arc<DownloadService>,
+}
+
+impl PortablePdbService {
+    pub fn new(
+        difs_cache: Cache,
+        shared_cache_svc: Arc<SharedCacheService>,
+        download_svc: Arc<DownloadService>,
+    ) -> Self {
+        Self {
+            cache: Arc::new(Cacher::new(difs_cache, shared_cache_svc)),
+            download_svc,
+        }
+    }
+
+    /// Returns the Portable PDB for the `identifier` if one is found on any of the `sources`.
+    ///
+    /// Sources are searched in order, the file found on the first source is returned.
+    pub async fn fetch(
+        &self,
+        identifier: ObjectId,
+        scope: Scope,
+        sources: Arc<[SourceConfig]>,
+    ) -> Option<Arc<PortablePdb>> {
+        let jobs = sources.iter().map(|source| {
+            self.fetch_from_source_with_error(identifier.clone(), scope.clone(), source.clone())
+                .bind_hub(Hub::new_from_top(Hub::current()))
+        });
+        future::join_all(jobs).await.into_iter().flatten().next()
+    }
+
+    /// Wraps `fetch_from_source` in sentry error handling.
+    async fn fetch_from_source_with_error(
+        &self,
+        identifier: ObjectId,
+        scope: Scope,
+        source: SourceConfig,
+    ) -> Option<Arc<PortablePdb>> {
+        match self
+            .fetch_from_source(identifier, scope, source)
+            .await
+            .context("Portable PDB svc failed for single source")
+        {
+            Ok(res) => res,
+            Err(err) => {
+                tracing::warn!("{}: {:?}", err, err.source());
+                sentry::capture_error(&*err);
+                None
+            }
+        }
+    }
+
+    /// Fetches all Portable PDBs listed by `source` and returns the first valid one.
+    async fn fetch_from_source(
+        &self,
+        identifier: ObjectId,
+        scope: Scope,
+        source: SourceConfig,
+    ) -> Result<Option<Arc<PortablePdb>>, Error> {
+        let file_sources = self
+            .download_svc
+            .list_files(source, &[FileType::PortablePdb], identifier.clone())
+            .await?;
+
+        let fetch_jobs = file_sources.into_iter().map(|file_source| {
+            let scope = if file_source.is_public() {
+                Scope::Global
+            } else {
+                scope.clone()
+            };
+            let request = FetchPortablePdb {
+                scope,
+                file_source,
+                identifier: identifier.clone(),
+                download_svc: self.download_svc.clone(),
+                cache: self.cache.clone(),
+            };
+            self.cache
+                .compute_memoized(request)
+                .bind_hub(Hub::new_from_top(Hub::current()))
+        });
+
+        let mut ret = None;
+        for result in future::join_all(fetch_jobs).await {
+            match result {
+                Ok(ppdb) if ret.is_none() => ret = (*ppdb).clone(),
+                Ok(_) => (),
+                Err(err) => {
+                    let stderr: &dyn std::error::Error = (*err).as_ref();
+                    let mut event = sentry::event_from_error(stderr);
+                    event.message = Some("Failure fetching Portable PDB from source".into());
+                    sentry::capture_event(event);
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
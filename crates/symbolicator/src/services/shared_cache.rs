@@ -11,7 +11,7 @@ use std::fmt;
 use std::io::SeekFrom;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Context as _, Error, Result};
 use futures::{Future, TryStreamExt};
@@ -469,6 +469,20 @@ impl SharedCacheKey {
         path
     }
 
+    /// The key under which negative and malformed items are shared.
+    ///
+    /// The key contains the current time window of length `ttl`, so items are no longer looked
+    /// up once their window has passed.  This also ensures that stale items, which are never
+    /// overwritten, do not block newer ones.  Backends can remove expired items using a
+    /// lifecycle rule matching the `.negative` suffix.
+    fn negative_key(&self, ttl: Duration) -> SharedCacheKey {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let window = now.as_secs() / ttl.as_secs().max(1);
+        let mut key = self.clone();
+        key.local_key.cache_key = format!("{}.{}.negative", self.local_key.cache_key, window);
+        key
+    }
+
     /// The [`SharedCacheKey::relative_path`] as a GCS bucket key.
     fn gcs_bucket_key(&self) -> String {
         // All our paths should be UTF-8, we don't construct non-UTF-8 paths.
@@ -573,6 +587,7 @@ pub struct SharedCacheService {
 struct InnerSharedCacheService {
     backend: Arc<SharedCacheBackend>,
     upload_queue_tx: mpsc::Sender<UploadMessage>,
+    negative_ttl: Option<Duration>,
 }
 
 impl SharedCacheService {
@@ -598,6 +613,7 @@ impl SharedCacheService {
             *inner.write().await = Some(InnerSharedCacheService {
                 backend,
                 upload_queue_tx: tx,
+                negative_ttl: config.negative_ttl,
             });
         }
     }
@@ -800,6 +816,38 @@ impl SharedCacheService {
         }
     }
 
+    /// Returns the time after which shared negative items expire, if they are shared at all.
+    async fn negative_ttl(&self) -> Option<Duration> {
+        self.inner.read().await.as_ref()?.negative_ttl
+    }
+
+    /// Retrieve a negative or malformed item from the shared cache.
+    ///
+    /// This works like [`SharedCacheService::fetch`], but always returns `false` unless
+    /// sharing of negative items is enabled via [`SharedCacheConfig::negative_ttl`].
+    pub async fn fetch_negative<W>(&self, key: &SharedCacheKey, writer: &mut W) -> bool
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        match self.negative_ttl().await {
+            Some(ttl) => self.fetch(&key.negative_key(ttl), writer).await,
+            None => false,
+        }
+    }
+
+    /// Place a negative or malformed item on the shared cache.
+    ///
+    /// This works like [`SharedCacheService::store`], but is a no-op unless sharing of
+    /// negative items is enabled via [`SharedCacheConfig::negative_ttl`].
+    pub async fn store_negative(
+        &self,
+        key: SharedCacheKey,
+        src: File,
+    ) -> Option<oneshot::Receiver<()>> {
+        let ttl = self.negative_ttl().await?;
+        self.store(key.negative_key(ttl), src, CacheStoreReason::New).await
+    }
+
     /// Place a file on the shared cache, if it does not yet exist there.
     ///
     /// Errors are transparently hidden, this service handles any errors itself.
@@ -943,6 +991,7 @@ mod tests {
         let cfg = SharedCacheConfig {
             max_concurrent_uploads: 10,
             max_upload_queue_size: 10,
+            negative_ttl: None,
             backend: SharedCacheBackendConfig::Filesystem(FilesystemSharedCacheConfig {
                 path: dir.path().to_path_buf(),
             }),
@@ -981,6 +1030,7 @@ mod tests {
         let cfg = SharedCacheConfig {
             max_concurrent_uploads: 10,
             max_upload_queue_size: 10,
+            negative_ttl: None,
             backend: SharedCacheBackendConfig::Filesystem(FilesystemSharedCacheConfig {
                 path: dir.path().to_path_buf(),
             }),
@@ -1014,6 +1064,7 @@ mod tests {
         let cfg = SharedCacheConfig {
             max_concurrent_uploads: 10,
             max_upload_queue_size: 10,
+            negative_ttl: None,
             backend: SharedCacheBackendConfig::Filesystem(FilesystemSharedCacheConfig {
                 path: dir.path().to_path_buf(),
             }),
@@ -1060,6 +1111,7 @@ mod tests {
         let cfg = SharedCacheConfig {
             max_concurrent_uploads: 10,
             max_upload_queue_size: 10,
+            negative_ttl: None,
             backend: SharedCacheBackendConfig::Gcs(GcsSharedCacheConfig::from(credentials)),
         };
         let svc = SharedCacheService::new(Some(cfg)).await;
@@ -1117,6 +1169,7 @@ mod tests {
         let cfg = SharedCacheConfig {
             max_concurrent_uploads: 10,
             max_upload_queue_size: 10,
+            negative_ttl: None,
             backend: SharedCacheBackendConfig::Gcs(GcsSharedCacheConfig::from(credentials)),
         };
         let svc = SharedCacheService::new(Some(cfg)).await;
@@ -1221,4 +1274,47 @@ mod tests {
 
         assert!(state.exists(&key).await.unwrap());
     }
+
+    #[tokio::test]
+    async fn test_filesystem_negative_store_fetch() {
+        test::setup();
+        let dir = test::tempdir();
+
+        let key = SharedCacheKey {
+            name: CacheName::Objects,
+            version: 0,
+            local_key: CacheKey {
+                cache_key: "some_item".to_string(),
+                scope: Scope::Global,
+            },
+        };
+
+        for negative_ttl in [None, Some(Duration::from_secs(300))] {
+            let cfg = SharedCacheConfig {
+                max_concurrent_uploads: 10,
+                max_upload_queue_size: 10,
+                negative_ttl,
+                backend: SharedCacheBackendConfig::Filesystem(FilesystemSharedCacheConfig {
+                    path: dir.path().to_path_buf(),
+                }),
+            };
+            let svc = SharedCacheService::new(Some(cfg)).await;
+            wait_init(&svc).await;
+
+            let fd = File::from_std(tempfile::tempfile().unwrap());
+            if let Some(recv) = svc.store_negative(key.clone(), fd).await {
+                // Wait for storing to complete.
+                recv.await.unwrap();
+            }
+
+            let mut writer = Vec::new();
+            let ret = svc.fetch_negative(&key, &mut writer).await;
+            assert_eq!(ret, negative_ttl.is_some());
+            assert_eq!(writer, b"");
+
+            // Negative items must not be visible as positive ones.
+            let ret = svc.fetch(&key, &mut writer).await;
+            assert!(!ret);
+        }
+    }
 }
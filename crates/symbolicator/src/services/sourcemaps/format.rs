@@ -0,0 +1,243 @@
+//! Helpers for JavaScript source maps.
+//!
+//! Source maps are parsed with the `sourcemap` crate, including index maps with inline sections.
+//! This module wraps its lookups and finds the source map referenced by a minified script.
+
+use thiserror::Error;
+use url::Url;
+
+/// The prefixes of comments referencing the source map of a script.
+const SOURCE_MAPPING_URL_PREFIXES: [&str; 2] = ["//# sourceMappingURL=", "//@ sourceMappingURL="];
+
+/// Errors happening while parsing a source map.
+#[derive(Debug, Error)]
+pub enum SourceMapError {
+    #[error("invalid source map")]
+    Invalid(#[from] sourcemap::Error),
+}
+
+/// The original location of a position in a minified script.
+///
+/// Lines and columns are zero-based, as in the source map itself.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLocation<'a> {
+    /// The path or URL of the original source file.
+    pub source: &'a str,
+    /// The line in the original source.
+    pub line: u32,
+    /// The column in the original source.
+    pub column: u32,
+    /// The original name of the identifier at this position, if known.
+    pub name: Option<&'a str>,
+    /// The contents of the original source, if embedded in the source map.
+    pub contents: Option<&'a str>,
+}
+
+/// A parsed source map.
+#[derive(Debug)]
+pub struct SourceMap {
+    inner: sourcemap::DecodedMap,
+}
+
+impl SourceMap {
+    /// Parses a source map or an index map.
+    ///
+    /// Index maps are flattened, which requires all of their sections to be inline.
+    pub fn parse(data: &[u8]) -> Result<Self, SourceMapError> {
+        let inner = match sourcemap::decode_slice(data)? {
+            sourcemap::DecodedMap::Index(index) => sourcemap::DecodedMap::Regular(index.flatten()?),
+            map => map,
+        };
+
+        Ok(Self { inner })
+    }
+
+    /// Returns the original location of the given zero-based `line` and `column`.
+    ///
+    /// This is the closest mapping at or before `column` on the same line.
+    pub fn lookup(&self, line: u32, column: u32) -> Option<SourceLocation<'_>> {
+        let source_map = match self.inner {
+            sourcemap::DecodedMap::Regular(ref source_map) => source_map,
+            sourcemap::DecodedMap::Hermes(ref hermes) => hermes,
+            // Index maps are flattened while parsing.
+            sourcemap::DecodedMap::Index(_) => return None,
+        };
+
+        let token = source_map.lookup_token(line, column)?;
+        if token.get_dst_line() != line {
+            return None;
+        }
+
+        Some(SourceLocation {
+            source: token.get_source()?,
+            line: token.get_src_line(),
+            column: token.get_src_col(),
+            name: token.get_name(),
+            contents: source_map.get_source_contents(token.get_src_id()),
+        })
+    }
+}
+
+/// Returns the `sourceMappingURL` referenced by a comment at the end of a minified script.
+pub fn find_source_mapping_url(source: &[u8]) -> Option<String> {
+    for line in source.rsplit(|byte| *byte == b'\n') {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        for prefix in SOURCE_MAPPING_URL_PREFIXES {
+            if let Some(url) = line.strip_prefix(prefix) {
+                return Some(url.trim().to_owned());
+            }
+        }
+
+        // The reference must be part of the trailing comments of the script.
+        if !line.starts_with("//") {
+            return None;
+        }
+    }
+
+    None
+}
+
+/// Decodes the contents of a `data:` URL, as used for source maps embedded in scripts.
+///
+/// Returns `None` if `url` is not a data URL.
+pub fn decode_data_url(url: &str) -> Option<Vec<u8>> {
+    let (header, data) = url.strip_prefix("data:")?.split_once(',')?;
+    if header.ends_with(";base64") {
+        base64::decode(data).ok()
+    } else {
+        Some(data.as_bytes().to_vec())
+    }
+}
+
+/// Resolves `url` relative to the URL of the file referencing it.
+pub fn resolve_url(base: &str, url: &str) -> String {
+    if is_absolute(url) {
+        return url.to_owned();
+    }
+
+    if let Ok(resolved) = Url::parse(base).and_then(|base| base.join(url)) {
+        return resolved.to_string();
+    }
+
+    match base.rfind('/') {
+        Some(index) => format!("{}/{}", &base[..index], url),
+        None => url.to_owned(),
+    }
+}
+
+/// Whether `url` is an absolute URL or path.
+fn is_absolute(url: &str) -> bool {
+    url.starts_with('/') || Url::parse(url).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE_MAP: &str = r#"{
+        "version": 3,
+        "file": "app.min.js",
+        "sourceRoot": "webpack:///src",
+        "sources": ["app.ts"],
+        "sourcesContent": ["let a = 1;\nfunction greet() {}\n"],
+        "names": ["greet"],
+        "mappings": "AAAA,IAAMA;AACN"
+    }"#;
+
+    #[test]
+    fn test_lookup() {
+        let source_map = SourceMap::parse(SOURCE_MAP.as_bytes()).unwrap();
+
+        let location = source_map.lookup(0, 0).unwrap();
+        assert_eq!(location.source, "webpack:///src/app.ts");
+        assert_eq!((location.line, location.column), (0, 0));
+        assert_eq!(location.name, None);
+        assert_eq!(location.contents, Some("let a = 1;\nfunction greet() {}\n"));
+
+        let location = source_map.lookup(0, 10).unwrap();
+        assert_eq!((location.line, location.column), (0, 6));
+        assert_eq!(location.name, Some("greet"));
+
+        let location = source_map.lookup(1, 5).unwrap();
+        assert_eq!((location.line, location.column), (1, 0));
+
+        assert_eq!(source_map.lookup(2, 0), None);
+    }
+
+    #[test]
+    fn test_index_map() {
+        let index_map = format!(
+            r#")]}}'
+            {{
+                "version": 3,
+                "sections": [
+                    {{"offset": {{"line": 0, "column": 0}}, "map": {}}},
+                    {{"offset": {{"line": 10, "column": 4}}, "map": {{
+                        "version": 3,
+                        "sources": ["/lib.js"],
+                        "names": [],
+                        "mappings": "AAAA"
+                    }}}}
+                ]
+            }}"#,
+            SOURCE_MAP
+        );
+        let source_map = SourceMap::parse(index_map.as_bytes()).unwrap();
+
+        assert_eq!(source_map.lookup(0, 10).unwrap().name, Some("greet"));
+        assert_eq!(source_map.lookup(10, 2), None);
+
+        let location = source_map.lookup(10, 4).unwrap();
+        assert_eq!(location.source, "/lib.js");
+        assert_eq!(location.contents, None);
+    }
+
+    #[test]
+    fn test_invalid_mappings() {
+        let source_map = r#"{"version": 3, "sources": [], "mappings": "AA"}"#;
+        assert!(SourceMap::parse(source_map.as_bytes()).is_err());
+
+        let source_map = r#"{"version": 3, "sources": [], "mappings": "AAAA"}"#;
+        assert!(SourceMap::parse(source_map.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_find_source_mapping_url() {
+        let script = b"function a(){}\n//# sourceMappingURL=app.min.js.map\n// trailing\n\n";
+        assert_eq!(
+            find_source_mapping_url(script).as_deref(),
+            Some("app.min.js.map")
+        );
+
+        let script = b"//# sourceMappingURL=app.min.js.map\nfunction a(){}\n";
+        assert_eq!(find_source_mapping_url(script), None);
+    }
+
+    #[test]
+    fn test_decode_data_url() {
+        let url = "data:application/json;charset=utf-8;base64,eyJ2ZXJzaW9uIjozfQ==";
+        assert_eq!(decode_data_url(url).unwrap(), br#"{"version":3}"#);
+        assert_eq!(decode_data_url("app.min.js.map"), None);
+    }
+
+    #[test]
+    fn test_resolve_url() {
+        assert_eq!(
+            resolve_url("https://example.com/static/app.min.js", "app.min.js.map"),
+            "https://example.com/static/app.min.js.map"
+        );
+        assert_eq!(
+            resolve_url("~/static/app.min.js", "maps/app.min.js.map"),
+            "~/static/maps/app.min.js.map"
+        );
+        assert_eq!(
+            resolve_url("~/static/app.min.js", "https://cdn.example.com/app.js.map"),
+            "https://cdn.example.com/app.js.map"
+        );
+    }
+}
@@ -0,0 +1,446 @@
+//! Service to retrieve JavaScript source maps.
+//!
+//! JavaScript frames are addressed by the URL of a minified script and a line and column within
+//! it.  The [`SourceMap`] of the script is either referenced directly by its module, or found
+//! through the `sourceMappingURL` comment at the end of the script.  Both the scripts and their
+//! source maps are fetched from the configured sources.  Source maps embedded in `data:` URLs are
+//! decoded once and cached alongside their script.  Source maps are parsed on the CPU pool.
+
+use std::fs::File;
+use std::io::{self, Cursor, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Error};
+use futures::future::{self, BoxFuture};
+use sentry::{Hub, SentryFutureExt};
+use symbolic::common::ByteView;
+use tempfile::tempfile_in;
+
+use crate::cache::{Cache, CacheStatus};
+use crate::services::cacher::{CacheItemRequest, CacheKey, CachePath, Cacher};
+use crate::services::download::{DownloadService, DownloadStatus, RemoteDif};
+use crate::sources::{FileType, SourceConfig};
+use crate::types::{ObjectId, Scope};
+use crate::utils::compression::decompress_object_file;
+use crate::utils::futures::{m, measure, CancelOnDrop};
+
+use super::shared_cache::SharedCacheService;
+
+mod format;
+
+pub use format::{SourceLocation, SourceMap, SourceMapError};
+
+/// A file fetched to symbolicate JavaScript frames.
+#[derive(Debug, Clone)]
+enum JsArtifact {
+    /// A minified script, of which only the referenced source map URL is kept.
+    MinifiedSource {
+        /// The cache key of the script, which also keys a source map embedded in it.
+        cache_key: CacheKey,
+        source_mapping_url: Option<String>,
+    },
+    /// The unparsed contents of a source map.
+    SourceMap(ByteView<'static>),
+}
+
+/// The interface to the [`Cacher`] service.
+///
+/// The main work is done by the [`CacheItemRequest`] impl.
+#[derive(Debug, Clone)]
+struct FetchJsArtifact {
+    scope: Scope,
+    file_source: RemoteDif,
+    file_type: FileType,
+    download_svc: Arc<DownloadService>,
+    cache: Arc<Cacher<FetchJsArtifact>>,
+    cpu_pool: tokio::runtime::Handle,
+}
+
+impl FetchJsArtifact {
+    /// Downloads the file and saves it to `path`.
+    ///
+    /// Actual implementation of [`FetchJsArtifact::compute`].
+    async fn fetch_file(self, path: PathBuf) -> Result<CacheStatus, Error> {
+        let download_file = self.cache.tempfile()?;
+        let cache_key = self.get_cache_key();
+
+        let result = self
+            .download_svc
+            .download(self.file_source, download_file.path())
+            .await;
+
+        match result {
+            Ok(DownloadStatus::NotFound) => {
+                tracing::debug!("No {} found for {}", self.file_type.as_ref(), cache_key);
+                return Ok(CacheStatus::Negative);
+            }
+            Err(e) => {
+                let stderr: &dyn std::error::Error = &e;
+                tracing::debug!(stderr, "Error while downloading file");
+                return Ok(CacheStatus::CacheSpecificError(e.for_cache()));
+            }
+            Ok(DownloadStatus::Completed) => {
+                // fall through
+            }
+        }
+        let download_dir = download_file
+            .path()
+            .parent()
+            .ok_or_else(|| Error::msg("Parent of download dir not found"))?;
+        let decompressed_path = tempfile_in(download_dir)?;
+        let mut decompressed = match decompress_object_file(&download_file, decompressed_path) {
+            Ok(file) => file,
+            Err(err) => {
+                return Ok(CacheStatus::Malformed(err.to_string()));
+            }
+        };
+
+        // Seek back to the start and parse the file.
+        decompressed.seek(SeekFrom::Start(0))?;
+        let view = ByteView::map_file(decompressed)?;
+
+        if self.file_type == FileType::SourceMap {
+            let data = view.clone();
+            let validate = async move { SourceMap::parse(&data).map(drop) };
+            let result = CancelOnDrop::new(self.cpu_pool.spawn(validate.bind_hub(Hub::current())))
+                .await
+                .context("Source map validation was canceled")?;
+
+            if let Err(err) = result {
+                metric!(counter("services.sourcemaps.loaderror") += 1);
+                tracing::debug!("Failed to parse source map: {}", err);
+                return Ok(CacheStatus::Malformed(err.to_string()));
+            }
+        }
+
+        // The file is valid, lets save it.
+        let mut destination = File::create(path)?;
+        let mut cursor = Cursor::new(&view);
+        io::copy(&mut cursor, &mut destination)?;
+
+        Ok(CacheStatus::Positive)
+    }
+}
+
+impl CacheItemRequest for FetchJsArtifact {
+    /// The file, which is only available for positive cache entries.
+    type Item = Option<JsArtifact>;
+    type Error = Error;
+
+    fn get_cache_key(&self) -> CacheKey {
+        self.file_source.cache_key(self.scope.clone())
+    }
+
+    /// Downloads a file, writing it to `path`.
+    ///
+    /// Only when [`CacheStatus::Positive`] is returned is the data written to `path` used.
+    fn compute(&self, path: &Path) -> BoxFuture<'static, Result<CacheStatus, Self::Error>> {
+        let fut = self
+            .clone()
+            .fetch_file(path.to_path_buf())
+            .bind_hub(Hub::current());
+
+        let source_name = self.file_source.source_type_name().into();
+
+        let future = tokio::time::timeout(Duration::from_secs(1200), fut);
+        let future = measure(
+            "sourcemaps",
+            m::timed_result,
+            Some(("source_type", source_name)),
+            future,
+        );
+        Box::pin(async move {
+            future
+                .await
+                .map_err(|_| Error::msg("Timeout fetching JavaScript file"))?
+        })
+    }
+
+    fn load(
+        &self,
+        _scope: Scope,
+        status: CacheStatus,
+        data: ByteView<'static>,
+        _path: CachePath,
+    ) -> Self::Item {
+        match status {
+            CacheStatus::Positive => Some(match self.file_type {
+                FileType::SourceMap => JsArtifact::SourceMap(data),
+                _ => JsArtifact::MinifiedSource {
+                    cache_key: self.get_cache_key(),
+                    source_mapping_url: format::find_source_mapping_url(&data),
+                },
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes a source map embedded in a minified script as `data:` URL.
+///
+/// The decoded source map is cached under the key of the script it is embedded in.
+#[derive(Debug, Clone)]
+struct FetchEmbeddedSourceMap {
+    script_key: CacheKey,
+    data_url: Arc<str>,
+    cpu_pool: tokio::runtime::Handle,
+}
+
+impl CacheItemRequest for FetchEmbeddedSourceMap {
+    /// The decoded source map, which is only available for positive cache entries.
+    type Item = Option<ByteView<'static>>;
+    type Error = Error;
+
+    fn get_cache_key(&self) -> CacheKey {
+        CacheKey {
+            cache_key: format!("{}.sourcemap", self.script_key.cache_key),
+            scope: self.script_key.scope.clone(),
+        }
+    }
+
+    /// Decodes and validates the source map on the CPU pool, writing it to `path`.
+    fn compute(&self, path: &Path) -> BoxFuture<'static, Result<CacheStatus, Self::Error>> {
+        let path = path.to_path_buf();
+        let data_url = self.data_url.clone();
+
+        let future = async move {
+            let data = match format::decode_data_url(&data_url) {
+                Some(data) => data,
+                None => return Ok(CacheStatus::Malformed("invalid data URL".to_owned())),
+            };
+
+            if let Err(err) = SourceMap::parse(&data) {
+                metric!(counter("services.sourcemaps.loaderror") += 1);
+                tracing::debug!("Failed to parse embedded source map: {}", err);
+                return Ok(CacheStatus::Malformed(err.to_string()));
+            }
+
+            std::fs::write(path, data)?;
+            Ok(CacheStatus::Positive)
+        };
+
+        let future = CancelOnDrop::new(self.cpu_pool.spawn(future.bind_hub(Hub::current())));
+        Box::pin(async move {
+            future
+                .await
+                .map_err(|_| Error::msg("Decoding embedded source map was canceled"))?
+        })
+    }
+
+    fn load(
+        &self,
+        _scope: Scope,
+        status: CacheStatus,
+        data: ByteView<'static>,
+        _path: CachePath,
+    ) -> Self::Item {
+        match status {
+            CacheStatus::Positive => Some(data),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceMapService {
+    cache: Arc<Cacher<FetchJsArtifact>>,
+    embedded_cache: Arc<Cacher<FetchEmbeddedSourceMap>>,
+    download_svc: Arc<DownloadService>,
+    cpu_pool: tokio::runtime::Handle,
+}
+
+impl SourceMapService {
+    pub fn new(
+        difs_cache: Cache,
+        shared_cache_svc: Arc<SharedCacheService>,
+        download_svc: Arc<DownloadService>,
+        cpu_pool: tokio::runtime::Handle,
+    ) -> Self {
+        Self {
+            cache: Arc::new(Cacher::new(difs_cache.clone(), shared_cache_svc.clone())),
+            embedded_cache: Arc::new(Cacher::new(difs_cache, shared_cache_svc)),
+            download_svc,
+            cpu_pool,
+        }
+    }
+
+    /// Returns the source map of the minified script identified by `identifier`.
+    ///
+    /// The `code_file` of the identifier is the URL of the script.  If the `debug_file` is
+    /// given, it is the URL of the source map.  Otherwise, the script is fetched to look up the
+    /// source map it references, which may also be embedded in a `data:` URL.
+    pub async fn fetch(
+        &self,
+        identifier: ObjectId,
+        scope: Scope,
+        sources: Arc<[SourceConfig]>,
+    ) -> Option<Arc<SourceMap>> {
+        let source_map_url = match identifier.debug_file {
+            Some(ref url) => url.clone(),
+            None => {
+                let script_url = identifier.code_file.as_deref()?;
+                let (cache_key, url) = match self
+                    .fetch_file(FileType::MinifiedSource, &identifier, &scope, &sources)
+                    .await?
+                {
+                    JsArtifact::MinifiedSource {
+                        cache_key,
+                        source_mapping_url,
+                    } => (cache_key, source_mapping_url?),
+                    JsArtifact::SourceMap(_) => return None,
+                };
+
+                if url.starts_with("data:") {
+                    let data = self.fetch_embedded(cache_key, url).await?;
+                    return self.parse(data).await;
+                }
+
+                format::resolve_url(script_url, &url)
+            }
+        };
+
+        let identifier = ObjectId {
+            debug_file: Some(source_map_url),
+            ..identifier
+        };
+        let data = match self
+            .fetch_file(FileType::SourceMap, &identifier, &scope, &sources)
+            .await?
+        {
+            JsArtifact::SourceMap(data) => data,
+            JsArtifact::MinifiedSource { .. } => return None,
+        };
+        self.parse(data).await
+    }
+
+    /// Parses a source map on the CPU pool.
+    async fn parse(&self, data: ByteView<'static>) -> Option<Arc<SourceMap>> {
+        let parse = async move { SourceMap::parse(&data) };
+        match CancelOnDrop::new(self.cpu_pool.spawn(parse.bind_hub(Hub::current()))).await {
+            Ok(Ok(source_map)) => Some(Arc::new(source_map)),
+            Ok(Err(err)) => {
+                tracing::debug!("Failed to parse source map: {}", err);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Returns the decoded source map embedded in the script with the given cache key.
+    async fn fetch_embedded(
+        &self,
+        script_key: CacheKey,
+        data_url: String,
+    ) -> Option<ByteView<'static>> {
+        let request = FetchEmbeddedSourceMap {
+            script_key,
+            data_url: data_url.into(),
+            cpu_pool: self.cpu_pool.clone(),
+        };
+
+        match self.embedded_cache.compute_memoized(request).await {
+            Ok(data) => (*data).clone(),
+            Err(err) => {
+                let stderr: &dyn std::error::Error = (*err).as_ref();
+                let mut event = sentry::event_from_error(stderr);
+                event.message = Some("Failure decoding embedded source map".into());
+                sentry::capture_event(event);
+                None
+            }
+        }
+    }
+
+    /// Returns the file of the given type if it is found on any of the `sources`.
+    ///
+    /// Sources are searched in order, the file found on the first source is returned.
+    async fn fetch_file(
+        &self,
+        file_type: FileType,
+        identifier: &ObjectId,
+        scope: &Scope,
+        sources: &[SourceConfig],
+    ) -> Option<JsArtifact> {
+        let jobs = sources.iter().map(|source| {
+            self.fetch_from_source_with_error(
+                file_type,
+                identifier.clone(),
+                scope.clone(),
+                source.clone(),
+            )
+            .bind_hub(Hub::new_from_top(Hub::current()))
+        });
+        future::join_all(jobs).await.into_iter().flatten().next()
+    }
+
+    /// Wraps `fetch_from_source` in sentry error handling.
+    async fn fetch_from_source_with_error(
+        &self,
+        file_type: FileType,
+        identifier: ObjectId,
+        scope: Scope,
+        source: SourceConfig,
+    ) -> Option<JsArtifact> {
+        match self
+            .fetch_from_source(file_type, identifier, scope, source)
+            .await
+            .context("Source map svc failed for single source")
+        {
+            Ok(res) => res,
+            Err(err) => {
+                tracing::warn!("{}: {:?}", err, err.source());
+                sentry::capture_error(&*err);
+                None
+            }
+        }
+    }
+
+    /// Fetches all files of the given type listed by `source` and returns the first valid one.
+    async fn fetch_from_source(
+        &self,
+        file_type: FileType,
+        identifier: ObjectId,
+        scope: Scope,
+        source: SourceConfig,
+    ) -> Result<Option<JsArtifact>, Error> {
+        let file_sources = self
+            .download_svc
+            .list_files(source, &[file_type], identifier)
+            .await?;
+
+        let fetch_jobs = file_sources.into_iter().map(|file_source| {
+            let scope = if file_source.is_public() {
+                Scope::Global
+            } else {
+                scope.clone()
+            };
+            let request = FetchJsArtifact {
+                scope,
+                file_source,
+                file_type,
+                download_svc: self.download_svc.clone(),
+                cache: self.cache.clone(),
+                cpu_pool: self.cpu_pool.clone(),
+            };
+            self.cache
+                .compute_memoized(request)
+                .bind_hub(Hub::new_from_top(Hub::current()))
+        });
+
+        let mut ret = None;
+        for result in future::join_all(fetch_jobs).await {
+            match result {
+                Ok(artifact) if ret.is_none() => ret = (*artifact).clone(),
+                Ok(_) => (),
+                Err(err) => {
+                    let stderr: &dyn std::error::Error = (*err).as_ref();
+                    let mut event = sentry::event_from_error(stderr);
+                    event.message = Some("Failure fetching JavaScript file from source".into());
+                    sentry::capture_event(event);
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
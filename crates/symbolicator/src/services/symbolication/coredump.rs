@@ -0,0 +1,920 @@
+//! Processing of Linux ELF core dumps.
+//!
+//! Core dumps are converted into a minimal minidump containing the threads, their stack
+//! memory, the mapped modules and the crashing signal.  This minidump is then stackwalked and
+//! symbolicated exactly like an uploaded minidump, including fetching CFI for its modules.
+//!
+//! Only 64-bit little-endian cores of `x86_64` and `aarch64` processes are supported.  All
+//! multi-byte values in both formats are little-endian.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use sentry::SentryFutureExt;
+use symbolic::common::ByteView;
+use tempfile::TempPath;
+use thiserror::Error;
+
+use crate::sources::SourceConfig;
+use crate::types::{CompletedSymbolicationResponse, RequestId, RequestOptions, Scope};
+use crate::utils::futures::CancelOnDrop;
+
+use super::{MaxRequestsError, SymbolicationActor, SymbolicationError};
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_CORE: u16 = 4;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+
+const NT_PRSTATUS: u32 = 1;
+const NT_AUXV: u32 = 6;
+const NT_SIGINFO: u32 = 0x5349_4749;
+const NT_FILE: u32 = 0x4649_4c45;
+const NT_GNU_BUILD_ID: u32 = 3;
+
+const AT_NULL: u64 = 0;
+const AT_SYSINFO_EHDR: u64 = 33;
+
+/// Offset of `pr_pid` in `struct elf_prstatus`.
+const PRSTATUS_PID: usize = 32;
+/// Offset of `pr_reg` in `struct elf_prstatus`.
+const PRSTATUS_REGS: usize = 112;
+
+const MINIDUMP_SIGNATURE: u32 = 0x504d_444d;
+const MINIDUMP_VERSION: u32 = 0xa793;
+const THREAD_LIST_STREAM: u32 = 3;
+const MODULE_LIST_STREAM: u32 = 4;
+const MEMORY_LIST_STREAM: u32 = 5;
+const EXCEPTION_STREAM: u32 = 6;
+const SYSTEM_INFO_STREAM: u32 = 7;
+const PLATFORM_LINUX: u32 = 0x8201;
+const CV_SIGNATURE_ELF: u32 = 0x4270_454c;
+
+/// The number of bytes below the stack pointer included in the stack memory of a thread.
+const STACK_RED_ZONE: u64 = 128;
+
+/// The maximum size of stack memory copied into the minidump per thread.
+const MAX_STACK_SIZE: u64 = 512 * 1024;
+
+/// The name under which the kernel's vDSO is listed in the module list.
+const VDSO_NAME: &str = "linux-gate.so";
+
+/// An error converting an ELF core dump.
+#[derive(Debug, Error)]
+pub enum CoredumpError {
+    #[error("not a 64-bit little-endian ELF core file")]
+    NotCore,
+
+    #[error("unsupported core file architecture {0}")]
+    UnsupportedArch(u16),
+
+    #[error("malformed ELF core file")]
+    Malformed,
+
+    #[error("core file contains no threads")]
+    NoThreads,
+
+    #[error("converted minidump exceeds 4 GiB")]
+    TooLarge,
+}
+
+/// The processor architecture of a core dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CoreArch {
+    Amd64,
+    Arm64,
+}
+
+impl CoreArch {
+    /// The `PROCESSOR_ARCHITECTURE` in the minidump system info.
+    fn processor_architecture(self) -> u16 {
+        match self {
+            CoreArch::Amd64 => 9,
+            CoreArch::Arm64 => 12,
+        }
+    }
+
+    /// The number of 64-bit registers in `pr_reg` of `struct elf_prstatus`.
+    fn register_count(self) -> usize {
+        match self {
+            CoreArch::Amd64 => 27,
+            CoreArch::Arm64 => 34,
+        }
+    }
+
+    /// Returns the stack pointer from the raw `pr_reg` registers.
+    fn stack_pointer(self, registers: &[u64]) -> u64 {
+        match self {
+            CoreArch::Amd64 => registers[19],
+            CoreArch::Arm64 => registers[31],
+        }
+    }
+
+    /// Writes a minidump `CONTEXT` from the raw `pr_reg` registers.
+    fn write_context(self, registers: &[u64]) -> Vec<u8> {
+        match self {
+            CoreArch::Amd64 => {
+                // `CONTEXT_AMD64` with control, integer and segment registers.
+                let mut context = vec![0; 1232];
+                put_u32(&mut context, 0x30, 0x0010_0007);
+                for (index, reg) in [17, 23, 24, 25, 26, 20].into_iter().enumerate() {
+                    put_u16(&mut context, 0x38 + index * 2, registers[reg] as u16);
+                }
+                put_u32(&mut context, 0x44, registers[18] as u32);
+                // rax, rcx, rdx, rbx, rsp, rbp, rsi, rdi, r8 to r15
+                let order = [10, 11, 12, 5, 19, 4, 13, 14, 9, 8, 7, 6, 3, 2, 1, 0];
+                for (index, reg) in order.into_iter().enumerate() {
+                    put_u64(&mut context, 0x78 + index * 8, registers[reg]);
+                }
+                put_u64(&mut context, 0xf8, registers[16]);
+                context
+            }
+            CoreArch::Arm64 => {
+                // `CONTEXT_ARM64` with control and integer registers.
+                let mut context = vec![0; 912];
+                put_u32(&mut context, 0, 0x0040_0003);
+                put_u32(&mut context, 4, registers[33] as u32);
+                // x0 to x30, sp, pc
+                for (index, value) in registers[..33].iter().enumerate() {
+                    put_u64(&mut context, 8 + index * 8, *value);
+                }
+                context
+            }
+        }
+    }
+}
+
+/// A thread from an `NT_PRSTATUS` note.
+#[derive(Debug)]
+struct CoreThread {
+    tid: u32,
+    registers: Vec<u64>,
+}
+
+/// A file-backed mapping from the `NT_FILE` note.
+#[derive(Debug)]
+struct FileMapping {
+    start: u64,
+    end: u64,
+    file_offset: u64,
+    path: String,
+}
+
+/// The signal which caused the core dump, from the `NT_SIGINFO` note.
+#[derive(Debug)]
+struct CoreSignal {
+    number: u32,
+    code: u32,
+    address: u64,
+}
+
+/// A parsed ELF core dump.
+#[derive(Debug)]
+struct Core<'a> {
+    arch: CoreArch,
+    /// Memory of `PT_LOAD` segments which is present in the core, by virtual address.
+    memory: Vec<(u64, &'a [u8])>,
+    threads: Vec<CoreThread>,
+    mappings: Vec<FileMapping>,
+    signal: Option<CoreSignal>,
+    vdso: Option<u64>,
+}
+
+impl<'a> Core<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, CoredumpError> {
+        let ident = data.get(..16).ok_or(CoredumpError::NotCore)?;
+        if !ident.starts_with(ELF_MAGIC) || ident[4] != ELFCLASS64 || ident[5] != ELFDATA2LSB {
+            return Err(CoredumpError::NotCore);
+        }
+        if read_u16(data, 16) != Some(ET_CORE) {
+            return Err(CoredumpError::NotCore);
+        }
+
+        let arch = match read_u16(data, 18).ok_or(CoredumpError::Malformed)? {
+            EM_X86_64 => CoreArch::Amd64,
+            EM_AARCH64 => CoreArch::Arm64,
+            machine => return Err(CoredumpError::UnsupportedArch(machine)),
+        };
+
+        let mut core = Core {
+            arch,
+            memory: Vec::new(),
+            threads: Vec::new(),
+            mappings: Vec::new(),
+            signal: None,
+            vdso: None,
+        };
+
+        for header in program_headers(data).ok_or(CoredumpError::Malformed)? {
+            match header.ty {
+                PT_LOAD if header.filesz > 0 => {
+                    // Segments may be truncated if the core was cut off, so take what is there.
+                    let start = (header.offset as usize).min(data.len());
+                    let end = start.saturating_add(header.filesz as usize).min(data.len());
+                    core.memory.push((header.vaddr, &data[start..end]));
+                }
+                PT_NOTE => {
+                    let start = header.offset as usize;
+                    let end = start.saturating_add(header.filesz as usize);
+                    let notes = data.get(start..end).ok_or(CoredumpError::Malformed)?;
+                    for note in Notes::new(notes).filter(|note| note.name == b"CORE") {
+                        core.add_note(note.ty, note.desc);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if core.threads.is_empty() {
+            return Err(CoredumpError::NoThreads);
+        }
+
+        Ok(core)
+    }
+
+    fn add_note(&mut self, ty: u32, desc: &[u8]) {
+        match ty {
+            NT_PRSTATUS => {
+                let registers = (0..self.arch.register_count())
+                    .map(|index| read_u64(desc, PRSTATUS_REGS + index * 8))
+                    .collect::<Option<Vec<_>>>();
+                if let (Some(tid), Some(registers)) = (read_u32(desc, PRSTATUS_PID), registers) {
+                    self.threads.push(CoreThread { tid, registers });
+                }
+            }
+            NT_SIGINFO => {
+                if let (Some(number), Some(code)) = (read_u32(desc, 0), read_u32(desc, 8)) {
+                    // `si_addr` is only meaningful for SIGILL, SIGBUS, SIGFPE and SIGSEGV.
+                    let address = match number {
+                        4 | 7 | 8 | 11 => read_u64(desc, 16).unwrap_or_default(),
+                        _ => 0,
+                    };
+                    self.signal = Some(CoreSignal {
+                        number,
+                        code,
+                        address,
+                    });
+                }
+            }
+            NT_AUXV => {
+                self.vdso = (0..)
+                    .map_while(|index| {
+                        Some((read_u64(desc, index * 16)?, read_u64(desc, index * 16 + 8)?))
+                    })
+                    .take_while(|(key, _)| *key != AT_NULL)
+                    .find(|(key, _)| *key == AT_SYSINFO_EHDR)
+                    .map(|(_, value)| value);
+            }
+            NT_FILE => self.mappings = parse_file_note(desc).unwrap_or_default(),
+            _ => (),
+        }
+    }
+
+    /// Returns `len` bytes of memory at `addr`, if they are contained in the core.
+    fn read_memory(&self, addr: u64, len: u64) -> Option<&'a [u8]> {
+        let (start, data) = self.segment_at(addr)?;
+        let offset = (addr - start) as usize;
+        data.get(offset..offset.checked_add(len as usize)?)
+    }
+
+    /// Returns the segment containing `addr`.
+    fn segment_at(&self, addr: u64) -> Option<(u64, &'a [u8])> {
+        self.memory
+            .iter()
+            .copied()
+            .find(|(start, data)| addr >= *start && addr - start < data.len() as u64)
+    }
+
+    /// Returns the memory of the stack above the given stack pointer.
+    fn stack_memory(&self, stack_pointer: u64) -> Option<(u64, &'a [u8])> {
+        let (start, data) = self.segment_at(stack_pointer)?;
+        let stack_start = stack_pointer.saturating_sub(STACK_RED_ZONE).max(start);
+        let offset = (stack_start - start) as usize;
+        let len = (data.len() - offset).min(MAX_STACK_SIZE as usize);
+        Some((stack_start, &data[offset..offset + len]))
+    }
+
+    /// Reads the GNU build id of an ELF image mapped at `base`.
+    ///
+    /// This relies on the ELF header and notes being contained in the core, which is the case
+    /// with the kernel's default `coredump_filter`.
+    fn build_id(&self, base: u64) -> Option<&'a [u8]> {
+        let header = self.read_memory(base, 64)?;
+        if !header.starts_with(ELF_MAGIC) || header[4] != ELFCLASS64 || header[5] != ELFDATA2LSB {
+            return None;
+        }
+
+        let phoff = read_u64(header, 32)?;
+        let phentsize = read_u16(header, 54)? as u64;
+        let phnum = read_u16(header, 56)? as u64;
+        let phdrs = self.read_memory(base.checked_add(phoff)?, phentsize * phnum)?;
+        let headers: Vec<_> = (0..phnum as usize)
+            .map(|index| ProgramHeader::parse(phdrs, index * phentsize as usize))
+            .collect::<Option<_>>()?;
+
+        // Addresses in shared libraries and PIEs are relative to their load bias.
+        let first_load = headers.iter().find(|header| header.ty == PT_LOAD)?;
+        let bias = base.wrapping_sub(first_load.vaddr & !0xfff);
+
+        headers
+            .iter()
+            .filter(|header| header.ty == PT_NOTE)
+            .filter_map(|header| self.read_memory(bias.wrapping_add(header.vaddr), header.filesz))
+            .flat_map(Notes::new)
+            .find(|note| note.name == b"GNU" && note.ty == NT_GNU_BUILD_ID)
+            .map(|note| note.desc)
+    }
+
+    /// Returns the modules of the process as `(base, size, name, build_id)`.
+    ///
+    /// Modules are formed from all file mappings with the same path, and the vDSO.
+    fn modules(&self) -> Vec<(u64, u64, &str, Option<&'a [u8]>)> {
+        let mut ranges = BTreeMap::<&str, (u64, u64, Option<u64>)>::new();
+        for mapping in &self.mappings {
+            let range = ranges
+                .entry(&mapping.path)
+                .or_insert((mapping.start, mapping.end, None));
+            range.0 = range.0.min(mapping.start);
+            range.1 = range.1.max(mapping.end);
+            if mapping.file_offset == 0 && range.2.is_none() {
+                range.2 = Some(mapping.start);
+            }
+        }
+
+        let mut modules: Vec<_> = ranges
+            .into_iter()
+            .map(|(path, (start, end, header))| {
+                let base = header.unwrap_or(start);
+                (
+                    base,
+                    end.saturating_sub(base),
+                    path,
+                    header.and_then(|base| self.build_id(base)),
+                )
+            })
+            .collect();
+
+        if let Some(vdso) = self.vdso {
+            if let Some((start, data)) = self.segment_at(vdso) {
+                let size = start + data.len() as u64 - vdso;
+                modules.push((vdso, size, VDSO_NAME, self.build_id(vdso)));
+            }
+        }
+
+        modules.sort_by_key(|module| module.0);
+        modules
+    }
+}
+
+/// An ELF64 program header.
+#[derive(Debug)]
+struct ProgramHeader {
+    ty: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+impl ProgramHeader {
+    fn parse(data: &[u8], offset: usize) -> Option<Self> {
+        Some(ProgramHeader {
+            ty: read_u32(data, offset)?,
+            offset: read_u64(data, offset + 8)?,
+            vaddr: read_u64(data, offset + 16)?,
+            filesz: read_u64(data, offset + 32)?,
+        })
+    }
+}
+
+/// Returns the program headers of an ELF64 file.
+fn program_headers(data: &[u8]) -> Option<Vec<ProgramHeader>> {
+    let phoff = read_u64(data, 32)? as usize;
+    let phentsize = read_u16(data, 54)? as usize;
+    let phnum = read_u16(data, 56)? as usize;
+
+    (0..phnum)
+        .map(|index| ProgramHeader::parse(data, phoff.checked_add(index * phentsize)?))
+        .collect()
+}
+
+/// An ELF note.
+#[derive(Debug)]
+struct Note<'a> {
+    name: &'a [u8],
+    ty: u32,
+    desc: &'a [u8],
+}
+
+/// Iterates the notes in the contents of an ELF note segment.
+struct Notes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Notes<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for Notes<'a> {
+    type Item = Note<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let namesz = read_u32(self.data, 0)? as usize;
+        let descsz = read_u32(self.data, 4)? as usize;
+        let ty = read_u32(self.data, 8)?;
+
+        let name_end = 12usize.checked_add(namesz)?;
+        let desc_start = align4(name_end);
+        let desc_end = desc_start.checked_add(descsz)?;
+
+        let name = self.data.get(12..name_end)?;
+        let desc = self.data.get(desc_start..desc_end)?;
+        self.data = self.data.get(align4(desc_end)..).unwrap_or_default();
+
+        let name = name.strip_suffix(b"\0").unwrap_or(name);
+        Some(Note { name, ty, desc })
+    }
+}
+
+/// Parses the file mappings from an `NT_FILE` note.
+fn parse_file_note(desc: &[u8]) -> Option<Vec<FileMapping>> {
+    let count = read_u64(desc, 0)? as usize;
+    let page_size = read_u64(desc, 8)?;
+
+    let names_offset = count.checked_mul(24)?.checked_add(16)?;
+    let mut names = desc.get(names_offset..)?.split(|byte| *byte == 0);
+
+    let mappings = (0..count)
+        .map(|index| {
+            let offset = 16 + index * 24;
+            Some(FileMapping {
+                start: read_u64(desc, offset)?,
+                end: read_u64(desc, offset + 8)?,
+                file_offset: read_u64(desc, offset + 16)?.checked_mul(page_size)?,
+                path: String::from_utf8_lossy(names.next()?).into_owned(),
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    // Empty or inverted mappings can only come from a malformed note.
+    Some(
+        mappings
+            .into_iter()
+            .filter(|mapping| mapping.start < mapping.end)
+            .collect(),
+    )
+}
+
+/// Writes the blobs and streams of a minidump.
+struct MinidumpWriter {
+    buf: Vec<u8>,
+    streams: Vec<(u32, u32, u32)>,
+}
+
+impl MinidumpWriter {
+    /// The size of the header, followed by the stream directory.
+    const HEADER_SIZE: usize = 32;
+
+    fn new(stream_count: usize) -> Self {
+        Self {
+            buf: vec![0; Self::HEADER_SIZE + stream_count * 12],
+            streams: Vec::new(),
+        }
+    }
+
+    /// Writes `data` and returns its location as `(size, rva)`.
+    ///
+    /// Fails if the location does not fit into the 32-bit fields of a minidump.
+    fn write(&mut self, data: &[u8]) -> Result<(u32, u32), CoredumpError> {
+        let rva = self.buf.len() + (8 - self.buf.len() % 8) % 8;
+        let location = (u32::try_from(data.len()), u32::try_from(rva));
+        let (size, rva) = match location {
+            (Ok(size), Ok(rva)) => (size, rva),
+            _ => return Err(CoredumpError::TooLarge),
+        };
+
+        self.buf.resize(rva as usize, 0);
+        self.buf.extend_from_slice(data);
+        Ok((size, rva))
+    }
+
+    /// Writes a `MINIDUMP_STRING` and returns its RVA.
+    fn write_string(&mut self, string: &str) -> Result<u32, CoredumpError> {
+        let units: Vec<u16> = string.encode_utf16().collect();
+        let length = u32::try_from(units.len() * 2).map_err(|_| CoredumpError::TooLarge)?;
+        let mut data = Vec::with_capacity(4 + units.len() * 2 + 2);
+        data.extend_from_slice(&length.to_le_bytes());
+        for unit in units.into_iter().chain([0]) {
+            data.extend_from_slice(&unit.to_le_bytes());
+        }
+        Ok(self.write(&data)?.1)
+    }
+
+    /// Writes a stream and adds it to the stream directory.
+    fn write_stream(&mut self, stream_type: u32, data: &[u8]) -> Result<(), CoredumpError> {
+        let (size, rva) = self.write(data)?;
+        self.streams.push((stream_type, size, rva));
+        Ok(())
+    }
+
+    /// Writes the header and stream directory and returns the minidump.
+    fn finish(mut self, timestamp: u32) -> Vec<u8> {
+        let mut header = Vec::with_capacity(Self::HEADER_SIZE);
+        header.extend_from_slice(&MINIDUMP_SIGNATURE.to_le_bytes());
+        header.extend_from_slice(&MINIDUMP_VERSION.to_le_bytes());
+        header.extend_from_slice(&(self.streams.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(Self::HEADER_SIZE as u32).to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes()); // checksum
+        header.extend_from_slice(&timestamp.to_le_bytes());
+        header.extend_from_slice(&0u64.to_le_bytes()); // flags
+
+        for (stream_type, size, rva) in &self.streams {
+            header.extend_from_slice(&stream_type.to_le_bytes());
+            header.extend_from_slice(&size.to_le_bytes());
+            header.extend_from_slice(&rva.to_le_bytes());
+        }
+
+        self.buf[..header.len()].copy_from_slice(&header);
+        self.buf
+    }
+}
+
+/// Converts an ELF core dump into a minidump.
+///
+/// The minidump contains the system info, all threads with their registers and stack memory,
+/// the modules from the `NT_FILE` note and the vDSO, and the crashing signal if it is known.
+/// The first thread in the core is the one which received the signal.
+pub(super) fn coredump_to_minidump(data: &[u8], timestamp: u32) -> Result<Vec<u8>, CoredumpError> {
+    let core = Core::parse(data)?;
+    let mut writer = MinidumpWriter::new(5);
+
+    let mut system_info = vec![0; 56];
+    put_u16(&mut system_info, 0, core.arch.processor_architecture());
+    system_info[6] = 1; // number of processors
+    put_u32(&mut system_info, 20, PLATFORM_LINUX);
+    let csd_version = writer.write_string("")?;
+    put_u32(&mut system_info, 24, csd_version);
+    writer.write_stream(SYSTEM_INFO_STREAM, &system_info)?;
+
+    let mut thread_list = (core.threads.len() as u32).to_le_bytes().to_vec();
+    let mut memory_list = Vec::new();
+    let mut contexts = Vec::with_capacity(core.threads.len());
+    for thread in &core.threads {
+        let context = writer.write(&core.arch.write_context(&thread.registers))?;
+        contexts.push(context);
+
+        let stack_pointer = core.arch.stack_pointer(&thread.registers);
+        let (stack_start, stack) = core.stack_memory(stack_pointer).unwrap_or_default();
+        let stack = writer.write(stack)?;
+        let mut stack_descriptor = stack_start.to_le_bytes().to_vec();
+        stack_descriptor.extend_from_slice(&stack.0.to_le_bytes());
+        stack_descriptor.extend_from_slice(&stack.1.to_le_bytes());
+
+        let mut entry = vec![0; 48];
+        put_u32(&mut entry, 0, thread.tid);
+        entry[24..40].copy_from_slice(&stack_descriptor);
+        put_u32(&mut entry, 40, context.0);
+        put_u32(&mut entry, 44, context.1);
+        thread_list.extend_from_slice(&entry);
+
+        if stack.0 > 0 {
+            memory_list.extend_from_slice(&stack_descriptor);
+        }
+    }
+    writer.write_stream(THREAD_LIST_STREAM, &thread_list)?;
+
+    let modules = core.modules();
+    let mut module_list = (modules.len() as u32).to_le_bytes().to_vec();
+    for (base, size, name, build_id) in modules {
+        let name_rva = writer.write_string(name)?;
+        let cv_record = build_id
+            .map(|build_id| {
+                let mut record = CV_SIGNATURE_ELF.to_le_bytes().to_vec();
+                record.extend_from_slice(build_id);
+                writer.write(&record)
+            })
+            .transpose()?;
+
+        let mut entry = vec![0; 108];
+        put_u64(&mut entry, 0, base);
+        put_u32(&mut entry, 8, size as u32);
+        put_u32(&mut entry, 20, name_rva);
+        if let Some((cv_size, cv_rva)) = cv_record {
+            put_u32(&mut entry, 76, cv_size);
+            put_u32(&mut entry, 80, cv_rva);
+        }
+        module_list.extend_from_slice(&entry);
+    }
+    writer.write_stream(MODULE_LIST_STREAM, &module_list)?;
+
+    let memory_count = (memory_list.len() / 16) as u32;
+    let mut memory_stream = memory_count.to_le_bytes().to_vec();
+    memory_stream.extend_from_slice(&memory_list);
+    writer.write_stream(MEMORY_LIST_STREAM, &memory_stream)?;
+
+    if let Some(signal) = &core.signal {
+        let mut exception = vec![0; 168];
+        put_u32(&mut exception, 0, core.threads[0].tid);
+        put_u32(&mut exception, 8, signal.number);
+        put_u32(&mut exception, 12, signal.code);
+        put_u64(&mut exception, 24, signal.address);
+        put_u32(&mut exception, 160, contexts[0].0);
+        put_u32(&mut exception, 164, contexts[0].1);
+        writer.write_stream(EXCEPTION_STREAM, &exception)?;
+    }
+
+    Ok(writer.finish(timestamp))
+}
+
+/// Converts the core dump at `core_file` into a minidump file in the same directory.
+fn convert_coredump_file(core_file: TempPath) -> anyhow::Result<TempPath> {
+    let core = ByteView::open(&core_file)?;
+    tracing::debug!("Converting core dump ({} bytes)", core.len());
+    metric!(time_raw("coredump.upload.size") = core.len() as u64);
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as u32);
+    let minidump = coredump_to_minidump(&core, timestamp)?;
+
+    let mut minidump_file = tempfile::Builder::new();
+    minidump_file.prefix("minidump").suffix(".dmp");
+    let mut minidump_file = match core_file.parent() {
+        Some(dir) => minidump_file.tempfile_in(dir),
+        None => minidump_file.tempfile(),
+    }?;
+    minidump_file.write_all(&minidump)?;
+
+    Ok(minidump_file.into_temp_path())
+}
+
+impl SymbolicationActor {
+    async fn do_process_coredump(
+        &self,
+        scope: Scope,
+        core_file: TempPath,
+        sources: Arc<[SourceConfig]>,
+        options: RequestOptions,
+    ) -> Result<CompletedSymbolicationResponse, SymbolicationError> {
+        let future = async move { convert_coredump_file(core_file) };
+        let minidump_file =
+            CancelOnDrop::new(self.cpu_pool.spawn(future.bind_hub(sentry::Hub::current())))
+                .await
+                .context("Convert coredump future cancelled")??;
+
+        self.do_process_minidump(scope, minidump_file, sources, options)
+            .await
+    }
+
+    /// Creates a new request to process an ELF core dump.
+    ///
+    /// Returns `None` if the `SymbolicationActor` is already processing the
+    /// maximum number of requests, as given by `max_concurrent_requests`.
+    pub fn process_coredump(
+        &self,
+        scope: Scope,
+        core_file: TempPath,
+        sources: Arc<[SourceConfig]>,
+        options: RequestOptions,
+    ) -> Result<RequestId, MaxRequestsError> {
+        let slf = self.clone();
+        let span = sentry::configure_scope(|scope| scope.get_span());
+        let ctx = sentry::TransactionContext::continue_from_span(
+            "process_coredump",
+            "process_coredump",
+            span,
+        );
+        self.create_symbolication_request(async move {
+            let transaction = sentry::start_transaction(ctx);
+            sentry::configure_scope(|scope| scope.set_span(Some(transaction.clone().into())));
+            let res = slf
+                .do_process_coredump(scope, core_file, sources, options)
+                .await;
+            transaction.finish();
+            res
+        })
+    }
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    let bytes = buf.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> Option<u64> {
+    let bytes = buf.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buf: &mut [u8], offset: usize, value: u64) {
+    buf[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use minidump::{
+        Minidump, MinidumpException, MinidumpMemoryList, MinidumpModuleList, MinidumpThreadList,
+        Module,
+    };
+
+    use super::*;
+
+    const LIB_BASE: u64 = 0x7f00_0000_0000;
+    const STACK_BASE: u64 = 0x7ffe_0000_0000;
+    const BUILD_ID: [u8; 20] = [
+        0xc0, 0xbc, 0xc3, 0xf1, 0x98, 0x27, 0xfe, 0x65, 0x3d, 0x58, 0x0e, 0xf5, 0x99, 0xb7, 0x5f,
+        0x35, 0x3d, 0xe1, 0x2c, 0x72,
+    ];
+
+    fn note(name: &[u8], ty: u32, desc: &[u8]) -> Vec<u8> {
+        let mut note = Vec::new();
+        note.extend_from_slice(&(name.len() as u32 + 1).to_le_bytes());
+        note.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+        note.extend_from_slice(&ty.to_le_bytes());
+        note.extend_from_slice(name);
+        note.push(0);
+        note.resize(align4(note.len()), 0);
+        note.extend_from_slice(desc);
+        note.resize(align4(note.len()), 0);
+        note
+    }
+
+    fn program_header(ty: u32, offset: u64, vaddr: u64, filesz: u64) -> Vec<u8> {
+        let mut header = vec![0; 56];
+        put_u32(&mut header, 0, ty);
+        put_u64(&mut header, 8, offset);
+        put_u64(&mut header, 16, vaddr);
+        put_u64(&mut header, 32, filesz);
+        put_u64(&mut header, 40, filesz);
+        header
+    }
+
+    fn elf_header(ty: u16, phnum: u16) -> Vec<u8> {
+        let mut header = vec![0; 64];
+        header[..4].copy_from_slice(ELF_MAGIC);
+        header[4] = ELFCLASS64;
+        header[5] = ELFDATA2LSB;
+        put_u16(&mut header, 16, ty);
+        put_u16(&mut header, 18, EM_X86_64);
+        put_u64(&mut header, 32, 64);
+        put_u16(&mut header, 54, 56);
+        put_u16(&mut header, 56, phnum);
+        header
+    }
+
+    /// Builds the first page of a shared library with a build id note.
+    fn library_page() -> Vec<u8> {
+        let build_id = note(b"GNU", NT_GNU_BUILD_ID, &BUILD_ID);
+        let notes_offset = 64 + 2 * 56;
+
+        let mut page = elf_header(3, 2);
+        page.extend(program_header(PT_LOAD, 0, 0, 0x1000));
+        page.extend(program_header(
+            PT_NOTE,
+            notes_offset,
+            notes_offset,
+            build_id.len() as u64,
+        ));
+        page.extend(build_id);
+        page.resize(0x1000, 0);
+        page
+    }
+
+    /// Builds an x86_64 core with a single crashed thread and one mapped library.
+    fn core_file() -> Vec<u8> {
+        core_file_with_mapping(LIB_BASE, LIB_BASE + 0x3000)
+    }
+
+    /// Builds the core of [`core_file`] with the library at `start..end` in the `NT_FILE` note.
+    fn core_file_with_mapping(start: u64, end: u64) -> Vec<u8> {
+        let mut prstatus = vec![0; PRSTATUS_REGS + 27 * 8 + 8];
+        put_u32(&mut prstatus, PRSTATUS_PID, 1234);
+        put_u64(&mut prstatus, PRSTATUS_REGS + 16 * 8, LIB_BASE + 0x100); // rip
+        put_u64(&mut prstatus, PRSTATUS_REGS + 19 * 8, STACK_BASE + 0x800); // rsp
+
+        let mut siginfo = vec![0; 128];
+        put_u32(&mut siginfo, 0, 11);
+        put_u32(&mut siginfo, 8, 1);
+        put_u64(&mut siginfo, 16, 0xdead);
+
+        let mut file = Vec::new();
+        for value in [1, 0x1000, start, end, 0] {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+        file.extend_from_slice(b"/usr/lib/libcrash.so\0");
+
+        let mut notes = note(b"CORE", NT_PRSTATUS, &prstatus);
+        notes.extend(note(b"CORE", NT_SIGINFO, &siginfo));
+        notes.extend(note(b"CORE", NT_FILE, &file));
+
+        let headers_end = 64 + 3 * 56;
+        let notes_offset = headers_end as u64;
+        let library_offset = notes_offset + notes.len() as u64;
+        let stack_offset = library_offset + 0x1000;
+
+        let mut core = elf_header(ET_CORE, 3);
+        core.extend(program_header(PT_NOTE, notes_offset, 0, notes.len() as u64));
+        core.extend(program_header(PT_LOAD, library_offset, LIB_BASE, 0x1000));
+        core.extend(program_header(PT_LOAD, stack_offset, STACK_BASE, 0x1000));
+        core.extend(notes);
+        core.extend(library_page());
+        core.extend((0..0x1000).map(|index| index as u8));
+        core
+    }
+
+    #[test]
+    fn test_coredump_to_minidump() {
+        let minidump = coredump_to_minidump(&core_file(), 0).unwrap();
+        let minidump = Minidump::read(minidump.as_slice()).unwrap();
+
+        let threads = minidump.get_stream::<MinidumpThreadList>().unwrap();
+        assert_eq!(threads.threads.len(), 1);
+        let thread = &threads.threads[0];
+        assert_eq!(thread.raw.thread_id, 1234);
+        let context = thread.context.as_ref().unwrap();
+        assert_eq!(context.get_instruction_pointer(), LIB_BASE + 0x100);
+        assert_eq!(context.get_stack_pointer(), STACK_BASE + 0x800);
+
+        let stack = thread.stack.as_ref().unwrap();
+        assert_eq!(stack.base_address, STACK_BASE + 0x800 - STACK_RED_ZONE);
+        assert_eq!(stack.bytes.len() as u64, 0x800 + STACK_RED_ZONE);
+        let memory = minidump.get_stream::<MinidumpMemoryList>().unwrap();
+        assert!(memory.memory_at_address(STACK_BASE + 0x900).is_some());
+
+        let modules = minidump.get_stream::<MinidumpModuleList>().unwrap();
+        let module = modules.iter().next().unwrap();
+        assert_eq!(module.code_file(), "/usr/lib/libcrash.so");
+        assert_eq!(module.base_address(), LIB_BASE);
+        assert_eq!(module.size(), 0x3000);
+        assert_eq!(
+            module.debug_identifier().unwrap().to_string(),
+            "f1c3bcc0-2798-65fe-3d58-0ef599b75f35"
+        );
+        assert_eq!(
+            module.code_identifier().unwrap().to_string().to_lowercase(),
+            "c0bcc3f19827fe653d580ef599b75f353de12c72"
+        );
+
+        let exception = minidump.get_stream::<MinidumpException>().unwrap();
+        assert_eq!(exception.raw.thread_id, 1234);
+        assert_eq!(exception.raw.exception_record.exception_code, 11);
+        assert_eq!(exception.raw.exception_record.exception_address, 0xdead);
+    }
+
+    #[test]
+    fn test_coredump_invalid() {
+        assert!(matches!(
+            coredump_to_minidump(b"MDMP", 0),
+            Err(CoredumpError::NotCore)
+        ));
+
+        let header = elf_header(ET_CORE, 0);
+        assert!(matches!(
+            coredump_to_minidump(&header, 0),
+            Err(CoredumpError::NoThreads)
+        ));
+
+        // Mappings ending before they start are dropped.
+        let core = core_file_with_mapping(LIB_BASE + 0x3000, LIB_BASE);
+        let minidump = coredump_to_minidump(&core, 0).unwrap();
+        let minidump = Minidump::read(minidump.as_slice()).unwrap();
+        let modules = minidump.get_stream::<MinidumpModuleList>().unwrap();
+        assert_eq!(modules.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_writer_locations() {
+        let mut writer = MinidumpWriter::new(0);
+        assert_eq!(writer.write(b"abc").unwrap(), (3, 32));
+        assert_eq!(writer.write(b"de").unwrap(), (2, 40));
+        assert_eq!(writer.write_string("x").unwrap(), 48);
+        assert_eq!(writer.buf.len(), 48 + 4 + 4);
+    }
+}
@@ -0,0 +1,126 @@
+//! Classification of symbolicated frames into in-app and system frames.
+//!
+//! Frames are only classified if the request provides [`InAppRules`].
+
+use crate::types::{Glob, InAppRules, SymbolicatedFrame};
+
+use super::is_likely_base_frame;
+
+const GLOB_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// Annotates `frame` with whether it is in-app according to `rules`, and whether it is a
+/// well-known system frame, such as a thread entry point.
+pub fn classify_frame(frame: &mut SymbolicatedFrame, rules: &InAppRules) {
+    frame.in_app = Some(matches_rules(frame, rules));
+    frame.system = is_likely_base_frame(frame);
+}
+
+fn matches_glob(patterns: &[Glob], path: Option<&str>) -> bool {
+    path.map_or(false, |path| {
+        let path = path.replace('\\', "/");
+        patterns
+            .iter()
+            .any(|pattern| pattern.matches_with(&path, GLOB_OPTIONS))
+    })
+}
+
+fn matches_rules(frame: &SymbolicatedFrame, rules: &InAppRules) -> bool {
+    let package = frame.raw.package.as_deref();
+    if matches_glob(&rules.packages, package)
+        || matches_glob(&rules.paths, frame.raw.abs_path.as_deref())
+    {
+        return true;
+    }
+
+    if let Some(package) = package {
+        let module = package.rsplit(&['/', '\\'][..]).next().unwrap_or(package);
+        if rules
+            .modules
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(module))
+        {
+            return true;
+        }
+    }
+
+    let function = frame
+        .raw
+        .function
+        .as_deref()
+        .or(frame.raw.symbol.as_deref());
+    function.map_or(false, |function| {
+        rules
+            .function_prefixes
+            .iter()
+            .any(|prefix| function.starts_with(prefix.as_str()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::RawFrame;
+
+    fn rules() -> InAppRules {
+        serde_json::from_str(
+            r#"{
+                "packages": ["/Applications/MyApp.app/**"],
+                "paths": ["**/src/myapp/**"],
+                "modules": ["libmyapp.so"],
+                "function_prefixes": ["myapp::"]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn classify(package: &str, abs_path: Option<&str>, function: &str) -> (Option<bool>, bool) {
+        let mut frame = SymbolicatedFrame {
+            raw: RawFrame {
+                package: Some(package.to_owned()),
+                abs_path: abs_path.map(str::to_owned),
+                function: Some(function.to_owned()),
+                ..RawFrame::default()
+            },
+            ..SymbolicatedFrame::default()
+        };
+        classify_frame(&mut frame, &rules());
+        (frame.in_app, frame.system)
+    }
+
+    #[test]
+    fn test_classify_frame() {
+        let in_app = (Some(true), false);
+        let not_in_app = (Some(false), false);
+
+        assert_eq!(
+            classify("/Applications/MyApp.app/Contents/MacOS/MyApp", None, "foo"),
+            in_app
+        );
+        assert_eq!(
+            classify("/usr/lib/libc.so.6", Some("/build/src/myapp/main.c"), "foo"),
+            in_app
+        );
+        assert_eq!(
+            classify("C:\\Program Files\\LIBMYAPP.SO", None, "foo"),
+            in_app
+        );
+        assert_eq!(classify("/usr/lib/libc.so.6", None, "myapp::run"), in_app);
+        assert_eq!(classify("/usr/lib/libc.so.6", None, "memcpy"), not_in_app);
+    }
+
+    #[test]
+    fn test_classify_system_frame() {
+        assert_eq!(
+            classify("/usr/lib/libc.so.6", None, "start_thread"),
+            (Some(false), true)
+        );
+        assert_eq!(
+            classify("/Applications/MyApp.app/Contents/MacOS/MyApp", None, "main"),
+            (Some(true), true)
+        );
+    }
+}
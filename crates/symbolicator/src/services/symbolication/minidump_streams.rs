@@ -0,0 +1,308 @@
+//! Extraction of additional minidump streams for the symbolication response.
+//!
+//! Most of these streams are not needed for stackwalking.  Streams parsed by rust-minidump are
+//! converted from its types, the remaining ones are read directly from the raw minidump data,
+//! where all multi-byte values are little-endian.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryInto;
+
+use minidump::format as md;
+use minidump::{
+    LinuxOsStr, MinidumpException, MinidumpLinuxEnviron, MinidumpLinuxLsbRelease,
+    MinidumpMemoryInfoList, MinidumpModuleList, MinidumpUnloadedModuleList, Module,
+};
+
+use crate::types::{
+    ExceptionRecord, HandleDescriptor, LinuxProcStreams, MemoryRegion, MinidumpSection,
+    MinidumpStreams, ObjectType, UnloadedModule,
+};
+use crate::utils::hex::HexValue;
+
+use super::process_minidump::object_info_from_minidump_module;
+
+const HANDLE_DATA_STREAM: u32 = 12;
+const LINUX_CMD_LINE: u32 = 0x4767_0006;
+const LINUX_MAPS: u32 = 0x4767_0009;
+
+/// Extracts the requested `sections` from a minidump.
+///
+/// Returns `None` if no sections were requested.  Sections which are missing from the
+/// minidump or fail to parse are left empty.
+pub(super) fn extract_minidump_streams(
+    buf: &[u8],
+    object_type: ObjectType,
+    sections: &BTreeSet<MinidumpSection>,
+) -> Option<MinidumpStreams> {
+    if sections.is_empty() {
+        return None;
+    }
+
+    let dump = match minidump::Minidump::read(buf) {
+        Ok(dump) => dump,
+        Err(err) => {
+            let stderr: &dyn std::error::Error = &err;
+            tracing::warn!(stderr, "Failed to read minidump streams");
+            return Some(MinidumpStreams::default());
+        }
+    };
+    let raw_stream = |stream_type: u32| dump.get_raw_stream(stream_type).ok();
+
+    let mut streams = MinidumpStreams::default();
+    for section in sections {
+        match section {
+            MinidumpSection::Exception => {
+                streams.exception = dump
+                    .get_stream::<MinidumpException>()
+                    .ok()
+                    .map(|exception| exception_record(&exception.raw));
+            }
+            MinidumpSection::MemoryInfo => {
+                streams.memory_info = dump
+                    .get_stream::<MinidumpMemoryInfoList>()
+                    .ok()
+                    .map(|list| list.iter().map(|info| memory_region(&info.raw)).collect());
+            }
+            MinidumpSection::Modules => {
+                streams.modules = dump.get_stream::<MinidumpModuleList>().ok().map(|list| {
+                    list.iter()
+                        .map(|module| object_info_from_minidump_module(object_type, module))
+                        .collect()
+                });
+            }
+            MinidumpSection::UnloadedModules => {
+                streams.unloaded_modules = dump
+                    .get_stream::<MinidumpUnloadedModuleList>()
+                    .ok()
+                    .map(|list| unloaded_modules(&list));
+            }
+            MinidumpSection::Handles => {
+                streams.handles =
+                    raw_stream(HANDLE_DATA_STREAM).and_then(|stream| parse_handles(buf, stream));
+            }
+            MinidumpSection::LinuxProc => {
+                let proc = LinuxProcStreams {
+                    cmdline: raw_stream(LINUX_CMD_LINE).map(parse_cmdline),
+                    environ: dump
+                        .get_stream::<MinidumpLinuxEnviron>()
+                        .ok()
+                        .map(|environ| string_map(environ.iter())),
+                    maps: raw_stream(LINUX_MAPS).map(parse_maps),
+                    lsb_release: dump
+                        .get_stream::<MinidumpLinuxLsbRelease>()
+                        .ok()
+                        .map(|lsb_release| string_map(lsb_release.iter())),
+                };
+                streams.linux_proc = Some(proc);
+            }
+        }
+    }
+
+    Some(streams)
+}
+
+/// Reads the list of modules which were unloaded before the minidump was written.
+///
+/// Returns an empty list if the minidump has no unloaded module list.
+pub(super) fn read_unloaded_modules(buf: &[u8]) -> Vec<UnloadedModule> {
+    minidump::Minidump::read(buf)
+        .ok()
+        .and_then(|dump| dump.get_stream::<MinidumpUnloadedModuleList>().ok())
+        .map(|list| unloaded_modules(&list))
+        .unwrap_or_default()
+}
+
+/// Converts a `MINIDUMP_EXCEPTION_STREAM`.
+fn exception_record(stream: &md::MINIDUMP_EXCEPTION_STREAM) -> ExceptionRecord {
+    let record = &stream.exception_record;
+    let parameters = record
+        .exception_information
+        .iter()
+        .take(record.number_parameters as usize)
+        .map(|&parameter| HexValue(parameter))
+        .collect();
+
+    ExceptionRecord {
+        thread_id: stream.thread_id,
+        code: HexValue(record.exception_code.into()),
+        flags: HexValue(record.exception_flags.into()),
+        address: HexValue(record.exception_address),
+        parameters,
+    }
+}
+
+/// Converts a `MINIDUMP_MEMORY_INFO`.
+fn memory_region(info: &md::MINIDUMP_MEMORY_INFO) -> MemoryRegion {
+    MemoryRegion {
+        base_address: HexValue(info.base_address),
+        allocation_base: HexValue(info.allocation_base),
+        allocation_protection: HexValue(info.allocation_protection.into()),
+        region_size: info.region_size,
+        state: HexValue(info.state.into()),
+        protection: HexValue(info.protection.into()),
+        ty: HexValue(info._type.into()),
+    }
+}
+
+fn unloaded_modules(list: &MinidumpUnloadedModuleList) -> Vec<UnloadedModule> {
+    list.iter()
+        .map(|module| UnloadedModule {
+            code_file: module.code_file().into_owned(),
+            image_addr: HexValue(module.raw.base_of_image),
+            image_size: module.raw.size_of_image.into(),
+            checksum: HexValue(module.raw.checksum.into()),
+            timestamp: module.raw.time_date_stamp,
+        })
+        .collect()
+}
+
+/// Collects the key-value pairs of a Linux `/proc` or `/etc` file.
+///
+/// Surrounding quotes of values, as used in `/etc/lsb-release`, are removed.
+fn string_map<'a>(
+    entries: impl Iterator<Item = (&'a LinuxOsStr, &'a LinuxOsStr)>,
+) -> BTreeMap<String, String> {
+    entries
+        .map(|(key, value)| {
+            let key = String::from_utf8_lossy(key.as_bytes());
+            let value = String::from_utf8_lossy(value.as_bytes());
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            (key.trim().to_owned(), value.to_owned())
+        })
+        .collect()
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> Option<u64> {
+    let bytes = buf.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Reads a `MINIDUMP_STRING` at the given RVA of the minidump.
+///
+/// An RVA of `0` denotes a missing string.
+fn read_string(buf: &[u8], rva: u32) -> Option<String> {
+    if rva == 0 {
+        return None;
+    }
+
+    let offset = rva as usize;
+    let len = read_u32(buf, offset)? as usize;
+    let start = offset.checked_add(4)?;
+    let bytes = buf.get(start..start.checked_add(len)?)?;
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect();
+
+    Some(String::from_utf16_lossy(&units))
+}
+
+/// Iterates the entries of a list stream with a `SizeOfHeader` and `SizeOfEntry` header.
+///
+/// Entries smaller than `min_entry_size` are rejected.
+fn list_entries(
+    stream: &[u8],
+    count: usize,
+    min_entry_size: usize,
+) -> Option<impl Iterator<Item = &[u8]>> {
+    let header_size = read_u32(stream, 0)? as usize;
+    let entry_size = read_u32(stream, 4)? as usize;
+    if entry_size < min_entry_size {
+        return None;
+    }
+
+    let entries = stream.get(header_size..)?;
+    Some(entries.chunks_exact(entry_size).take(count))
+}
+
+/// Parses a `MINIDUMP_HANDLE_DATA_STREAM`.
+///
+/// Type and object names are stored as RVAs, so this needs the entire minidump in `buf`.
+fn parse_handles(buf: &[u8], stream: &[u8]) -> Option<Vec<HandleDescriptor>> {
+    let count = read_u32(stream, 8)? as usize;
+    let entries = list_entries(stream, count, 32)?;
+
+    entries
+        .map(|entry| {
+            Some(HandleDescriptor {
+                handle: HexValue(read_u64(entry, 0)?),
+                type_name: read_string(buf, read_u32(entry, 8)?),
+                object_name: read_string(buf, read_u32(entry, 12)?),
+                attributes: HexValue(read_u32(entry, 16)?.into()),
+                granted_access: HexValue(read_u32(entry, 20)?.into()),
+                handle_count: read_u32(entry, 24)?,
+                pointer_count: read_u32(entry, 28)?,
+            })
+        })
+        .collect()
+}
+
+/// Splits a `\0`-separated list of strings, such as `/proc/self/cmdline`.
+fn split_nul(stream: &[u8]) -> impl Iterator<Item = String> + '_ {
+    stream
+        .split(|b| *b == 0)
+        .filter(|s| !s.is_empty())
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+}
+
+fn parse_cmdline(stream: &[u8]) -> Vec<String> {
+    split_nul(stream).collect()
+}
+
+fn parse_maps(stream: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(stream)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test;
+
+    fn read_streams(fixture: &str, section: MinidumpSection) -> MinidumpStreams {
+        let buf = test::read_fixture(fixture);
+        let sections = BTreeSet::from([section]);
+        extract_minidump_streams(&buf, ObjectType::Unknown, &sections).unwrap()
+    }
+
+    #[test]
+    fn test_exception_linux() {
+        let streams = read_streams("linux.dmp", MinidumpSection::Exception);
+        let exception = streams.exception.unwrap();
+        assert_eq!(exception.code, HexValue(11)); // SIGSEGV
+        assert_eq!(exception.address, HexValue(0x45));
+    }
+
+    #[test]
+    fn test_exception_windows() {
+        let streams = read_streams("windows.dmp", MinidumpSection::Exception);
+        let exception = streams.exception.unwrap();
+        assert_eq!(exception.code, HexValue(0xc000_0005)); // EXCEPTION_ACCESS_VIOLATION
+        assert_eq!(exception.parameters.get(1), Some(&HexValue(0x45)));
+    }
+
+    #[test]
+    fn test_parse_linux_proc() {
+        assert_eq!(
+            parse_cmdline(b"/bin/app\0--flag\0"),
+            vec!["/bin/app", "--flag"]
+        );
+        assert_eq!(
+            parse_maps(b"00400000-00452000 r-xp 00000000 08:02 173521 /bin/app\n\n"),
+            vec!["00400000-00452000 r-xp 00000000 08:02 173521 /bin/app"]
+        );
+    }
+}
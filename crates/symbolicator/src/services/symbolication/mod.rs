@@ -18,26 +18,33 @@ use symbolic::common::{Arch, CodeId, DebugId, InstructionInfo, Language, Name};
 use symbolic::demangle::{Demangle, DemangleOptions};
 use thiserror::Error;
 
+use crate::config::{MinidumpSanitizerConfig, StackwalkConfig};
 use crate::services::cficaches::{CfiCacheActor, CfiCacheError};
 use crate::services::objects::ObjectsActor;
+use crate::services::ppdb::PortablePdbService;
+use crate::services::sourcemaps::SourceMapService;
 use crate::services::symcaches::{SymCacheActor, SymCacheError};
 use crate::sources::SourceConfig;
 use crate::types::{
-    CompleteObjectInfo, CompleteStacktrace, CompletedSymbolicationResponse, FrameStatus,
-    FrameTrust, ObjectFileStatus, ObjectId, ObjectType, RawFrame, RawObjectInfo, RawStacktrace,
-    Registers, RequestId, RequestOptions, Scope, Signal, SymbolicatedFrame, SymbolicationResponse,
-    SystemInfo,
+    CompleteObjectInfo, CompleteStacktrace, CompletedSymbolicationResponse, DemangleStyle,
+    DemanglingOptions, FrameStatus, FrameTrust, InlineFrames, MissingModule, ObjectFileStatus,
+    ObjectId, ObjectType, PointerSymbol, QualityReport, RawFrame, RawObjectInfo, RawStacktrace,
+    Registers, RequestId, RequestOptions, Scope, Signal, StacktraceQuality, SymbolicatedFrame,
+    SymbolicationResponse, SystemInfo,
 };
+use crate::utils::addr::AddrMode;
 use crate::utils::futures::{m, measure, CallOnDrop, CancelOnDrop};
 use crate::utils::hex::HexValue;
 
+mod coredump;
+mod in_app;
+mod minidump_streams;
 mod module_lookup;
 mod process_minidump;
+mod sandbox;
 
-use module_lookup::ModuleLookup;
-
-/// Options for demangling all symbols.
-const DEMANGLE_OPTIONS: DemangleOptions = DemangleOptions::complete().return_type(false);
+use module_lookup::{get_context_lines, ModuleLookup};
+pub use sandbox::{run_stackwalk_worker, StackwalkPool};
 
 /// The maximum delay we allow for polling a finished request before dropping it.
 const MAX_POLL_DELAY: Duration = Duration::from_secs(90);
@@ -89,6 +96,8 @@ pub struct SymbolicationActor {
     objects: ObjectsActor,
     symcaches: SymCacheActor,
     cficaches: CfiCacheActor,
+    ppdbs: PortablePdbService,
+    sourcemaps: SourceMapService,
     diagnostics_cache: crate::cache::Cache,
     io_pool: tokio::runtime::Handle,
     cpu_pool: tokio::runtime::Handle,
@@ -96,6 +105,9 @@ pub struct SymbolicationActor {
     max_concurrent_requests: Option<usize>,
     current_requests: Arc<AtomicUsize>,
     symbolication_taskmon: tokio_metrics::TaskMonitor,
+    stackwalk_config: StackwalkConfig,
+    stackwalk_pool: Option<StackwalkPool>,
+    sanitizer_config: Arc<MinidumpSanitizerConfig>,
 }
 
 impl fmt::Debug for SymbolicationActor {
@@ -104,6 +116,8 @@ impl fmt::Debug for SymbolicationActor {
             .field("objects", &self.objects)
             .field("symcaches", &self.symcaches)
             .field("cficaches", &self.cficaches)
+            .field("ppdbs", &self.ppdbs)
+            .field("sourcemaps", &self.sourcemaps)
             .field("diagnostics_cache", &self.diagnostics_cache)
             .field("io_pool", &self.io_pool)
             .field("cpu_pool", &self.cpu_pool)
@@ -111,6 +125,9 @@ impl fmt::Debug for SymbolicationActor {
             .field("max_concurrent_requests", &self.max_concurrent_requests)
             .field("current_requests", &self.current_requests)
             .field("symbolication_taskmon", &"<TaskMonitor>")
+            .field("stackwalk_config", &self.stackwalk_config)
+            .field("stackwalk_pool", &self.stackwalk_pool)
+            .field("sanitizer_config", &self.sanitizer_config)
             .finish()
     }
 }
@@ -121,15 +138,22 @@ impl SymbolicationActor {
         objects: ObjectsActor,
         symcaches: SymCacheActor,
         cficaches: CfiCacheActor,
+        ppdbs: PortablePdbService,
+        sourcemaps: SourceMapService,
         diagnostics_cache: crate::cache::Cache,
         io_pool: tokio::runtime::Handle,
         cpu_pool: tokio::runtime::Handle,
         max_concurrent_requests: Option<usize>,
+        stackwalk_config: StackwalkConfig,
+        stackwalk_pool: Option<StackwalkPool>,
+        sanitizer_config: MinidumpSanitizerConfig,
     ) -> Self {
         SymbolicationActor {
             objects,
             symcaches,
             cficaches,
+            ppdbs,
+            sourcemaps,
             diagnostics_cache,
             io_pool,
             cpu_pool,
@@ -137,6 +161,9 @@ impl SymbolicationActor {
             max_concurrent_requests,
             current_requests: Arc::new(AtomicUsize::new(0)),
             symbolication_taskmon: tokio_metrics::TaskMonitor::new(),
+            stackwalk_config,
+            stackwalk_pool,
+            sanitizer_config: Arc::new(sanitizer_config),
         }
     }
 
@@ -279,35 +306,182 @@ fn object_id_from_object_info(object_info: &RawObjectInfo) -> ObjectId {
     }
 }
 
+/// Demangles `name` according to the request's [`DemanglingOptions`].
+///
+/// Returns `None` if demangling is disabled or the name could not be demangled.
+fn demangle_name(name: &Name<'_>, options: &DemanglingOptions) -> Option<String> {
+    let demangle_options = match options.style {
+        DemangleStyle::None => return None,
+        DemangleStyle::Short => DemangleOptions::name_only(),
+        DemangleStyle::Full => DemangleOptions::complete().return_type(options.return_type),
+    };
+
+    // symbolic always strips the hash from Rust names, so bypass it to keep the hash.
+    if !options.strip_rust_hash && Name::from(name.as_str()).detect_language() == Language::Rust {
+        if let Ok(demangled) = rustc_demangle::try_demangle(name.as_str()) {
+            return Some(demangled.to_string());
+        }
+    }
+
+    name.demangle(demangle_options)
+}
+
+/// Symbolicates a managed frame, which is addressed by a method token and an IL offset.
+fn symbolicate_il_frame(
+    caches: &ModuleLookup,
+    module_index: usize,
+    frame: &mut RawFrame,
+    index: usize,
+) -> Result<Vec<SymbolicatedFrame>, FrameStatus> {
+    let (object_info, ppdb) = caches
+        .lookup_portable_pdb(module_index)
+        .ok_or(FrameStatus::UnknownImage)?;
+
+    frame.package = object_info.raw.code_file.clone();
+
+    let ppdb = ppdb.ok_or(FrameStatus::Missing)?;
+    let method_token = frame
+        .function_id
+        .and_then(|id| u32::try_from(id.0).ok())
+        .ok_or(FrameStatus::MissingSymbol)?;
+    let il_offset =
+        u32::try_from(frame.instruction_addr.0).map_err(|_| FrameStatus::MissingSymbol)?;
+
+    let line_info = match ppdb.lookup(method_token, il_offset) {
+        Ok(Some(line_info)) => line_info,
+        Ok(None) => return Err(FrameStatus::MissingSymbol),
+        Err(_) => return Err(FrameStatus::Malformed),
+    };
+
+    let filename = line_info
+        .path
+        .rsplit(&['/', '\\'][..])
+        .next()
+        .unwrap_or(&line_info.path)
+        .to_owned();
+
+    Ok(vec![SymbolicatedFrame {
+        status: FrameStatus::Symbolicated,
+        original_index: Some(index),
+        raw: RawFrame {
+            filename: Some(filename),
+            abs_path: Some(line_info.path),
+            lineno: Some(line_info.line),
+            ..frame.clone()
+        },
+        ..SymbolicatedFrame::default()
+    }])
+}
+
+/// Symbolicates a JavaScript frame, which is addressed by a line and column in a minified script.
+fn symbolicate_js_frame(
+    caches: &ModuleLookup,
+    module_index: usize,
+    frame: &mut RawFrame,
+    index: usize,
+) -> Result<Vec<SymbolicatedFrame>, FrameStatus> {
+    let (object_info, source_map) = caches
+        .lookup_source_map(module_index)
+        .ok_or(FrameStatus::UnknownImage)?;
+
+    frame.package = object_info.raw.code_file.clone();
+
+    let source_map = source_map.ok_or(FrameStatus::Missing)?;
+
+    // Frames count lines and columns starting at `1`, source maps start at `0`.
+    let (line, column) = match (frame.lineno, frame.colno) {
+        (Some(line), Some(column)) if line > 0 && column > 0 => (line - 1, column - 1),
+        _ => return Err(FrameStatus::MissingSymbol),
+    };
+    let location = source_map
+        .lookup(line, column)
+        .ok_or(FrameStatus::MissingSymbol)?;
+
+    let lineno = location.line + 1;
+    let (pre_context, context_line, post_context) = match location
+        .contents
+        .and_then(|contents| get_context_lines(contents, lineno, 5))
+    {
+        Some((pre_context, context_line, post_context)) => {
+            (pre_context, Some(context_line), post_context)
+        }
+        None => (vec![], None, vec![]),
+    };
+
+    let filename = location
+        .source
+        .rsplit('/')
+        .next()
+        .unwrap_or(location.source)
+        .to_owned();
+
+    Ok(vec![SymbolicatedFrame {
+        status: FrameStatus::Symbolicated,
+        original_index: Some(index),
+        raw: RawFrame {
+            function: location
+                .name
+                .map(str::to_owned)
+                .or_else(|| frame.function.clone()),
+            filename: Some(filename),
+            abs_path: Some(location.source.to_owned()),
+            lineno: Some(lineno),
+            colno: Some(location.column + 1),
+            pre_context,
+            context_line,
+            post_context,
+            ..frame.clone()
+        },
+        ..SymbolicatedFrame::default()
+    }])
+}
+
 fn symbolicate_frame(
     caches: &ModuleLookup,
     registers: &Registers,
     signal: Option<Signal>,
+    demangling: &DemanglingOptions,
     frame: &mut RawFrame,
     index: usize,
 ) -> Result<Vec<SymbolicatedFrame>, FrameStatus> {
+    match frame.addr_mode {
+        AddrMode::Il(module_index) => {
+            return symbolicate_il_frame(caches, module_index, frame, index)
+        }
+        AddrMode::Js(module_index) => {
+            return symbolicate_js_frame(caches, module_index, frame, index)
+        }
+        AddrMode::Abs | AddrMode::Rel(_) => {}
+    }
+
     let lookup_result = caches
         .lookup_symcache(frame.instruction_addr.0, frame.addr_mode)
         .ok_or(FrameStatus::UnknownImage)?;
 
     frame.package = lookup_result.object_info.raw.code_file.clone();
+
+    // Frames in unloaded modules are reported distinctly if we cannot find debug info for them,
+    // they likely point to stale code or are false positives from stack scanning.
+    let missing_status = match lookup_result.object_info.raw.unloaded {
+        true => FrameStatus::UnloadedImage,
+        false => FrameStatus::Missing,
+    };
+
     if lookup_result.symcache.is_none() {
         if lookup_result.object_info.debug_status == ObjectFileStatus::Malformed {
             return Err(FrameStatus::Malformed);
         } else {
-            return Err(FrameStatus::Missing);
+            return Err(missing_status);
         }
     }
 
     tracing::trace!("Loading symcache");
-    let symcache = match lookup_result
+    let symcache_file = lookup_result
         .symcache
-        .as_ref()
-        .expect("symcache should always be available at this point")
-        .parse()
-    {
+        .expect("symcache should always be available at this point");
+    let symcache = match symcache_file.parse() {
         Ok(Some(x)) => x,
-        Ok(None) => return Err(FrameStatus::Missing),
+        Ok(None) => return Err(missing_status),
         Err(_) => return Err(FrameStatus::Malformed),
     };
 
@@ -360,6 +534,9 @@ fn symbolicate_frame(
 
     let mut rv = vec![];
 
+    // Without debug info, the symcache was built from the symbol table of the code file.
+    let symbol_table_only = !symcache_file.features().has_debug_info;
+
     // The symbol addr only makes sense for the outermost top-level function, and not its inlinees.
     // We keep track of it while iterating and only set it for the last frame,
     // which is the top-level function.
@@ -403,8 +580,8 @@ fn symbolicate_frame(
             (lang, detected) => lang == detected, // avoid false-positive detections
         };
 
-        let demangled_opt = name.demangle(DEMANGLE_OPTIONS);
-        if should_demangle && demangled_opt.is_none() {
+        let demangled_opt = demangle_name(&name, demangling);
+        if should_demangle && demangling.style != DemangleStyle::None && demangled_opt.is_none() {
             sentry::with_scope(
                 |scope| scope.set_extra("identifier", name.to_string().into()),
                 || {
@@ -420,10 +597,16 @@ fn symbolicate_frame(
         rv.push(SymbolicatedFrame {
             status: FrameStatus::Symbolicated,
             original_index: Some(index),
+            symbol_table_only,
+            in_app: None,
+            system: false,
+            inlined: true,
+            inlined_frames: vec![],
             raw: RawFrame {
                 package: lookup_result.object_info.raw.code_file.clone(),
                 addr_mode: lookup_result.preferred_addr_mode(),
                 instruction_addr,
+                function_id: frame.function_id,
                 symbol: Some(line_info.symbol().to_string()),
                 abs_path: if !abs_path.is_empty() {
                     Some(abs_path)
@@ -440,6 +623,7 @@ fn symbolicate_frame(
                     frame.filename.clone()
                 },
                 lineno: Some(line_info.line()),
+                colno: None,
                 pre_context: vec![],
                 context_line: None,
                 post_context: vec![],
@@ -449,12 +633,16 @@ fn symbolicate_frame(
                     language => Some(language),
                 },
                 trust: frame.trust,
+                registers: Registers::new(),
             },
         });
     }
 
+    // The registers belong to the physical frame, not any of the inlined frames.
     if let Some(last_frame) = rv.last_mut() {
+        last_frame.inlined = false;
         last_frame.raw.sym_addr = sym_addr;
+        last_frame.raw.registers = std::mem::take(&mut frame.registers);
     }
 
     if rv.is_empty() {
@@ -464,59 +652,84 @@ fn symbolicate_frame(
     Ok(rv)
 }
 
-/// Stacktrace related Metrics
-///
-/// This gives some metrics about the quality of the stack traces included
-/// in a symbolication request. See the individual members for more information.
+/// Returns the frames symbolicated from a single instruction address according to the
+/// [`InlineFrames`] mode of the request.
+fn apply_inline_frames(
+    frames: Vec<SymbolicatedFrame>,
+    mode: InlineFrames,
+) -> Vec<SymbolicatedFrame> {
+    match mode {
+        InlineFrames::Expand => frames,
+        InlineFrames::Collapse => {
+            let (inlined_frames, mut frames): (Vec<_>, Vec<_>) =
+                frames.into_iter().partition(|frame| frame.inlined);
+            if let Some(frame) = frames.first_mut() {
+                frame.inlined_frames = inlined_frames;
+            }
+            frames
+        }
+        InlineFrames::Drop => frames.into_iter().filter(|frame| !frame.inlined).collect(),
+    }
+}
+
+/// Resolves the symbol that a pointer, such as a register value, points into.
 ///
-/// These numbers are being accumulated across one symbolication request, and are emitted
-/// as a histogram.
-#[derive(Default)]
-struct StacktraceMetrics {
-    /// A truncated stack trace is one that does not end in a
-    /// well known thread base.
-    truncated_traces: u64,
-
-    /// We classify a short stacktrace as one that has less that 5 frames.
-    short_traces: u64,
-
-    /// This indicated a stack trace that has at least one bad frame
-    /// from the below categories.
-    bad_traces: u64,
-
-    /// Frames that were scanned.
-    ///
-    /// These are frequently wrong and lead to bad and incomplete stack traces.
-    /// We can improve (lower) these numbers by having more usable CFI info.
-    scanned_frames: u64,
+/// Returns `None` if `addr` does not fall into any module.  Unlike instruction addresses of
+/// frames, the address is looked up as-is, without any caller address heuristics.  The symbol is
+/// only attached if `addr` lies within the function's range, otherwise just the module and the
+/// offset into it are returned.
+fn symbolicate_pointer(
+    caches: &ModuleLookup,
+    addr: u64,
+    demangling: &DemanglingOptions,
+) -> Option<PointerSymbol> {
+    let lookup_result = caches.lookup_pointer(addr)?;
+    let relative_addr = lookup_result.relative_addr?;
+
+    let mut pointer = PointerSymbol {
+        addr: HexValue(addr),
+        package: lookup_result.object_info.raw.code_file.clone(),
+        offset: HexValue(relative_addr),
+        ..PointerSymbol::default()
+    };
 
-    /// Unsymbolicated Frames.
-    ///
-    /// These may be the result of unavailable or broken debug info.
-    /// We can improve (lower) these numbers by having more usable debug info.
-    unsymbolicated_frames: u64,
+    let symcache = match lookup_result.symcache.map(|file| file.parse()) {
+        Some(Ok(Some(symcache))) => symcache,
+        _ => return Some(pointer),
+    };
 
-    /// Unsymbolicated Context Frames.
-    ///
-    /// This is an indication of broken contexts, or failure to extract it from minidumps.
-    unsymbolicated_context_frames: u64,
+    // The last line info is the outermost function, which owns the symbol.
+    let line_info = symcache
+        .lookup(relative_addr)
+        .ok()
+        .and_then(|line_infos| line_infos.filter_map(Result::ok).last());
+
+    if let Some(line_info) = line_info {
+        // Functions from symbol tables have no line info and extend up to the next symbol, so the
+        // last one also covers any data following it, such as vtables.  Symbol maps, as well as
+        // line info from debug info, have exact ranges.
+        let is_last_function = lookup_result
+            .last_function_addr
+            .map_or(true, |last_addr| last_addr <= relative_addr);
+        if line_info.line() == 0 && !lookup_result.synthetic && is_last_function {
+            return Some(pointer);
+        }
 
-    /// Unsymbolicated Frames found by scanning.
-    unsymbolicated_scanned_frames: u64,
+        let name = line_info.function_name();
+        let function_address = line_info.function_address();
 
-    /// Unsymbolicated Frames found by CFI.
-    ///
-    /// These are the result of the *previous* frame being wrongly scanned.
-    unsymbolicated_cfi_frames: u64,
+        pointer.symbol = Some(line_info.symbol().to_string());
+        pointer.function = Some(match demangle_name(&name, demangling) {
+            Some(demangled) => demangled,
+            None => name.into_cow().into_owned(),
+        });
+        pointer.sym_addr = Some(HexValue(
+            lookup_result.expose_preferred_addr(function_address),
+        ));
+        pointer.offset = HexValue(relative_addr.saturating_sub(function_address));
+    }
 
-    /// Frames referencing unmapped memory regions.
-    ///
-    /// These may be the result of issues in the client-side module finder, or
-    /// broken debug-id information.
-    ///
-    /// We can improve this by fixing client-side implementations and having
-    /// proper debug-ids.
-    unmapped_frames: u64,
+    Some(pointer)
 }
 
 /// Determine if the [`SymbolicatedFrame`] is likely to be a thread base.
@@ -553,7 +766,7 @@ fn is_likely_base_frame(frame: &SymbolicatedFrame) -> bool {
 
 fn record_symbolication_metrics(
     origin: StacktraceOrigin,
-    metrics: StacktraceMetrics,
+    metrics: StacktraceQuality,
     modules: &[CompleteObjectInfo],
     stacktraces: &[CompleteStacktrace],
 ) {
@@ -658,35 +871,52 @@ fn record_symbolication_metrics(
             metrics.unsymbolicated_scanned_frames,
         "platform" => &platform, "origin" => &origin
     );
+    metric!(
+        time_raw("symbolication.symbol_table_frames") = metrics.symbol_table_frames,
+        "platform" => &platform, "origin" => &origin
+    );
     metric!(
         time_raw("symbolication.unmapped_frames") = metrics.unmapped_frames,
         "platform" => &platform, "origin" => &origin
     );
 }
 
+/// Symbolicates all frames of `thread`, returning the quality of the symbolicated stacktrace.
 fn symbolicate_stacktrace(
     thread: RawStacktrace,
     caches: &ModuleLookup,
-    metrics: &mut StacktraceMetrics,
     signal: Option<Signal>,
-) -> CompleteStacktrace {
+    options: &RequestOptions,
+) -> (CompleteStacktrace, StacktraceQuality) {
+    let mut metrics = StacktraceQuality::default();
     let mut symbolicated_frames = vec![];
     let mut unsymbolicated_frames_iter = thread.frames.into_iter().enumerate().peekable();
 
     while let Some((index, mut frame)) = unsymbolicated_frames_iter.next() {
-        match symbolicate_frame(caches, &thread.registers, signal, &mut frame, index) {
+        match symbolicate_frame(
+            caches,
+            &thread.registers,
+            signal,
+            &options.demangling,
+            &mut frame,
+            index,
+        ) {
             Ok(frames) => {
                 if matches!(frame.trust, FrameTrust::Scan) {
                     metrics.scanned_frames += 1;
                 }
-                symbolicated_frames.extend(frames)
+                if frames.iter().any(|frame| frame.symbol_table_only) {
+                    metrics.symbol_table_frames += 1;
+                }
+                symbolicated_frames.extend(apply_inline_frames(frames, options.inline_frames))
             }
             Err(status) => {
                 // Since symbolication failed, the function name was not demangled. In case there is
                 // either one of `function` or `symbol`, treat that as mangled name and try to
                 // demangle it. If that succeeds, write the demangled name back.
                 let mangled = frame.function.as_deref().xor(frame.symbol.as_deref());
-                let demangled = mangled.and_then(|m| Name::from(m).demangle(DEMANGLE_OPTIONS));
+                let demangled =
+                    mangled.and_then(|m| demangle_name(&Name::from(m), &options.demangling));
                 if let Some(demangled) = demangled {
                     if let Some(old_mangled) = frame.function.replace(demangled) {
                         frame.symbol = Some(old_mangled);
@@ -709,8 +939,15 @@ fn symbolicate_stacktrace(
                 //   instruction_addr that is not in any image *we* consider valid. We discard
                 //   images which do not have a debug id, while the stackscanner considers them
                 //   perfectly fine.
+                // - UnloadedImage: If the stackscanner found a stale return address into a module
+                //   which has been unloaded since.
                 if frame.trust == FrameTrust::Scan
-                    && (status == FrameStatus::MissingSymbol || status == FrameStatus::UnknownImage)
+                    && matches!(
+                        status,
+                        FrameStatus::MissingSymbol
+                            | FrameStatus::UnknownImage
+                            | FrameStatus::UnloadedImage
+                    )
                 {
                     continue;
                 }
@@ -752,6 +989,7 @@ fn symbolicate_stacktrace(
                     status,
                     original_index: Some(index),
                     raw: frame,
+                    ..SymbolicatedFrame::default()
                 });
             }
         }
@@ -776,12 +1014,60 @@ fn symbolicate_stacktrace(
         metrics.bad_traces += 1;
     }
 
-    CompleteStacktrace {
+    if let Some(ref rules) = options.in_app {
+        for frame in &mut symbolicated_frames {
+            for inlined_frame in &mut frame.inlined_frames {
+                in_app::classify_frame(inlined_frame, rules);
+            }
+            in_app::classify_frame(frame, rules);
+        }
+    }
+
+    let register_symbols = if options.symbolicate_registers {
+        thread
+            .registers
+            .iter()
+            .filter_map(|(name, value)| {
+                let symbol = symbolicate_pointer(caches, value.0, &options.demangling)?;
+                Some((name.clone(), symbol))
+            })
+            .collect()
+    } else {
+        BTreeMap::new()
+    };
+
+    let stacktrace = CompleteStacktrace {
         thread_id: thread.thread_id,
         is_requesting: thread.is_requesting,
         registers: thread.registers,
+        register_symbols,
         frames: symbolicated_frames,
+        memory: thread.memory,
+        provenance: thread.provenance,
+    };
+
+    (stacktrace, metrics)
+}
+
+/// Counts the frames which are missing debug information, by the index of their module.
+fn count_missing_frames(
+    caches: &ModuleLookup,
+    stacktraces: &[CompleteStacktrace],
+) -> BTreeMap<usize, u64> {
+    let mut missing_frames = BTreeMap::new();
+
+    let frames = stacktraces.iter().flat_map(|trace| &trace.frames);
+    for frame in frames.filter(|frame| frame.status == FrameStatus::Missing) {
+        let lookup_result =
+            caches.lookup_symcache(frame.raw.instruction_addr.0, frame.raw.addr_mode);
+        if let Some(lookup_result) = lookup_result {
+            *missing_frames
+                .entry(lookup_result.module_index)
+                .or_default() += 1;
+        }
     }
+
+    missing_frames
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -833,6 +1119,9 @@ pub struct SymbolicateStacktraces {
     /// be symbolicated as it is not clear which debug file to load.
     pub modules: Vec<CompleteObjectInfo>,
 
+    /// The address that caused the crash, such as the faulting address of a segfault.
+    pub crash_address: Option<u64>,
+
     /// Options that came with this request, see [`RequestOptions`].
     pub options: RequestOptions,
 }
@@ -872,25 +1161,68 @@ impl SymbolicationActor {
             signal,
             origin,
             modules,
+            crash_address,
+            options,
             ..
         } = request;
 
+        let crash_address = crash_address.filter(|_| options.symbolicate_registers);
+        let pointers: Vec<u64> = if options.symbolicate_registers {
+            stacktraces
+                .iter()
+                .flat_map(|trace| trace.registers.values().map(|value| value.0))
+                .chain(crash_address)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
         let mut module_lookup = ModuleLookup::new(scope, sources, modules.into_iter());
+        module_lookup.add_symbol_maps(&options.symbol_maps);
+        module_lookup
+            .fetch_symcaches(self.symcaches.clone(), &stacktraces, &pointers)
+            .await;
         module_lookup
-            .fetch_symcaches(self.symcaches.clone(), &stacktraces)
+            .fetch_portable_pdbs(self.ppdbs.clone(), &stacktraces)
+            .await;
+        module_lookup
+            .fetch_source_maps(self.sourcemaps.clone(), &stacktraces)
             .await;
 
         let future = async move {
-            let mut metrics = StacktraceMetrics::default();
+            let mut metrics = StacktraceQuality::default();
+            let mut qualities = Vec::with_capacity(stacktraces.len());
             let stacktraces: Vec<_> = stacktraces
                 .into_iter()
-                .map(|trace| symbolicate_stacktrace(trace, &module_lookup, &mut metrics, signal))
+                .map(|trace| {
+                    let (trace, quality) =
+                        symbolicate_stacktrace(trace, &module_lookup, signal, &options);
+                    metrics.add(&quality);
+                    qualities.push(quality);
+                    trace
+                })
                 .collect();
 
-            (module_lookup, stacktraces, metrics)
+            let quality = options.quality_report.then(|| {
+                (
+                    qualities,
+                    count_missing_frames(&module_lookup, &stacktraces),
+                )
+            });
+
+            let crash_address_symbol = crash_address
+                .and_then(|addr| symbolicate_pointer(&module_lookup, addr, &options.demangling));
+
+            (
+                module_lookup,
+                stacktraces,
+                metrics,
+                quality,
+                crash_address_symbol,
+            )
         };
 
-        let (mut module_lookup, mut stacktraces, metrics) =
+        let (mut module_lookup, mut stacktraces, metrics, quality, crash_address_symbol) =
             CancelOnDrop::new(self.cpu_pool.spawn(future.bind_hub(sentry::Hub::current())))
                 .await
                 .context("Symbolication future cancelled")?;
@@ -902,27 +1234,32 @@ impl SymbolicationActor {
         let future = async move {
             let debug_sessions = module_lookup.prepare_debug_sessions();
 
+            let add_context_lines = |frame: &mut SymbolicatedFrame| {
+                let (abs_path, lineno) = match (&frame.raw.abs_path, frame.raw.lineno) {
+                    (&Some(ref abs_path), Some(lineno)) => (abs_path, lineno),
+                    _ => return,
+                };
+
+                let result = module_lookup.get_context_lines(
+                    &debug_sessions,
+                    frame.raw.instruction_addr.0,
+                    frame.raw.addr_mode,
+                    abs_path,
+                    lineno,
+                    5,
+                );
+
+                if let Some((pre_context, context_line, post_context)) = result {
+                    frame.raw.pre_context = pre_context;
+                    frame.raw.context_line = Some(context_line);
+                    frame.raw.post_context = post_context;
+                }
+            };
+
             for trace in &mut stacktraces {
                 for frame in &mut trace.frames {
-                    let (abs_path, lineno) = match (&frame.raw.abs_path, frame.raw.lineno) {
-                        (&Some(ref abs_path), Some(lineno)) => (abs_path, lineno),
-                        _ => continue,
-                    };
-
-                    let result = module_lookup.get_context_lines(
-                        &debug_sessions,
-                        frame.raw.instruction_addr.0,
-                        frame.raw.addr_mode,
-                        abs_path,
-                        lineno,
-                        5,
-                    );
-
-                    if let Some((pre_context, context_line, post_context)) = result {
-                        frame.raw.pre_context = pre_context;
-                        frame.raw.context_line = Some(context_line);
-                        frame.raw.post_context = post_context;
-                    }
+                    frame.inlined_frames.iter_mut().for_each(add_context_lines);
+                    add_context_lines(frame);
                 }
             }
             // explicitly drop this, so it does not borrow `module_lookup` anymore.
@@ -930,12 +1267,26 @@ impl SymbolicationActor {
 
             // bring modules back into the original order
             let modules = module_lookup.into_inner();
+
+            let quality = quality.map(|(qualities, missing_frames)| QualityReport {
+                total: metrics.clone(),
+                stacktraces: qualities,
+                missing_modules: missing_frames
+                    .into_iter()
+                    .map(|(module_index, frames)| {
+                        MissingModule::new(module_index, &modules[module_index].raw, frames)
+                    })
+                    .collect(),
+            });
+
             record_symbolication_metrics(origin, metrics, &modules, &stacktraces);
 
             CompletedSymbolicationResponse {
                 signal,
                 stacktraces,
                 modules,
+                quality,
+                crash_address_symbol,
                 ..Default::default()
             }
         };
@@ -1034,6 +1385,7 @@ fn map_apple_binary_image(image: apple_crash_report_parser::BinaryImage) -> Comp
             0 => None,
             size => Some(size),
         },
+        unloaded: false,
     };
 
     raw_info.into()
@@ -1089,6 +1441,7 @@ impl SymbolicationActor {
                     is_requesting: Some(thread.crashed),
                     registers,
                     frames,
+                    ..RawStacktrace::default()
                 });
             }
 
@@ -1099,6 +1452,7 @@ impl SymbolicationActor {
                 origin: StacktraceOrigin::AppleCrashReport,
                 signal: None,
                 stacktraces,
+                crash_address: None,
                 options,
             };
 
@@ -1231,6 +1585,8 @@ impl From<&SymCacheError> for ObjectFileStatus {
             SymCacheError::Timeout => ObjectFileStatus::Timeout,
             SymCacheError::Malformed => ObjectFileStatus::Malformed,
             SymCacheError::ObjectParsing(_) => ObjectFileStatus::Malformed,
+            SymCacheError::SymbolMap(_) => ObjectFileStatus::Malformed,
+            SymCacheError::DebugInfo(_) => ObjectFileStatus::Malformed,
             _ => {
                 // Just in case we didn't handle an error properly,
                 // capture it here. If an error was captured with
@@ -1251,6 +1607,7 @@ mod tests {
     use crate::config::Config;
     use crate::services::Service;
     use crate::test::{self, fixture};
+    use crate::types::SymbolMap;
     use crate::utils::addr::AddrMode;
 
     /// Setup tests and create a test service.
@@ -1298,11 +1655,14 @@ mod tests {
                 debug_id: Some("502fc0a5-1ec1-3e47-9998-684fa139dca7".to_owned()),
                 image_addr: HexValue(0x1_0000_0000),
                 image_size: Some(4096),
+                unloaded: false,
                 code_file: None,
                 debug_file: None,
             })],
+            crash_address: None,
             options: RequestOptions {
                 dif_candidates: true,
+                ..Default::default()
             },
         }
     }
@@ -1405,6 +1765,7 @@ mod tests {
             origin: StacktraceOrigin::Symbolicate,
             sources: Arc::new([]),
             scope: Default::default(),
+            crash_address: None,
             options: Default::default(),
         };
 
@@ -1435,6 +1796,7 @@ mod tests {
                 Arc::new([source]),
                 RequestOptions {
                     dif_candidates: true,
+                    ..Default::default()
                 },
             )
             .unwrap();
@@ -1482,6 +1844,7 @@ mod tests {
             origin: StacktraceOrigin::Symbolicate,
             sources: Arc::new([source]),
             scope: Default::default(),
+            crash_address: None,
             options: Default::default(),
         };
 
@@ -1530,8 +1893,10 @@ mod tests {
             origin: StacktraceOrigin::Symbolicate,
             sources: Arc::new([source]),
             scope: Default::default(),
+            crash_address: None,
             options: RequestOptions {
                 dif_candidates: true,
+                ..Default::default()
             },
         };
 
@@ -1556,6 +1921,7 @@ mod tests {
             debug_file: None,
             image_addr: HexValue(42),
             image_size: Some(0),
+            unloaded: false,
         });
 
         let lookup = ModuleLookup::new(Scope::Global, Arc::new([]), std::iter::once(info.clone()));
@@ -1566,6 +1932,208 @@ mod tests {
         assert!(lookup_result.symcache.is_none());
     }
 
+    #[tokio::test]
+    async fn test_quality_report() {
+        let (service, _cache_dir) = setup_service().await;
+        let symbolication = service.symbolication();
+
+        let mut request = get_symbolication_request(vec![]);
+        request.options.quality_report = true;
+        let request_id = symbolication.symbolicate_stacktraces(request).unwrap();
+
+        let response = match symbolication.get_response(request_id, None).await {
+            Some(SymbolicationResponse::Completed(response)) => response,
+            other => panic!("unexpected response: {:?}", other),
+        };
+
+        let quality = response.quality.unwrap();
+        assert_eq!(quality.total.unsymbolicated_frames, 1);
+        assert_eq!(quality.total.bad_traces, 1);
+        assert_eq!(quality.stacktraces, vec![quality.total.clone()]);
+        assert_eq!(quality.missing_modules.len(), 1);
+
+        let missing = &quality.missing_modules[0];
+        assert_eq!(missing.module_index, 0);
+        assert_eq!(missing.unsymbolicated_frames, 1);
+        assert_eq!(
+            missing.debug_id.as_deref(),
+            Some("502fc0a5-1ec1-3e47-9998-684fa139dca7")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_symbol_maps() {
+        let (service, _cache_dir) = setup_service().await;
+        let symbolication = service.symbolication();
+
+        let mut request = get_symbolication_request(vec![]);
+        request.stacktraces[0].frames.push(RawFrame {
+            instruction_addr: HexValue(0x7f00_0000_1048),
+            ..RawFrame::default()
+        });
+        request.options.symbol_maps = vec![SymbolMap {
+            name: Some("jit".to_owned()),
+            perf_map: "7f0000001000 40 jitted::foo\n7f0000001040 20 jitted::bar\n".to_owned(),
+        }];
+        let request_id = symbolication.symbolicate_stacktraces(request).unwrap();
+
+        let response = match symbolication.get_response(request_id, None).await {
+            Some(SymbolicationResponse::Completed(response)) => response,
+            other => panic!("unexpected response: {:?}", other),
+        };
+
+        let frame = &response.stacktraces[0].frames[1];
+        assert_eq!(frame.status, FrameStatus::Symbolicated);
+        assert_eq!(frame.raw.function.as_deref(), Some("jitted::bar"));
+        assert_eq!(frame.raw.package.as_deref(), Some("jit"));
+
+        let module = &response.modules[1];
+        assert_eq!(module.raw.code_file.as_deref(), Some("jit"));
+        assert_eq!(module.raw.image_addr, HexValue(0x7f00_0000_1000));
+        assert_eq!(module.debug_status, ObjectFileStatus::Found);
+    }
+
+    #[tokio::test]
+    async fn test_symbolicate_registers() {
+        let (service, _cache_dir) = setup_service().await;
+        let symbolication = service.symbolication();
+
+        let mut request = get_symbolication_request(vec![]);
+        let registers = &mut request.stacktraces[0].registers;
+        registers.insert("rax".to_owned(), HexValue(0x7f00_0000_1048));
+        registers.insert("rbx".to_owned(), HexValue(0x7f00_0000_2000));
+        request.crash_address = Some(0x7f00_0000_1004);
+        request.options.symbolicate_registers = true;
+        request.options.symbol_maps = vec![SymbolMap {
+            name: Some("jit".to_owned()),
+            perf_map: "7f0000001000 40 jitted::foo\n7f0000001040 20 jitted::bar\n".to_owned(),
+        }];
+        let request_id = symbolication.symbolicate_stacktraces(request).unwrap();
+
+        let response = match symbolication.get_response(request_id, None).await {
+            Some(SymbolicationResponse::Completed(response)) => response,
+            other => panic!("unexpected response: {:?}", other),
+        };
+
+        let register_symbols = &response.stacktraces[0].register_symbols;
+        assert_eq!(register_symbols.len(), 1);
+        let rax = &register_symbols["rax"];
+        assert_eq!(rax.function.as_deref(), Some("jitted::bar"));
+        assert_eq!(rax.package.as_deref(), Some("jit"));
+        assert_eq!(rax.sym_addr, Some(HexValue(0x7f00_0000_1040)));
+        assert_eq!(rax.offset, HexValue(0x8));
+
+        let crash_address_symbol = response.crash_address_symbol.unwrap();
+        assert_eq!(
+            crash_address_symbol.function.as_deref(),
+            Some("jitted::foo")
+        );
+        assert_eq!(crash_address_symbol.offset, HexValue(0x4));
+    }
+
+    #[tokio::test]
+    async fn test_symbolicate_data_pointer() {
+        let (service, _cache_dir) = setup_service().await;
+        let symbolication = service.symbolication();
+        let (_symsrv, source) = test::symbol_server();
+
+        // `main` is the last function of the module, followed by data.
+        let mut request = get_symbolication_request(vec![source]);
+        let registers = &mut request.stacktraces[0].registers;
+        registers.insert("rip".to_owned(), HexValue(0x1_0000_0fa0));
+        registers.insert("rdi".to_owned(), HexValue(0x1_0000_0ff0));
+        request.options.symbolicate_registers = true;
+        let request_id = symbolication.symbolicate_stacktraces(request).unwrap();
+
+        let response = match symbolication.get_response(request_id, None).await {
+            Some(SymbolicationResponse::Completed(response)) => response,
+            other => panic!("unexpected response: {:?}", other),
+        };
+
+        let register_symbols = &response.stacktraces[0].register_symbols;
+        let rip = &register_symbols["rip"];
+        assert_eq!(rip.function.as_deref(), Some("main"));
+        assert_eq!(rip.sym_addr, Some(HexValue(0x1_0000_0fa0)));
+        assert_eq!(rip.offset, HexValue(0x0));
+
+        let rdi = &register_symbols["rdi"];
+        assert_eq!(rdi.function, None);
+        assert_eq!(rdi.symbol, None);
+        assert_eq!(rdi.sym_addr, None);
+        assert_eq!(rdi.offset, HexValue(0xff0));
+    }
+
+    #[test]
+    fn test_demangle_name() {
+        let demangle =
+            |name: &str, options: DemanglingOptions| demangle_name(&Name::from(name), &options);
+        let cpp = "_ZN3foo3barEi";
+        let rust = "_ZN4core3ptr13drop_in_place17h0123456789abcdefE";
+
+        let options = DemanglingOptions::default();
+        assert_eq!(demangle(cpp, options).as_deref(), Some("foo::bar(int)"));
+        assert_eq!(
+            demangle(rust, options).as_deref(),
+            Some("core::ptr::drop_in_place")
+        );
+
+        let options = DemanglingOptions {
+            style: DemangleStyle::Short,
+            ..Default::default()
+        };
+        assert_eq!(demangle(cpp, options).as_deref(), Some("foo::bar"));
+
+        let options = DemanglingOptions {
+            style: DemangleStyle::None,
+            ..Default::default()
+        };
+        assert_eq!(demangle(cpp, options), None);
+
+        let options = DemanglingOptions {
+            strip_rust_hash: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            demangle(rust, options).as_deref(),
+            Some("core::ptr::drop_in_place::h0123456789abcdef")
+        );
+    }
+
+    #[test]
+    fn test_apply_inline_frames() {
+        let frame = |function: &str, inlined: bool| SymbolicatedFrame {
+            status: FrameStatus::Symbolicated,
+            inlined,
+            raw: RawFrame {
+                function: Some(function.to_owned()),
+                ..RawFrame::default()
+            },
+            ..SymbolicatedFrame::default()
+        };
+        let frames = vec![
+            frame("inner", true),
+            frame("middle", true),
+            frame("outer", false),
+        ];
+        let functions = |frames: &[SymbolicatedFrame]| {
+            frames
+                .iter()
+                .map(|frame| frame.raw.function.clone().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let expanded = apply_inline_frames(frames.clone(), InlineFrames::Expand);
+        assert_eq!(functions(&expanded), ["inner", "middle", "outer"]);
+
+        let collapsed = apply_inline_frames(frames.clone(), InlineFrames::Collapse);
+        assert_eq!(functions(&collapsed), ["outer"]);
+        assert_eq!(functions(&collapsed[0].inlined_frames), ["inner", "middle"]);
+
+        let dropped = apply_inline_frames(frames, InlineFrames::Drop);
+        assert_eq!(functions(&dropped), ["outer"]);
+        assert!(dropped[0].inlined_frames.is_empty());
+    }
+
     #[tokio::test]
     async fn test_max_requests() {
         test::setup();
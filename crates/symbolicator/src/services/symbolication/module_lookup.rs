@@ -7,12 +7,16 @@ use symbolic::common::{ByteView, SelfCell};
 use symbolic::debuginfo::{Object, ObjectDebugSession};
 
 use crate::services::objects::{FindObject, FoundObject, ObjectPurpose, ObjectsActor};
+use crate::services::ppdb::{PortablePdb, PortablePdbService};
+use crate::services::sourcemaps::{SourceMap, SourceMapService};
 use crate::services::symcaches::{FetchSymCache, SymCacheActor, SymCacheFile};
 use crate::sources::{FileType, SourceConfig};
 use crate::types::{
-    CompleteObjectInfo, CompleteStacktrace, ObjectFileStatus, RawStacktrace, Scope,
+    CompleteObjectInfo, CompleteStacktrace, ObjectFileStatus, ObjectType, RawObjectInfo,
+    RawStacktrace, Scope, SymbolMap,
 };
 use crate::utils::addr::AddrMode;
+use crate::utils::hex::HexValue;
 
 use super::object_id_from_object_info;
 
@@ -22,6 +26,11 @@ pub struct SymCacheLookupResult<'a> {
     pub object_info: &'a CompleteObjectInfo,
     pub symcache: Option<&'a SymCacheFile>,
     pub relative_addr: Option<u64>,
+    /// Whether the module was created from a [`SymbolMap`], whose symbols have exact sizes.
+    pub synthetic: bool,
+    /// The address of the last function in the SymCache, if the module is referenced by a
+    /// pointer.
+    pub last_function_addr: Option<u64>,
 }
 
 impl<'a> SymCacheLookupResult<'a> {
@@ -55,10 +64,27 @@ struct ModuleEntry {
     object_info: CompleteObjectInfo,
     symcache: Option<Arc<SymCacheFile>>,
     source_object: Option<SourceObject>,
+    /// The Portable PDB of a .NET module.
+    ppdb: Option<Arc<PortablePdb>>,
+    /// The source map of a minified JavaScript file.
+    source_map: Option<Arc<SourceMap>>,
+    /// Whether this module was created from a [`SymbolMap`].
+    synthetic: bool,
+    /// The address of the last function in `symcache`.
+    ///
+    /// This is only computed for modules referenced by pointers, see
+    /// [`lookup_pointer`](ModuleLookup::lookup_pointer).
+    last_function_addr: Option<u64>,
 }
 
 pub struct ModuleLookup {
+    /// All modules, with the synthetic ones at the end.
+    ///
+    /// Both the regular and the synthetic modules are sorted by their address, so that either
+    /// part can be binary searched.
     modules: Vec<ModuleEntry>,
+    /// The index of the first synthetic module in `modules`.
+    synthetic_start: usize,
     scope: Scope,
     sources: Arc<[SourceConfig]>,
 }
@@ -77,6 +103,10 @@ impl ModuleLookup {
                 object_info,
                 symcache: None,
                 source_object: None,
+                ppdb: None,
+                source_map: None,
+                synthetic: false,
+                last_function_addr: None,
             })
             .collect();
 
@@ -106,12 +136,73 @@ impl ModuleLookup {
         }
 
         Self {
+            synthetic_start: modules.len(),
             modules,
             scope,
             sources,
         }
     }
 
+    /// Adds synthetic modules for each of the `symbol_maps`.
+    ///
+    /// Every contiguous range of symbols becomes a synthetic module with an in-memory SymCache,
+    /// which is appended to the module list.  Since they usually cover memory allocated by a JIT
+    /// compiler, synthetic modules take precedence over other modules which may extend across
+    /// the same addresses, see [`get_module_by_addr`](Self::get_module_by_addr).
+    pub fn add_symbol_maps(&mut self, symbol_maps: &[SymbolMap]) {
+        if symbol_maps.is_empty() {
+            return;
+        }
+
+        for symbol_map in symbol_maps {
+            let symcaches = match SymCacheFile::from_symbol_map(symbol_map) {
+                Ok(symcaches) => symcaches,
+                Err(e) => {
+                    tracing::warn!("Failed to build symcache from symbol map: {}", e);
+                    continue;
+                }
+            };
+
+            for (symcache, image_addr, image_size) in symcaches {
+                self.add_synthetic_module(symbol_map, symcache, image_addr, image_size);
+            }
+        }
+
+        self.modules[self.synthetic_start..]
+            .sort_by_key(|entry| entry.object_info.raw.image_addr.0);
+    }
+
+    fn add_synthetic_module(
+        &mut self,
+        symbol_map: &SymbolMap,
+        symcache: SymCacheFile,
+        image_addr: u64,
+        image_size: u64,
+    ) {
+        let mut object_info = CompleteObjectInfo::from(RawObjectInfo {
+            ty: ObjectType::Unknown,
+            code_id: None,
+            code_file: symbol_map.name.clone(),
+            debug_id: None,
+            debug_file: None,
+            image_addr: HexValue(image_addr),
+            image_size: Some(image_size),
+            unloaded: false,
+        });
+        object_info.features.merge(symcache.features());
+
+        self.modules.push(ModuleEntry {
+            module_index: self.modules.len(),
+            object_info,
+            symcache: Some(Arc::new(symcache)),
+            source_object: None,
+            ppdb: None,
+            source_map: None,
+            synthetic: true,
+            last_function_addr: None,
+        });
+    }
+
     /// Returns the original `CompleteObjectInfo` list in its original sorting order.
     pub fn into_inner(mut self) -> Vec<CompleteObjectInfo> {
         self.modules.sort_by_key(|entry| entry.module_index);
@@ -127,6 +218,7 @@ impl ModuleLookup {
         &mut self,
         symcache_actor: SymCacheActor,
         stacktraces: &[RawStacktrace],
+        pointers: &[u64],
     ) {
         let mut referenced_objects = HashSet::new();
         for stacktrace in stacktraces {
@@ -138,6 +230,13 @@ impl ModuleLookup {
                 }
             }
         }
+        let mut pointer_objects = HashSet::new();
+        for &addr in pointers {
+            if let Some(SymCacheLookupResult { module_index, .. }) = self.lookup_pointer(addr) {
+                referenced_objects.insert(module_index);
+                pointer_objects.insert(module_index);
+            }
+        }
 
         let futures = self
             .modules
@@ -150,6 +249,20 @@ impl ModuleLookup {
                     return None;
                 }
 
+                if entry.synthetic {
+                    entry.object_info.debug_status = ObjectFileStatus::Found;
+                    return None;
+                }
+
+                // .NET modules are symbolicated from Portable PDBs, see `fetch_portable_pdbs`,
+                // and JavaScript modules from source maps, see `fetch_source_maps`.
+                if matches!(
+                    entry.object_info.raw.ty,
+                    ObjectType::PeDotnet | ObjectType::SourceMap
+                ) {
+                    return None;
+                }
+
                 let symcache_actor = symcache_actor.clone();
                 let request = FetchSymCache {
                     object_type: entry.object_info.raw.ty,
@@ -184,6 +297,10 @@ impl ModuleLookup {
                     entry.object_info.arch = symcache.arch();
                     entry.object_info.features.merge(symcache.features());
                     entry.object_info.candidates.merge(symcache.candidates());
+
+                    if pointer_objects.contains(&entry.module_index) {
+                        entry.last_function_addr = last_function_addr(symcache);
+                    }
                 }
 
                 entry.symcache = symcache;
@@ -192,6 +309,102 @@ impl ModuleLookup {
         }
     }
 
+    /// Fetches the Portable PDBs for the .NET modules referenced by the `stacktraces`.
+    #[tracing::instrument(skip_all)]
+    pub async fn fetch_portable_pdbs(
+        &mut self,
+        ppdb_svc: PortablePdbService,
+        stacktraces: &[RawStacktrace],
+    ) {
+        let mut referenced_objects = HashSet::new();
+        for stacktrace in stacktraces {
+            for frame in &stacktrace.frames {
+                if let Some(entry) =
+                    self.get_module_by_addr(frame.instruction_addr.0, frame.addr_mode)
+                {
+                    referenced_objects.insert(entry.module_index);
+                }
+            }
+        }
+
+        let futures: Vec<_> = self
+            .modules
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                entry.object_info.raw.ty == ObjectType::PeDotnet
+                    && referenced_objects.contains(&entry.module_index)
+            })
+            .map(|(idx, entry)| {
+                let ppdb_svc = ppdb_svc.clone();
+                let identifier = object_id_from_object_info(&entry.object_info.raw);
+                let scope = self.scope.clone();
+                let sources = self.sources.clone();
+
+                async move { (idx, ppdb_svc.fetch(identifier, scope, sources).await) }
+                    .bind_hub(Hub::new_from_top(Hub::current()))
+            })
+            .collect();
+
+        for (idx, ppdb) in future::join_all(futures).await {
+            if let Some(entry) = self.modules.get_mut(idx) {
+                entry.object_info.debug_status = match ppdb {
+                    Some(_) => ObjectFileStatus::Found,
+                    None => ObjectFileStatus::Missing,
+                };
+                entry.object_info.features.has_debug_info |= ppdb.is_some();
+                entry.ppdb = ppdb;
+            }
+        }
+    }
+
+    /// Fetches the source maps for the JavaScript modules referenced by the `stacktraces`.
+    #[tracing::instrument(skip_all)]
+    pub async fn fetch_source_maps(
+        &mut self,
+        source_map_svc: SourceMapService,
+        stacktraces: &[RawStacktrace],
+    ) {
+        let referenced_objects: HashSet<_> = stacktraces
+            .iter()
+            .flat_map(|stacktrace| &stacktrace.frames)
+            .filter_map(|frame| match frame.addr_mode {
+                AddrMode::Js(module_index) => Some(module_index),
+                _ => None,
+            })
+            .collect();
+
+        let futures: Vec<_> = self
+            .modules
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                entry.object_info.raw.ty == ObjectType::SourceMap
+                    && referenced_objects.contains(&entry.module_index)
+            })
+            .map(|(idx, entry)| {
+                let source_map_svc = source_map_svc.clone();
+                let identifier = object_id_from_object_info(&entry.object_info.raw);
+                let scope = self.scope.clone();
+                let sources = self.sources.clone();
+
+                async move { (idx, source_map_svc.fetch(identifier, scope, sources).await) }
+                    .bind_hub(Hub::new_from_top(Hub::current()))
+            })
+            .collect();
+
+        for (idx, source_map) in future::join_all(futures).await {
+            if let Some(entry) = self.modules.get_mut(idx) {
+                entry.object_info.debug_status = match source_map {
+                    Some(_) => ObjectFileStatus::Found,
+                    None => ObjectFileStatus::Missing,
+                };
+                entry.object_info.features.has_debug_info |= source_map.is_some();
+                entry.source_map = source_map;
+            }
+        }
+    }
+
     /// Fetches all the sources for the modules referenced by the `stacktraces`.
     #[tracing::instrument(skip_all)]
     pub async fn fetch_sources(
@@ -215,6 +428,11 @@ impl ModuleLookup {
             .iter_mut()
             .enumerate()
             .filter_map(|(idx, entry)| {
+                // The sources of JavaScript modules are embedded in their source maps.
+                if entry.object_info.raw.ty == ObjectType::SourceMap {
+                    return None;
+                }
+
                 let is_used = referenced_objects.contains(&entry.module_index);
                 if !is_used {
                     entry.object_info.debug_status = ObjectFileStatus::Unused;
@@ -222,6 +440,10 @@ impl ModuleLookup {
                     return None;
                 }
 
+                if entry.synthetic {
+                    return None;
+                }
+
                 let objects = objects.clone();
                 let find_request = FindObject {
                     filetypes: FileType::sources(),
@@ -233,8 +455,9 @@ impl ModuleLookup {
 
                 Some(
                     async move {
-                        let FoundObject { meta, candidates } =
-                            objects.find(find_request).await.unwrap_or_default();
+                        let FoundObject {
+                            meta, candidates, ..
+                        } = objects.find(find_request).await.unwrap_or_default();
 
                         let source_object = match meta {
                             None => None,
@@ -274,17 +497,54 @@ impl ModuleLookup {
         self.get_module_by_addr(addr, addr_mode).map(|entry| {
             let relative_addr = match addr_mode {
                 AddrMode::Abs => entry.object_info.abs_to_rel_addr(addr),
-                AddrMode::Rel(_) => Some(addr),
+                AddrMode::Rel(_) | AddrMode::Il(_) | AddrMode::Js(_) => Some(addr),
             };
             SymCacheLookupResult {
                 module_index: entry.module_index,
                 object_info: &entry.object_info,
                 symcache: entry.symcache.as_deref(),
                 relative_addr,
+                synthetic: entry.synthetic,
+                last_function_addr: entry.last_function_addr,
             }
         })
     }
 
+    /// Looks up the module containing the absolute address `addr`, such as a register value.
+    ///
+    /// Unlike [`lookup_symcache`](Self::lookup_symcache), this only matches modules with a known
+    /// size, since most such values are not pointers into code and would otherwise be attributed
+    /// to the closest module below them.
+    pub fn lookup_pointer(&self, addr: u64) -> Option<SymCacheLookupResult<'_>> {
+        self.lookup_symcache(addr, AddrMode::Abs).filter(|result| {
+            let raw = &result.object_info.raw;
+            match raw.image_size {
+                Some(size) if size != 0 => addr < raw.image_addr.0.saturating_add(size),
+                _ => false,
+            }
+        })
+    }
+
+    /// Looks up the .NET module with the given index and its Portable PDB, if one was found.
+    pub fn lookup_portable_pdb(
+        &self,
+        module_index: usize,
+    ) -> Option<(&CompleteObjectInfo, Option<&PortablePdb>)> {
+        self.get_module_by_addr(0, AddrMode::Il(module_index))
+            .filter(|entry| entry.object_info.raw.ty == ObjectType::PeDotnet)
+            .map(|entry| (&entry.object_info, entry.ppdb.as_deref()))
+    }
+
+    /// Looks up the JavaScript module with the given index and its source map, if one was found.
+    pub fn lookup_source_map(
+        &self,
+        module_index: usize,
+    ) -> Option<(&CompleteObjectInfo, Option<&SourceMap>)> {
+        self.get_module_by_addr(0, AddrMode::Js(module_index))
+            .filter(|entry| entry.object_info.raw.ty == ObjectType::SourceMap)
+            .map(|entry| (&entry.object_info, entry.source_map.as_deref()))
+    }
+
     /// Creates a [`ObjectDebugSession`] for each module that has a [`SourceObject`].
     ///
     /// This returns a separate HashMap purely to avoid self-referential borrowing issues.
@@ -319,36 +579,36 @@ impl ModuleLookup {
         let session = debug_sessions.get(&entry.module_index)?.as_ref()?;
         let source = session.source_by_path(abs_path).ok()??;
 
-        let lineno = lineno as usize;
-        let start_line = lineno.saturating_sub(n);
-        let line_diff = lineno - start_line;
-
-        let mut lines = source.lines().skip(start_line);
-        let pre_context = (&mut lines)
-            .take(line_diff.saturating_sub(1))
-            .map(|x| x.to_string())
-            .collect();
-        let context = lines.next()?.to_string();
-        let post_context = lines.take(n).map(|x| x.to_string()).collect();
-
-        Some((pre_context, context, post_context))
+        get_context_lines(&source, lineno, n)
     }
 
     /// Looks up the [`ModuleEntry`] for the given `addr` and `addr_mode`.
+    ///
+    /// Synthetic modules created from symbol maps take precedence within their address range.
+    /// Otherwise, the module with the closest start address below `addr` is returned.
     fn get_module_by_addr(&self, addr: u64, addr_mode: AddrMode) -> Option<&ModuleEntry> {
         match addr_mode {
             AddrMode::Abs => {
-                let idx = match self
-                    .modules
-                    .binary_search_by_key(&addr, |entry| entry.object_info.raw.image_addr.0)
-                {
-                    Ok(idx) => idx,
-                    Err(idx) if idx == 0 => {
-                        return None;
+                let (modules, synthetic_modules) = self.modules.split_at(self.synthetic_start);
+
+                let idx = synthetic_modules
+                    .partition_point(|entry| entry.object_info.raw.image_addr.0 <= addr);
+                if let Some(entry) = idx.checked_sub(1).map(|idx| &synthetic_modules[idx]) {
+                    let raw = &entry.object_info.raw;
+                    let end_addr = raw.image_addr.0.saturating_add(raw.image_size.unwrap_or(0));
+                    if addr < end_addr {
+                        return Some(entry);
                     }
-                    Err(idx) => idx - 1,
-                };
-                let entry = self.modules.get(idx)?;
+                }
+
+                let idx =
+                    modules.partition_point(|entry| entry.object_info.raw.image_addr.0 <= addr);
+                let entry = &modules[idx.checked_sub(1)?];
+
+                // JavaScript modules have no address range and are referenced by index.
+                if entry.object_info.raw.ty == ObjectType::SourceMap {
+                    return None;
+                }
 
                 let start_addr = entry.object_info.raw.image_addr.0;
                 let size = entry.object_info.raw.image_size.unwrap_or(0);
@@ -362,7 +622,9 @@ impl ModuleLookup {
 
                 Some(entry)
             }
-            AddrMode::Rel(this_module_index) => self
+            AddrMode::Rel(this_module_index)
+            | AddrMode::Il(this_module_index)
+            | AddrMode::Js(this_module_index) => self
                 .modules
                 .iter()
                 .find(|entry| entry.module_index == this_module_index),
@@ -370,6 +632,38 @@ impl ModuleLookup {
     }
 }
 
+/// Returns the start address of the last function in `symcache`.
+fn last_function_addr(symcache: &SymCacheFile) -> Option<u64> {
+    symcache
+        .parse()
+        .ok()??
+        .functions()
+        .filter_map(Result::ok)
+        .map(|function| function.address())
+        .max()
+}
+
+/// Returns the line `lineno` of `source`, plus `n` lines above/below.
+pub fn get_context_lines(
+    source: &str,
+    lineno: u32,
+    n: usize,
+) -> Option<(Vec<String>, String, Vec<String>)> {
+    let lineno = lineno as usize;
+    let start_line = lineno.saturating_sub(n);
+    let line_diff = lineno - start_line;
+
+    let mut lines = source.lines().skip(start_line);
+    let pre_context = (&mut lines)
+        .take(line_diff.saturating_sub(1))
+        .map(|x| x.to_string())
+        .collect();
+    let context = lines.next()?.to_string();
+    let post_context = lines.take(n).map(|x| x.to_string()).collect();
+
+    Some((pre_context, context, post_context))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::types::RawObjectInfo;
@@ -447,4 +741,75 @@ mod tests {
         let entry = modules.get_module_by_addr(0x4567, AddrMode::Abs);
         assert_eq!(entry.unwrap().object_info.raw.code_id.as_deref(), Some("c"));
     }
+
+    #[test]
+    fn portable_pdb_lookup() {
+        let raw_modules: Vec<RawObjectInfo> = serde_json::from_str(
+            r#"[{
+                "code_file": "native",
+                "type":"elf",
+                "image_addr": "0x1000"
+            },{
+                "code_file": "managed",
+                "type":"pe_dotnet",
+                "image_addr": "0x0"
+            }]"#,
+        )
+        .unwrap();
+
+        let modules = ModuleLookup::new(
+            Scope::Global,
+            Arc::new([]),
+            raw_modules.into_iter().map(From::from),
+        );
+
+        let code_file = |module_index| {
+            let (object_info, _) = modules.lookup_portable_pdb(module_index)?;
+            object_info.raw.code_file.clone()
+        };
+
+        assert_eq!(code_file(0), None);
+        assert_eq!(code_file(1).as_deref(), Some("managed"));
+        assert_eq!(code_file(2), None);
+    }
+
+    #[test]
+    fn symbol_map_precedence() {
+        let raw_modules: Vec<RawObjectInfo> = serde_json::from_str(
+            r#"[{
+                "code_file": "real",
+                "type":"elf",
+                "image_addr": "0x1000",
+                "image_size": 16384
+            }]"#,
+        )
+        .unwrap();
+
+        let mut modules = ModuleLookup::new(
+            Scope::Global,
+            Arc::new([]),
+            raw_modules.into_iter().map(From::from),
+        );
+        modules.add_symbol_maps(&[SymbolMap {
+            name: Some("jit".to_owned()),
+            perf_map: "2000 100 jitted::foo\n7f0000000000 100 jitted::far\n".to_owned(),
+        }]);
+
+        let code_file = |addr| {
+            let entry = modules.get_module_by_addr(addr, AddrMode::Abs)?;
+            entry.object_info.raw.code_file.clone()
+        };
+
+        // The real module covers the addresses around the JIT range.
+        assert_eq!(code_file(0x1800).as_deref(), Some("real"));
+        assert_eq!(code_file(0x2080).as_deref(), Some("jit"));
+        assert_eq!(code_file(0x2100).as_deref(), Some("real"));
+        assert_eq!(code_file(0x4fff).as_deref(), Some("real"));
+        assert_eq!(code_file(0x6000), None);
+
+        // Distant symbols form a separate module instead of one spanning the address space.
+        assert_eq!(code_file(0x7f00_0000_0080).as_deref(), Some("jit"));
+        assert_eq!(code_file(0x10_0000_0000), None);
+        assert_eq!(modules.into_inner().len(), 3);
+    }
 }
@@ -2,17 +2,18 @@ use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
+use anyhow::Context;
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use futures::future;
 use minidump::system_info::Os;
 use minidump::MinidumpContext;
-use minidump::{MinidumpModule, Module};
+use minidump::{MinidumpMemoryList, MinidumpModule, Module};
 use minidump_processor::{
-    FillSymbolError, FrameSymbolizer, FrameWalker, ProcessState, SymbolFile, SymbolProvider,
-    SymbolStats,
+    CallStack, FillSymbolError, FrameSymbolizer, FrameWalker, ProcessState, SymbolFile,
+    SymbolProvider, SymbolStats,
 };
 use parking_lot::RwLock;
 use sentry::types::DebugId;
@@ -23,18 +24,22 @@ use symbolic_minidump::cfi::CfiCache;
 use tempfile::TempPath;
 
 use crate::cache::CacheStatus;
+use crate::config::{MinidumpSanitizerConfig, StackwalkConfig};
 use crate::services::cficaches::{CfiCacheError, CfiCacheFile, FetchCfiCache};
-use crate::services::minidump::parse_stacktraces_from_minidump;
+use crate::services::minidump::{parse_stacktraces_from_minidump, write_stacktraces_to_minidump};
+use crate::services::minidump_sanitizer::sanitize_minidump;
 use crate::services::objects::ObjectError;
 use crate::sources::SourceConfig;
 use crate::types::{
     AllObjectCandidates, CompleteObjectInfo, CompletedSymbolicationResponse, FrameTrust,
-    ObjectFeatures, ObjectFileStatus, ObjectType, RawFrame, RawObjectInfo, RawStacktrace,
-    Registers, RequestId, RequestOptions, Scope, SystemInfo,
+    MemoryWindow, MinidumpStreams, ObjectFeatures, ObjectFileStatus, ObjectType, RawFrame,
+    RawObjectInfo, RawStacktrace, Registers, RequestId, RequestOptions, Scope, StackMemoryOptions,
+    StackMergeStrategy, StacktraceProvenance, StackwalkOptions, SystemInfo, UnloadedModule,
 };
-use crate::utils::futures::{m, measure};
+use crate::utils::futures::{m, measure, CancelOnDrop};
 use crate::utils::hex::HexValue;
 
+use super::minidump_streams::{extract_minidump_streams, read_unloaded_modules};
 use super::{
     object_id_from_object_info, MaxRequestsError, StacktraceOrigin, SymbolicateStacktraces,
     SymbolicationActor, SymbolicationError,
@@ -43,8 +48,14 @@ use super::{
 type CfiCacheResult = (DebugId, Result<Arc<CfiCacheFile>, Arc<CfiCacheError>>);
 type Minidump = minidump::Minidump<'static, ByteView<'static>>;
 
+/// The maximum size of a single memory window returned with a stacktrace.
+const MAX_MEMORY_WINDOW_SIZE: u64 = 16 * 1024;
+
+/// The maximum number of memory bytes returned across all stacktraces of a minidump.
+const MAX_MEMORY_BYTES: u64 = 1024 * 1024;
+
 #[derive(Debug, Serialize, Deserialize)]
-struct StackWalkMinidumpResult {
+pub(super) struct StackWalkMinidumpResult {
     modules: Option<Vec<(DebugId, RawObjectInfo)>>,
     missing_modules: Vec<DebugId>,
     stacktraces: Vec<RawStacktrace>,
@@ -65,6 +76,10 @@ pub(super) struct MinidumpState {
     crashed: bool,
     crash_reason: String,
     assertion: String,
+    #[serde(default)]
+    crash_address: Option<u64>,
+    #[serde(default)]
+    streams: Option<MinidumpStreams>,
 }
 
 impl MinidumpState {
@@ -108,6 +123,8 @@ impl MinidumpState {
                 })
                 .unwrap_or_default(),
             assertion: process_state.assertion.clone().unwrap_or_default(),
+            crash_address: process_state.crash_address,
+            streams: None,
         }
     }
 
@@ -129,6 +146,7 @@ impl MinidumpState {
         response.crashed = Some(self.crashed);
         response.crash_reason = Some(self.crash_reason);
         response.assertion = Some(self.assertion);
+        response.minidump_streams = self.streams;
     }
 
     /// Returns the type of executable object that produced this minidump.
@@ -369,7 +387,10 @@ impl CfiCacheModules {
     }
 }
 
-fn object_info_from_minidump_module(ty: ObjectType, module: &MinidumpModule) -> RawObjectInfo {
+pub(super) fn object_info_from_minidump_module(
+    ty: ObjectType,
+    module: &MinidumpModule,
+) -> RawObjectInfo {
     // Some modules are not objects but rather fonts or JIT areas or other mmapped files
     // which we don't care about.  These may not have complete information so map these to
     // our schema by converting to None when needed.
@@ -394,13 +415,42 @@ fn object_info_from_minidump_module(ty: ObjectType, module: &MinidumpModule) ->
             0 => None,
             size => Some(size),
         },
+        unloaded: false,
     }
 }
 
-async fn stackwalk(
+/// Options for a single [`stackwalk`] run, resolved from the request options and config.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub(super) struct StackwalkParams {
+    /// Whether to return the module list of the minidump.
+    return_modules: bool,
+    /// Whether to return memory around stack pointers and registers of each thread.
+    stack_memory: Option<StackMemoryOptions>,
+    /// Whether to return the recovered registers of every frame.
+    frame_registers: bool,
+    /// Maximum number of frames returned per thread.
+    max_frames: usize,
+    /// Whether to only return the requesting thread.
+    requesting_thread_only: bool,
+}
+
+#[cfg(test)]
+impl Default for StackwalkParams {
+    fn default() -> Self {
+        Self {
+            return_modules: false,
+            stack_memory: None,
+            frame_registers: false,
+            max_frames: StackwalkConfig::default().max_frames,
+            requesting_thread_only: false,
+        }
+    }
+}
+
+pub(super) async fn stackwalk(
     cfi_caches: Vec<(DebugId, PathBuf)>,
     minidump_path: PathBuf,
-    return_modules: bool,
+    params: StackwalkParams,
 ) -> anyhow::Result<StackWalkMinidumpResult> {
     // Stackwalk the minidump.
     let duration = Instant::now();
@@ -412,8 +462,8 @@ async fn stackwalk(
     let minidump_state = MinidumpState::from_process_state(&process_state);
     let object_type = minidump_state.object_type();
 
-    let missing_modules = provider.missing_ids();
-    let modules = return_modules.then(|| {
+    let mut missing_modules = provider.missing_ids();
+    let modules = params.return_modules.then(|| {
         process_state
             .modules
             .iter()
@@ -426,12 +476,32 @@ async fn stackwalk(
             .collect()
     });
 
-    // Finally iterate through the threads and build the stacktraces to
-    // return, marking modules as used when they are referenced by a frame.
+    // Select the threads to return, which is either all of them or only the requesting one.
     let requesting_thread_index: Option<usize> = process_state.requesting_thread;
     let threads = process_state.threads;
-    let mut stacktraces = Vec::with_capacity(threads.len());
-    for (index, thread) in threads.iter().enumerate() {
+    let selected_threads: Vec<(usize, &CallStack)> = threads
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| match requesting_thread_index {
+            Some(requesting) if params.requesting_thread_only => *index == requesting,
+            _ => true,
+        })
+        .collect();
+
+    // Only fetch CFI for modules which are referenced by the selected threads.
+    if selected_threads.len() < threads.len() {
+        let referenced: BTreeSet<DebugId> = selected_threads
+            .iter()
+            .flat_map(|(_, thread)| &thread.frames)
+            .filter_map(|frame| frame.module.as_ref()?.debug_identifier())
+            .collect();
+        missing_modules.retain(|id| referenced.contains(id));
+    }
+
+    // Finally iterate through the threads and build the stacktraces to
+    // return, marking modules as used when they are referenced by a frame.
+    let mut stacktraces = Vec::with_capacity(selected_threads.len());
+    for &(index, thread) in &selected_threads {
         let registers = match thread.frames.get(0) {
             Some(frame) => map_symbolic_registers(&frame.context),
             None => Registers::new(),
@@ -441,13 +511,17 @@ async fn stackwalk(
         // correlate to minidump size. Every other kind of bloated
         // input data we know is already trimmed/rejected by raw
         // byte size alone.
-        let frame_count = thread.frames.len().min(20000);
+        let frame_count = thread.frames.len().min(params.max_frames);
         let mut frames = Vec::with_capacity(frame_count);
         for frame in thread.frames.iter().take(frame_count) {
             frames.push(RawFrame {
                 instruction_addr: HexValue(frame.resume_address),
                 package: frame.module.as_ref().map(|m| m.code_file().into_owned()),
                 trust: frame.trust.into(),
+                registers: match params.frame_registers {
+                    true => map_symbolic_registers(&frame.context),
+                    false => Registers::new(),
+                },
                 ..RawFrame::default()
             });
         }
@@ -457,9 +531,41 @@ async fn stackwalk(
             thread_id: Some(thread.thread_id.into()),
             registers,
             frames,
+            ..RawStacktrace::default()
         });
     }
 
+    if let Some(options) = params.stack_memory {
+        match minidump.get_stream::<MinidumpMemoryList>() {
+            Ok(memory_list) => {
+                let window_size = options.window_size.min(MAX_MEMORY_WINDOW_SIZE);
+                let mut budget = options.max_bytes.min(MAX_MEMORY_BYTES);
+
+                // Give the requesting thread the first chance at the memory budget.
+                let requesting_position = selected_threads
+                    .iter()
+                    .position(|(index, _)| Some(*index) == requesting_thread_index);
+                let order = requesting_position
+                    .into_iter()
+                    .chain((0..selected_threads.len()).filter(|p| Some(*p) != requesting_position));
+                for position in order {
+                    let (index, thread) = selected_threads[position];
+                    let crash_address = process_state
+                        .crash_address
+                        .filter(|_| Some(index) == requesting_thread_index);
+                    stacktraces[position].memory = collect_memory_windows(
+                        &memory_list,
+                        thread,
+                        crash_address,
+                        window_size,
+                        &mut budget,
+                    );
+                }
+            }
+            Err(err) => tracing::debug!("Minidump has no memory list: {}", err),
+        }
+    }
+
     Ok(StackWalkMinidumpResult {
         modules,
         missing_modules,
@@ -469,6 +575,109 @@ async fn stackwalk(
     })
 }
 
+/// Creates the object info for a module which was unloaded before the crash.
+///
+/// The unloaded module list does not record debug identifiers.  For PE images, the code ID
+/// can be reconstructed from the image timestamp and size, which allows finding the
+/// executable and its debug file on symbol servers.
+fn object_info_from_unloaded_module(ty: ObjectType, module: &UnloadedModule) -> RawObjectInfo {
+    let code_id = match ty {
+        ObjectType::Pe if module.timestamp != 0 => {
+            Some(format!("{:08x}{:x}", module.timestamp, module.image_size))
+        }
+        _ => None,
+    };
+
+    RawObjectInfo {
+        ty,
+        code_id,
+        code_file: Some(module.code_file.clone()).filter(|file| !file.is_empty()),
+        debug_id: None,
+        debug_file: None,
+        image_addr: module.image_addr,
+        image_size: Some(module.image_size).filter(|size| *size != 0),
+        unloaded: true,
+    }
+}
+
+/// Collects windows of memory around the interesting addresses of a thread.
+///
+/// Windows are centered around the stack pointers of all frames, the crash address and the
+/// register values of the top frame, in this order.  Only memory contained in the minidump's
+/// memory list is returned, and overlapping windows are merged.  Windows are added until the
+/// `budget` is exhausted, the returned size is subtracted from it.
+fn collect_memory_windows(
+    memory_list: &MinidumpMemoryList,
+    thread: &CallStack,
+    crash_address: Option<u64>,
+    window_size: u64,
+    budget: &mut u64,
+) -> Vec<MemoryWindow> {
+    let stack_pointers = thread
+        .frames
+        .iter()
+        .map(|frame| frame.context.get_stack_pointer());
+    let registers = thread
+        .frames
+        .get(0)
+        .into_iter()
+        .flat_map(|frame| frame.context.valid_registers().map(|(_, value)| value));
+    let addresses = stack_pointers.chain(crash_address).chain(registers);
+
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for address in addresses {
+        if *budget == 0 {
+            break;
+        }
+
+        let memory = match memory_list.memory_at_address(address) {
+            Some(memory) => memory,
+            None => continue,
+        };
+
+        let region_end = memory.base_address.saturating_add(memory.size);
+        let start = address
+            .saturating_sub(window_size / 2)
+            .max(memory.base_address);
+        let end = start.saturating_add(window_size).min(region_end);
+
+        // Only account for the bytes not already covered by a previous window.
+        let covered: u64 = ranges
+            .iter()
+            .map(|&(s, e)| end.min(e).saturating_sub(start.max(s)))
+            .sum();
+        let size = (end - start).saturating_sub(covered);
+        if size == 0 || size > *budget {
+            continue;
+        }
+
+        *budget -= size;
+        ranges.push((start, end));
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start < last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    merged
+        .into_iter()
+        .filter_map(|(start, end)| {
+            let memory = memory_list.memory_at_address(start)?;
+            let offset = (start - memory.base_address) as usize;
+            let bytes = memory.bytes.get(offset..offset + (end - start) as usize)?;
+            Some(MemoryWindow {
+                start_addr: HexValue(start),
+                data: base64::encode(bytes),
+            })
+        })
+        .collect()
+}
+
 /// Builder object to collect modules from the minidump.
 ///
 /// This collects the modules found by the minidump processor and constructs the
@@ -514,6 +723,45 @@ impl ModuleListBuilder {
         Self { inner }
     }
 
+    /// Adds modules which were unloaded before the crash.
+    ///
+    /// The address range of an unloaded module may have been reused by another module since,
+    /// so unloaded modules overlapping any other module are skipped.  Like other modules without
+    /// a debug ID, they are only included in the final list if they are referenced by a frame.
+    fn add_unloaded_modules(&mut self, modules: Vec<RawObjectInfo>) {
+        fn range(info: &RawObjectInfo) -> (u64, u64) {
+            let HexValue(start) = info.image_addr;
+            match info.image_size {
+                Some(size) => (start, start.saturating_add(size)),
+                // Modules without size implicitly extend up to the next module.
+                None => (start, u64::MAX),
+            }
+        }
+
+        for raw_info in modules {
+            if raw_info.image_size.is_none() {
+                continue;
+            }
+
+            let (start, end) = range(&raw_info);
+            let overlaps = self.inner.iter().any(|(info, _)| {
+                let (other_start, other_end) = range(&info.raw);
+                start < other_end && other_start < end
+            });
+            if overlaps {
+                continue;
+            }
+
+            let mut obj_info: CompleteObjectInfo = raw_info.into();
+            // We never fetch CFI for unloaded modules, the stackwalker does not know about them.
+            obj_info.unwind_status = Some(ObjectFileStatus::Unused);
+            self.inner.push((obj_info, false));
+        }
+
+        // Sort by image address for binary search in `mark`.
+        self.inner.sort_by_key(|(info, _)| info.raw.image_addr);
+    }
+
     /// Finds the index of the module (for lookup in `self.modules`) that covers the gives `addr`.
     fn find_module_index(&self, addr: u64) -> Option<usize> {
         let search_index = self
@@ -625,11 +873,26 @@ impl SymbolicationActor {
     }
 
     /// Saves the given `minidump_file` in the diagnostics cache if configured to do so.
+    ///
+    /// The minidump is sanitized first if enabled in the configuration.  Minidumps which
+    /// cannot be sanitized are saved unmodified, unless configured to discard them.
     fn maybe_persist_minidump(&self, minidump_file: TempPath) {
         if let Some(dir) = self.diagnostics_cache.cache_dir() {
             if let Some(file_name) = minidump_file.file_name() {
                 let path = dir.join(file_name);
-                match minidump_file.persist(&path) {
+                let config = &self.sanitizer_config;
+                let result = if config.diagnostics {
+                    match persist_sanitized_minidump(&minidump_file, &path, config) {
+                        Err(e) if !config.discard_unsanitized => {
+                            tracing::warn!("Failed to sanitize minidump, saving it as is: {:?}", e);
+                            minidump_file.persist(&path).map_err(anyhow::Error::from)
+                        }
+                        result => result,
+                    }
+                } else {
+                    minidump_file.persist(&path).map_err(anyhow::Error::from)
+                };
+                match result {
                     Ok(_) => {
                         sentry::configure_scope(|scope| {
                             scope.set_extra(
@@ -646,23 +909,110 @@ impl SymbolicationActor {
         }
     }
 
-    async fn do_process_minidump(
+    /// Returns a sanitized copy of the given `minidump_file`.
+    ///
+    /// See [`sanitize_minidump`] for the data which is scrubbed.
+    pub async fn sanitize_minidump(&self, minidump_file: TempPath) -> anyhow::Result<Vec<u8>> {
+        let config = self.sanitizer_config.clone();
+        let future = async move {
+            let minidump = ByteView::open(&minidump_file)?;
+            Ok::<_, anyhow::Error>(sanitize_minidump(&minidump, &config)?)
+        };
+
+        CancelOnDrop::new(self.cpu_pool.spawn(future.bind_hub(sentry::Hub::current())))
+            .await
+            .context("Sanitize minidump future cancelled")?
+    }
+
+    pub(super) async fn do_process_minidump(
         &self,
         scope: Scope,
         minidump_file: TempPath,
         sources: Arc<[SourceConfig]>,
         options: RequestOptions,
     ) -> Result<CompletedSymbolicationResponse, SymbolicationError> {
-        let (request, state) = self
+        let (response, _minidump_file) = self
+            .do_process_minidump_file(scope, minidump_file, sources, options)
+            .await?;
+        Ok(response)
+    }
+
+    /// Processes a minidump and returns the symbolication response and the minidump file.
+    async fn do_process_minidump_file(
+        &self,
+        scope: Scope,
+        minidump_file: TempPath,
+        sources: Arc<[SourceConfig]>,
+        options: RequestOptions,
+    ) -> Result<(CompletedSymbolicationResponse, TempPath), SymbolicationError> {
+        let (request, state, minidump_file) = self
             .do_stackwalk_minidump(scope, minidump_file, sources, options)
             .await?;
 
         let mut response = self.do_symbolicate(request).await?;
         state.merge_into(&mut response);
 
+        Ok((response, minidump_file))
+    }
+
+    /// Processes a minidump and embeds the symbolicated stacktraces into a copy of it.
+    ///
+    /// The frames of all threads are written to the Sentry stackwalk extension stream, see
+    /// [`write_stacktraces_to_minidump`].  The copy is returned in the `symbolicated_minidump`
+    /// of the response.
+    async fn do_symbolicate_minidump(
+        &self,
+        scope: Scope,
+        minidump_file: TempPath,
+        sources: Arc<[SourceConfig]>,
+        options: RequestOptions,
+    ) -> Result<CompletedSymbolicationResponse, SymbolicationError> {
+        let (mut response, minidump_file) = self
+            .do_process_minidump_file(scope, minidump_file, sources, options)
+            .await?;
+
+        let stacktraces = response.stacktraces.clone();
+        let minidump = tokio::task::spawn_blocking(move || {
+            let minidump = ByteView::open(&minidump_file)?;
+            let symbolicated = write_stacktraces_to_minidump(&minidump, &stacktraces)?;
+            Ok::<_, anyhow::Error>(symbolicated)
+        })
+        .await
+        .context("Symbolicated minidump task failed")??;
+
+        response.symbolicated_minidump = Some(minidump.into());
         Ok(response)
     }
 
+    /// Creates a new request to process a minidump and embed the symbolicated stacktraces.
+    ///
+    /// Returns `None` if the `SymbolicationActor` is already processing the
+    /// maximum number of requests, as given by `max_concurrent_requests`.
+    pub fn symbolicate_minidump(
+        &self,
+        scope: Scope,
+        minidump_file: TempPath,
+        sources: Arc<[SourceConfig]>,
+        options: RequestOptions,
+    ) -> Result<RequestId, MaxRequestsError> {
+        let slf = self.clone();
+        let span = sentry::configure_scope(|scope| scope.get_span());
+        let ctx = sentry::TransactionContext::continue_from_span(
+            "symbolicate_minidump",
+            "symbolicate_minidump",
+            span,
+        );
+        self.create_symbolication_request(async move {
+            let transaction = sentry::start_transaction(ctx);
+            sentry::configure_scope(|scope| scope.set_span(Some(transaction.clone().into())));
+            let res = slf
+                .do_symbolicate_minidump(scope, minidump_file, sources, options)
+                .await;
+            transaction.finish();
+            res
+        })
+    }
+
     /// Creates a new request to process a minidump.
     ///
     /// Returns `None` if the `SymbolicationActor` is already processing the
@@ -699,6 +1049,8 @@ impl SymbolicationActor {
         minidump_path: &Path,
         sources: Arc<[SourceConfig]>,
         cfi_caches: &mut CfiCacheModules,
+        options: &RequestOptions,
+        limits: &StackwalkConfig,
     ) -> anyhow::Result<StackWalkMinidumpResult> {
         let mut iterations = 0;
 
@@ -708,14 +1060,28 @@ impl SymbolicationActor {
             iterations += 1;
 
             let mut result = {
-                let return_modules = modules.is_none();
+                let params = StackwalkParams {
+                    return_modules: modules.is_none(),
+                    stack_memory: options.stack_memory,
+                    frame_registers: options.frame_registers,
+                    max_frames: limits.max_frames,
+                    requesting_thread_only: options.stackwalk.requesting_thread_only,
+                };
                 let cfi_caches = cfi_caches.for_processing();
                 let minidump_path = minidump_path.to_path_buf();
+                let timeout = limits.iteration_timeout;
 
-                let future = stackwalk(cfi_caches, minidump_path, return_modules)
-                    .bind_hub(sentry::Hub::current());
-                tokio::time::timeout(Duration::from_secs(60), self.cpu_pool.spawn(future))
-                    .await???
+                match &self.stackwalk_pool {
+                    Some(pool) => {
+                        pool.stackwalk(cfi_caches, minidump_path, params, timeout)
+                            .await?
+                    }
+                    None => {
+                        let future = stackwalk(cfi_caches, minidump_path, params)
+                            .bind_hub(sentry::Hub::current());
+                        tokio::time::timeout(timeout, self.cpu_pool.spawn(future)).await???
+                    }
+                }
             };
 
             metric!(timer("minidump.stackwalk.duration") = result.duration);
@@ -731,9 +1097,9 @@ impl SymbolicationActor {
                 }
             };
 
-            // We put a hard limit of 5 iterations here.
+            // The number of iterations is limited by the configuration.
             // Previously, it was two, once scanning for referenced modules, then doing the stackwalk
-            if result.missing_modules.is_empty() || iterations >= 5 {
+            if result.missing_modules.is_empty() || iterations >= limits.max_iterations {
                 break result;
             }
 
@@ -769,7 +1135,9 @@ impl SymbolicationActor {
         minidump_file: TempPath,
         sources: Arc<[SourceConfig]>,
         options: RequestOptions,
-    ) -> Result<(SymbolicateStacktraces, MinidumpState), SymbolicationError> {
+    ) -> Result<(SymbolicateStacktraces, MinidumpState, TempPath), SymbolicationError> {
+        let limits = stackwalk_limits(&self.stackwalk_config, &options.stackwalk);
+
         let future = async move {
             let len = minidump_file.metadata()?.len();
             tracing::debug!("Processing minidump ({} bytes)", len);
@@ -782,6 +1150,8 @@ impl SymbolicationActor {
                 &minidump_file,
                 sources.clone(),
                 &mut cfi_caches,
+                &options,
+                &limits,
             );
 
             let result = match future.await {
@@ -795,22 +1165,38 @@ impl SymbolicationActor {
             let StackWalkMinidumpResult {
                 modules,
                 mut stacktraces,
-                minidump_state,
+                mut minidump_state,
                 ..
             } = result;
 
-            match parse_stacktraces_from_minidump(&ByteView::open(&minidump_file)?) {
-                Ok(Some(client_stacktraces)) => merge_clientside_with_processed_stacktraces(
-                    &mut stacktraces,
-                    client_stacktraces,
-                ),
+            let minidump = ByteView::open(&minidump_file)?;
+            match parse_stacktraces_from_minidump(&minidump) {
+                Ok(Some(client_stacktraces)) => {
+                    stacktraces = merge_clientside_with_processed_stacktraces(
+                        stacktraces,
+                        client_stacktraces,
+                        options.stack_merge,
+                    )
+                }
                 Err(e) => tracing::error!("invalid minidump extension: {}", e),
                 _ => (),
             }
 
+            minidump_state.streams = extract_minidump_streams(
+                &minidump,
+                minidump_state.object_type(),
+                &options.minidump_sections,
+            );
+
             // Start building the module list for the symbolication response.
             let mut module_builder =
                 ModuleListBuilder::new(cfi_caches, modules.unwrap_or_default());
+            let object_type = minidump_state.object_type();
+            let unloaded_modules = read_unloaded_modules(&minidump)
+                .iter()
+                .map(|module| object_info_from_unloaded_module(object_type, module))
+                .collect();
+            module_builder.add_unloaded_modules(unloaded_modules);
             module_builder.process_stacktraces(&stacktraces);
 
             let request = SymbolicateStacktraces {
@@ -820,13 +1206,14 @@ impl SymbolicationActor {
                 origin: StacktraceOrigin::Minidump,
                 signal: None,
                 stacktraces,
+                crash_address: minidump_state.crash_address,
                 options,
             };
 
-            Ok::<_, anyhow::Error>((request, minidump_state))
+            Ok::<_, anyhow::Error>((request, minidump_state, minidump_file))
         };
 
-        let future = tokio::time::timeout(Duration::from_secs(3600), future);
+        let future = tokio::time::timeout(limits.timeout, future);
         let future = measure("minidump_stackwalk", m::timed_result, None, future);
         future
             .await
@@ -835,34 +1222,130 @@ impl SymbolicationActor {
     }
 }
 
+/// Writes a sanitized copy of `minidump_file` to `path`.
+fn persist_sanitized_minidump(
+    minidump_file: &Path,
+    path: &Path,
+    config: &MinidumpSanitizerConfig,
+) -> anyhow::Result<()> {
+    let minidump = ByteView::open(minidump_file)?;
+    let sanitized = sanitize_minidump(&minidump, config)?;
+    std::fs::write(path, sanitized)?;
+    Ok(())
+}
+
+/// Applies the per-request overrides in `options` to the configured stackwalk limits.
+///
+/// Overrides are capped at the configured `max_requested_*` values, which default to the
+/// configured limits themselves.
+fn stackwalk_limits(config: &StackwalkConfig, options: &StackwalkOptions) -> StackwalkConfig {
+    fn limit<T: Ord + Copy>(configured: T, maximum: Option<T>, requested: Option<T>) -> T {
+        let maximum = maximum.unwrap_or(configured);
+        requested.map_or(configured, |requested| requested.min(maximum))
+    }
+
+    StackwalkConfig {
+        max_iterations: limit(
+            config.max_iterations,
+            config.max_requested_iterations,
+            options.max_iterations,
+        ),
+        iteration_timeout: limit(
+            config.iteration_timeout,
+            config.max_requested_iteration_timeout,
+            options.iteration_timeout,
+        ),
+        timeout: limit(
+            config.timeout,
+            config.max_requested_timeout,
+            options.timeout,
+        ),
+        max_frames: limit(
+            config.max_frames,
+            config.max_requested_frames,
+            options.max_frames,
+        ),
+        ..*config
+    }
+}
+
 /// Merges the stacktraces processed via rust-minidump with the ones captured on the client.
 ///
-/// For now, this means we will prefer the client-side stack trace over the processed one, but in
-/// the future we could be a bit smarter about what to do.
+/// Every returned stacktrace records its [`StacktraceProvenance`].  Which of the two stacktraces
+/// of a thread is returned depends on the [`StackMergeStrategy`].
 fn merge_clientside_with_processed_stacktraces(
-    processed_stacktraces: &mut [RawStacktrace],
+    processed_stacktraces: Vec<RawStacktrace>,
     clientside_stacktraces: Vec<RawStacktrace>,
-) {
+    strategy: StackMergeStrategy,
+) -> Vec<RawStacktrace> {
     let mut client_traces_by_id: HashMap<_, _> = clientside_stacktraces
         .into_iter()
+        .filter(|trace| !trace.frames.is_empty())
         .filter_map(|trace| trace.thread_id.map(|thread_id| (thread_id, trace)))
         .collect();
 
-    for thread in processed_stacktraces {
-        if let Some(thread_id) = thread.thread_id {
-            if let Some(client_thread) = client_traces_by_id.remove(&thread_id) {
-                // NOTE: we could gather all kinds of metrics here, as in:
-                // - are we finding more or less frames via CFI?
-                // - how many frames are the same
-                // - etc.
-                // We could also be a lot smarter about which threads/frames we chose. For now we
-                // will just always prefer client-side stack traces
-                if !client_thread.frames.is_empty() {
-                    thread.frames = client_thread.frames;
-                }
+    let mut merged = Vec::with_capacity(processed_stacktraces.len());
+    for mut thread in processed_stacktraces {
+        thread.provenance = Some(StacktraceProvenance::Server);
+
+        let client_thread = thread
+            .thread_id
+            .and_then(|thread_id| client_traces_by_id.remove(&thread_id));
+        let client_thread = match client_thread {
+            Some(client_thread) => client_thread,
+            None => {
+                merged.push(thread);
+                continue;
+            }
+        };
+
+        let use_client = match strategy {
+            StackMergeStrategy::PreferClient => true,
+            StackMergeStrategy::PreferServer => false,
+            StackMergeStrategy::Auto => {
+                cfi_coverage(&thread.frames, client_thread.frames.len()) < MIN_SERVER_CFI_COVERAGE
+            }
+            StackMergeStrategy::Both => {
+                let client_thread = RawStacktrace {
+                    thread_id: thread.thread_id,
+                    is_requesting: thread.is_requesting,
+                    frames: client_thread.frames,
+                    provenance: Some(StacktraceProvenance::Client),
+                    ..RawStacktrace::default()
+                };
+                merged.push(thread);
+                merged.push(client_thread);
+                continue;
             }
+        };
+
+        if use_client {
+            thread.frames = client_thread.frames;
+            thread.provenance = Some(StacktraceProvenance::Client);
         }
+        merged.push(thread);
     }
+
+    merged
+}
+
+/// The [`cfi_coverage`] a server-side stacktrace needs for [`StackMergeStrategy::Auto`] to
+/// prefer it over the client-side one.
+const MIN_SERVER_CFI_COVERAGE: f64 = 0.8;
+
+/// Returns the number of server-side frames recovered from the CPU context or via CFI,
+/// relative to the depth of the client-side stacktrace.
+///
+/// Frames found by scanning the stack or following frame pointers are likely to be wrong or
+/// to miss callers, so a server-side stacktrace mostly made up of them is worse than the
+/// client-side one.  A coverage of `1.0` or more means that stackwalking reliably recovered
+/// at least as many frames as the client.
+fn cfi_coverage(frames: &[RawFrame], client_frames: usize) -> f64 {
+    let reliable = frames
+        .iter()
+        .filter(|frame| matches!(frame.trust, FrameTrust::Context | FrameTrust::Cfi))
+        .count();
+    reliable as f64 / client_frames.max(1) as f64
 }
 
 fn map_symbolic_registers(context: &MinidumpContext) -> BTreeMap<String, HexValue> {
@@ -901,6 +1384,7 @@ mod tests {
     use crate::types::Scope;
     use crate::types::{
         CompleteObjectInfo, ObjectFileStatus, ObjectType, RawObjectInfo, RequestOptions,
+        SymbolicationResponse,
     };
     use crate::utils::hex::HexValue;
 
@@ -923,6 +1407,7 @@ mod tests {
             debug_file: None,
             image_addr: HexValue(addr),
             image_size: size,
+            unloaded: false,
         }
         .into();
         info.unwind_status = Some(ObjectFileStatus::Unused);
@@ -1030,12 +1515,44 @@ mod tests {
         assert_eq!(valid, vec![valid_object]);
     }
 
+    #[test]
+    fn test_code_module_builder_unloaded() {
+        let loaded_object = create_object_info(true, 0x1000, Some(0x1000));
+        let unloaded = |addr| UnloadedModule {
+            code_file: "C:\\Windows\\System32\\unloaded.dll".into(),
+            image_addr: HexValue(addr),
+            image_size: 0x1000,
+            timestamp: 0x5ab38ae0,
+            ..Default::default()
+        };
+
+        let mut builder = ModuleListBuilder {
+            inner: vec![(loaded_object.clone(), false)],
+        };
+        builder.add_unloaded_modules(vec![
+            // overlaps with the loaded module, the address range was reused
+            object_info_from_unloaded_module(ObjectType::Pe, &unloaded(0x1800)),
+            object_info_from_unloaded_module(ObjectType::Pe, &unloaded(0x3000)),
+        ]);
+        builder.mark_referenced(0x1800, false);
+        builder.mark_referenced(0x3800, false);
+
+        let valid = builder.build();
+        assert_eq!(valid.len(), 2);
+        assert_eq!(valid[0], loaded_object);
+        assert!(valid[1].raw.unloaded);
+        assert_eq!(valid[1].raw.image_addr, HexValue(0x3000));
+        assert_eq!(valid[1].raw.code_id.as_deref(), Some("5ab38ae01000"));
+        assert_eq!(valid[1].unwind_status, Some(ObjectFileStatus::Unused));
+    }
+
     macro_rules! stackwalk_minidump {
         ($path:expr) => {{
             stackwalk_minidump!(
                 $path,
                 RequestOptions {
                     dif_candidates: true,
+                    ..Default::default()
                 }
             )
         }};
@@ -1085,4 +1602,273 @@ mod tests {
     async fn test_minidump_linux() -> anyhow::Result<()> {
         stackwalk_minidump!("linux.dmp").await
     }
+
+    #[tokio::test]
+    async fn test_symbolicated_minidump() -> anyhow::Result<()> {
+        let (service, _cache_dir) = setup_service().await;
+        let symbolication = service.symbolication();
+        let (_symsrv, source) = test::symbol_server();
+
+        let sources: Arc<[SourceConfig]> = Arc::new([source]);
+        let mut minidump_file = NamedTempFile::new()?;
+        minidump_file.write_all(&test::read_fixture("linux.dmp"))?;
+        let request_id = symbolication.process_minidump(
+            Scope::Global,
+            minidump_file.into_temp_path(),
+            sources.clone(),
+            RequestOptions::default(),
+        )?;
+
+        let response = match symbolication.get_response(request_id, None).await {
+            Some(SymbolicationResponse::Completed(response)) => response,
+            other => panic!("unexpected response: {:?}", other),
+        };
+
+        let mut minidump_file = NamedTempFile::new()?;
+        minidump_file.write_all(&test::read_fixture("linux.dmp"))?;
+        let request_id = symbolication.symbolicate_minidump(
+            Scope::Global,
+            minidump_file.into_temp_path(),
+            sources,
+            RequestOptions::default(),
+        )?;
+
+        let minidump = match symbolication.get_response(request_id, None).await {
+            Some(SymbolicationResponse::Completed(response)) => response.symbolicated_minidump,
+            other => panic!("unexpected response: {:?}", other),
+        };
+        let minidump = minidump.unwrap();
+
+        let embedded = parse_stacktraces_from_minidump(&minidump)?.unwrap();
+        assert_eq!(embedded.len(), response.stacktraces.len());
+        for (embedded, stacktrace) in embedded.iter().zip(&response.stacktraces) {
+            assert_eq!(embedded.thread_id, stacktrace.thread_id);
+            assert_eq!(embedded.frames.len(), stacktrace.frames.len());
+        }
+
+        Ok(())
+    }
+
+    fn raw_stacktrace(thread_id: u64, trust: &[FrameTrust]) -> RawStacktrace {
+        let frames = trust
+            .iter()
+            .enumerate()
+            .map(|(index, trust)| RawFrame {
+                instruction_addr: HexValue(0x1000 + index as u64),
+                trust: *trust,
+                ..RawFrame::default()
+            })
+            .collect();
+
+        RawStacktrace {
+            thread_id: Some(thread_id),
+            frames,
+            ..RawStacktrace::default()
+        }
+    }
+
+    fn merge(strategy: StackMergeStrategy) -> Vec<RawStacktrace> {
+        let processed = vec![
+            raw_stacktrace(1, &[FrameTrust::Context, FrameTrust::Cfi, FrameTrust::Cfi]),
+            raw_stacktrace(2, &[FrameTrust::Context, FrameTrust::Scan]),
+            raw_stacktrace(3, &[FrameTrust::Context]),
+        ];
+        let clientside = vec![
+            raw_stacktrace(1, &[FrameTrust::PreWalked, FrameTrust::PreWalked]),
+            raw_stacktrace(2, &[FrameTrust::PreWalked, FrameTrust::PreWalked]),
+            raw_stacktrace(3, &[]),
+        ];
+        merge_clientside_with_processed_stacktraces(processed, clientside, strategy)
+    }
+
+    fn provenance(stacktraces: &[RawStacktrace]) -> Vec<(u64, StacktraceProvenance)> {
+        stacktraces
+            .iter()
+            .map(|thread| (thread.thread_id.unwrap(), thread.provenance.unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_stacktraces() {
+        use StacktraceProvenance::*;
+
+        assert_eq!(
+            provenance(&merge(StackMergeStrategy::PreferClient)),
+            [(1, Client), (2, Client), (3, Server)]
+        );
+        assert_eq!(
+            provenance(&merge(StackMergeStrategy::PreferServer)),
+            [(1, Server), (2, Server), (3, Server)]
+        );
+        assert_eq!(
+            provenance(&merge(StackMergeStrategy::Auto)),
+            [(1, Server), (2, Client), (3, Server)]
+        );
+
+        let both = merge(StackMergeStrategy::Both);
+        assert_eq!(
+            provenance(&both),
+            [
+                (1, Server),
+                (1, Client),
+                (2, Server),
+                (2, Client),
+                (3, Server)
+            ]
+        );
+        assert_eq!(both[0].frames.len(), 3);
+        assert_eq!(both[1].frames.len(), 2);
+        assert_eq!(both[1].frames[0].trust, FrameTrust::PreWalked);
+    }
+
+    #[test]
+    fn test_merge_stacktraces_auto_mixed_trust() {
+        use FrameTrust::*;
+        use StacktraceProvenance::*;
+
+        let processed = vec![
+            // A single scanned frame amid CFI frames.
+            raw_stacktrace(1, &[Context, Cfi, Cfi, Scan, Cfi, Cfi]),
+            // Stackwalking lost CFI after the second frame.
+            raw_stacktrace(2, &[Context, Cfi, Scan, Scan, Fp, Scan]),
+            // Fully unwound via CFI, but shorter than the client-side stacktrace.
+            raw_stacktrace(3, &[Context, Cfi, Cfi]),
+        ];
+        let clientside = vec![
+            raw_stacktrace(1, &[PreWalked; 6]),
+            raw_stacktrace(2, &[PreWalked; 6]),
+            raw_stacktrace(3, &[PreWalked; 6]),
+        ];
+
+        let merged = merge_clientside_with_processed_stacktraces(
+            processed,
+            clientside,
+            StackMergeStrategy::Auto,
+        );
+        assert_eq!(provenance(&merged), [(1, Server), (2, Client), (3, Client)]);
+    }
+
+    #[tokio::test]
+    async fn test_stackwalk_stack_memory() -> anyhow::Result<()> {
+        let params = StackwalkParams {
+            stack_memory: Some(StackMemoryOptions {
+                window_size: 64,
+                max_bytes: 1024,
+            }),
+            ..StackwalkParams::default()
+        };
+        let result = stackwalk(Vec::new(), test::fixture("linux.dmp"), params).await?;
+
+        let requesting = result
+            .stacktraces
+            .iter()
+            .find(|thread| thread.is_requesting == Some(true))
+            .unwrap();
+        assert!(!requesting.memory.is_empty());
+
+        let total: usize = result
+            .stacktraces
+            .iter()
+            .flat_map(|thread| &thread.memory)
+            .map(|window| base64::decode(&window.data).unwrap().len())
+            .sum();
+        assert!(total <= 1024);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stackwalk_frame_registers() -> anyhow::Result<()> {
+        let params = StackwalkParams {
+            frame_registers: true,
+            ..StackwalkParams::default()
+        };
+        let result = stackwalk(Vec::new(), test::fixture("linux.dmp"), params).await?;
+
+        for thread in &result.stacktraces {
+            if let Some(top_frame) = thread.frames.first() {
+                assert_eq!(top_frame.registers, thread.registers);
+            }
+        }
+        assert!(result
+            .stacktraces
+            .iter()
+            .flat_map(|thread| thread.frames.iter().skip(1))
+            .any(|frame| !frame.registers.is_empty()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stackwalk_requesting_thread_only() -> anyhow::Result<()> {
+        let params = StackwalkParams {
+            requesting_thread_only: true,
+            max_frames: 2,
+            ..StackwalkParams::default()
+        };
+        let result = stackwalk(Vec::new(), test::fixture("linux.dmp"), params).await?;
+
+        assert_eq!(result.stacktraces.len(), 1);
+        assert_eq!(result.stacktraces[0].is_requesting, Some(true));
+        assert!(result.stacktraces[0].frames.len() <= 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stackwalk_result_roundtrip() -> anyhow::Result<()> {
+        // Results are sent from isolated stackwalk workers as JSON.
+        let result = stackwalk(
+            Vec::new(),
+            test::fixture("linux.dmp"),
+            StackwalkParams::default(),
+        )
+        .await?;
+
+        let json = serde_json::to_string(&result)?;
+        let roundtrip: StackWalkMinidumpResult = serde_json::from_str(&json)?;
+        assert_eq!(
+            serde_json::to_value(&roundtrip)?,
+            serde_json::to_value(&result)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stackwalk_limits() {
+        let config = StackwalkConfig::default();
+        let options = StackwalkOptions {
+            max_iterations: Some(2),
+            timeout: Some(config.timeout * 2),
+            ..Default::default()
+        };
+
+        let limits = stackwalk_limits(&config, &options);
+        assert_eq!(limits.max_iterations, 2);
+        assert_eq!(limits.timeout, config.timeout);
+        assert_eq!(limits.iteration_timeout, config.iteration_timeout);
+        assert_eq!(limits.max_frames, config.max_frames);
+
+        let config = StackwalkConfig {
+            max_frames: 100,
+            max_requested_frames: Some(1000),
+            ..config
+        };
+        let options = StackwalkOptions {
+            max_frames: Some(500),
+            timeout: Some(config.timeout * 2),
+            ..Default::default()
+        };
+
+        let limits = stackwalk_limits(&config, &options);
+        assert_eq!(limits.max_frames, 500);
+        assert_eq!(limits.timeout, config.timeout);
+
+        let options = StackwalkOptions {
+            max_frames: Some(5000),
+            ..Default::default()
+        };
+        assert_eq!(stackwalk_limits(&config, &options).max_frames, 1000);
+    }
 }
@@ -0,0 +1,502 @@
+//! Stackwalking of minidumps in isolated worker subprocesses.
+//!
+//! Minidumps are untrusted input, and a pathological minidump can make the stackwalker panic,
+//! abort or exhaust all memory.  The [`StackwalkPool`] runs stackwalking in a pool of worker
+//! subprocesses instead, so that only the worker is lost in that case.
+//!
+//! Workers are started as `symbolicator stackwalk-worker` and read [`WorkerRequest`]s as JSON
+//! lines from `stdin`, answering each with a [`WorkerResponse`] as a JSON line on `stdout`.
+//! Workers which crash or time out are killed and replaced by a new worker on the next request.
+
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use parking_lot::Mutex;
+use sentry::types::DebugId;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Semaphore;
+
+use crate::config::StackwalkConfig;
+
+use super::process_minidump::{stackwalk, StackWalkMinidumpResult, StackwalkParams};
+
+/// A request to stackwalk a minidump, sent to a worker.
+#[derive(Debug, Serialize, Deserialize)]
+struct WorkerRequest {
+    cfi_caches: Vec<(DebugId, PathBuf)>,
+    minidump_path: PathBuf,
+    params: StackwalkParams,
+}
+
+/// The response of a worker to a [`WorkerRequest`].
+#[derive(Debug, Serialize, Deserialize)]
+enum WorkerResponse {
+    Ok(StackWalkMinidumpResult),
+    Err(String),
+}
+
+/// Serializes a message as a single JSON line.
+fn encode_message<T: Serialize>(message: &T) -> serde_json::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// The program and arguments which start a worker subprocess.
+#[derive(Clone, Debug)]
+struct WorkerCommand {
+    /// The worker executable, or `None` for the current executable.
+    program: Option<PathBuf>,
+    args: Vec<String>,
+}
+
+impl WorkerCommand {
+    /// Starts `symbolicator stackwalk-worker` with the resource limits from `config`.
+    fn stackwalk_worker(config: &StackwalkConfig) -> Self {
+        let mut args = vec!["stackwalk-worker".to_owned()];
+        if let Some(limit) = config.worker_memory_limit {
+            args.push("--memory-limit".to_owned());
+            args.push(limit.to_string());
+        }
+        if let Some(limit) = config.worker_cpu_limit {
+            args.push("--cpu-limit".to_owned());
+            args.push(limit.as_secs().max(1).to_string());
+        }
+
+        Self {
+            program: None,
+            args,
+        }
+    }
+}
+
+/// A running worker subprocess.
+#[derive(Debug)]
+struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Worker {
+    /// Spawns a new worker.
+    fn spawn(command: &WorkerCommand) -> io::Result<Self> {
+        let program = match command.program {
+            Some(ref program) => program.clone(),
+            None => std::env::current_exe()?,
+        };
+
+        let mut child = Command::new(program)
+            .args(&command.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let stdin = child.stdin.take().expect("worker stdin is piped");
+        let stdout = child.stdout.take().expect("worker stdout is piped");
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+        })
+    }
+
+    /// Sends a request to the worker and waits for its response.
+    async fn request(&mut self, request: &WorkerRequest) -> anyhow::Result<WorkerResponse> {
+        let line = encode_message(request)?;
+        self.stdin.write_all(&line).await?;
+        self.stdin.flush().await?;
+
+        let mut response = String::new();
+        if self.stdout.read_line(&mut response).await? == 0 {
+            let status = self.child.wait().await?;
+            anyhow::bail!("stackwalk worker exited unexpectedly: {}", status);
+        }
+
+        serde_json::from_str(&response).context("invalid response from stackwalk worker")
+    }
+}
+
+#[derive(Debug)]
+struct PoolInner {
+    idle: Mutex<Vec<Worker>>,
+    permits: Semaphore,
+    command: WorkerCommand,
+}
+
+/// A pool of worker subprocesses for stackwalking minidumps.
+///
+/// Workers are spawned lazily, up to the size of the pool.
+#[derive(Clone, Debug)]
+pub struct StackwalkPool {
+    inner: Arc<PoolInner>,
+}
+
+impl StackwalkPool {
+    /// Creates a new pool of at most `size` workers.
+    pub fn new(size: usize, config: StackwalkConfig) -> Self {
+        Self::with_command(size, WorkerCommand::stackwalk_worker(&config))
+    }
+
+    fn with_command(size: usize, command: WorkerCommand) -> Self {
+        Self {
+            inner: Arc::new(PoolInner {
+                idle: Mutex::new(Vec::new()),
+                permits: Semaphore::new(size.max(1)),
+                command,
+            }),
+        }
+    }
+
+    /// Stackwalks the minidump at `minidump_path` in one of the workers.
+    ///
+    /// If the worker does not respond within `timeout`, or it crashes, it is killed and an
+    /// error is returned.
+    pub(super) async fn stackwalk(
+        &self,
+        cfi_caches: Vec<(DebugId, PathBuf)>,
+        minidump_path: PathBuf,
+        params: StackwalkParams,
+        timeout: Duration,
+    ) -> anyhow::Result<StackWalkMinidumpResult> {
+        let _permit = self.inner.permits.acquire().await?;
+
+        let idle_worker = self.inner.idle.lock().pop();
+        let mut worker = match idle_worker {
+            Some(worker) => worker,
+            None => {
+                metric!(counter("minidump.stackwalk.worker.spawned") += 1);
+                Worker::spawn(&self.inner.command).context("failed to spawn stackwalk worker")?
+            }
+        };
+
+        let request = WorkerRequest {
+            cfi_caches,
+            minidump_path,
+            params,
+        };
+
+        // Dropping a worker kills its process, so only workers which responded are reused.
+        match tokio::time::timeout(timeout, worker.request(&request)).await {
+            Ok(Ok(response)) => {
+                self.inner.idle.lock().push(worker);
+                match response {
+                    WorkerResponse::Ok(result) => Ok(result),
+                    WorkerResponse::Err(err) => Err(anyhow::anyhow!(err)),
+                }
+            }
+            Ok(Err(err)) => {
+                metric!(counter("minidump.stackwalk.worker.crashed") += 1);
+                Err(err)
+            }
+            Err(elapsed) => {
+                metric!(counter("minidump.stackwalk.worker.timeout") += 1);
+                Err(elapsed.into())
+            }
+        }
+    }
+}
+
+/// Runs a stackwalk worker, processing requests from `stdin` until it is closed.
+///
+/// `memory_limit` limits the address space of the worker in bytes, and `cpu_limit` limits
+/// the CPU time in seconds that may be spent on a single request.  Exceeding the limits
+/// terminates the worker.
+pub fn run_stackwalk_worker(
+    memory_limit: Option<u64>,
+    cpu_limit: Option<u64>,
+) -> anyhow::Result<()> {
+    if let Some(limit) = memory_limit {
+        rlimit::limit_memory(limit).context("failed to limit worker memory")?;
+    }
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    serve_requests(stdin.lock(), stdout.lock(), cpu_limit)
+}
+
+/// Answers every request read from `input` with a response written to `output`.
+fn serve_requests(
+    input: impl BufRead,
+    mut output: impl Write,
+    cpu_limit: Option<u64>,
+) -> anyhow::Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    for line in input.lines() {
+        let request: WorkerRequest = serde_json::from_str(&line?)?;
+
+        if let Some(limit) = cpu_limit {
+            rlimit::limit_cpu_time(limit).context("failed to limit worker CPU time")?;
+        }
+
+        let future = stackwalk(request.cfi_caches, request.minidump_path, request.params);
+        let response = match runtime.block_on(future) {
+            Ok(result) => WorkerResponse::Ok(result),
+            Err(err) => WorkerResponse::Err(format!("{:#}", err)),
+        };
+
+        output.write_all(&encode_message(&response)?)?;
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+mod rlimit {
+    use std::io;
+    use std::mem::MaybeUninit;
+
+    fn get(resource: libc::c_int) -> io::Result<libc::rlimit> {
+        let mut limit = MaybeUninit::<libc::rlimit>::uninit();
+        // SAFETY: `getrlimit` initializes `limit` if it succeeds.
+        match unsafe { libc::getrlimit(resource as _, limit.as_mut_ptr()) } {
+            0 => Ok(unsafe { limit.assume_init() }),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Sets the soft limit of `resource`, capped to the current hard limit.
+    fn set(resource: libc::c_int, value: u64) -> io::Result<()> {
+        let mut limit = get(resource)?;
+        limit.rlim_cur = (value as libc::rlim_t).min(limit.rlim_max);
+        // SAFETY: `setrlimit` only reads from `limit`.
+        match unsafe { libc::setrlimit(resource as _, &limit) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Limits the address space of this process to `bytes`.
+    pub fn limit_memory(bytes: u64) -> io::Result<()> {
+        set(libc::RLIMIT_AS as libc::c_int, bytes)
+    }
+
+    /// Limits the CPU time of this process to `seconds` from now.
+    ///
+    /// The CPU time limit applies to the entire lifetime of the process, so this adds the
+    /// CPU time used so far.
+    pub fn limit_cpu_time(seconds: u64) -> io::Result<()> {
+        let mut usage = MaybeUninit::<libc::rusage>::uninit();
+        // SAFETY: `getrusage` initializes `usage` if it succeeds.
+        let usage = match unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } {
+            0 => unsafe { usage.assume_init() },
+            _ => return Err(io::Error::last_os_error()),
+        };
+
+        let used = (usage.ru_utime.tv_sec + usage.ru_stime.tv_sec) as u64;
+        set(libc::RLIMIT_CPU as libc::c_int, used + seconds)
+    }
+}
+
+#[cfg(not(unix))]
+mod rlimit {
+    use std::io;
+
+    pub fn limit_memory(_bytes: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn limit_cpu_time(_seconds: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test;
+
+    fn request(minidump_path: &str) -> WorkerRequest {
+        WorkerRequest {
+            cfi_caches: Vec::new(),
+            minidump_path: minidump_path.into(),
+            params: StackwalkParams::default(),
+        }
+    }
+
+    /// Creates a pool of a single worker which runs the given shell `script`.
+    ///
+    /// The script receives requests on `stdin`, which include the minidump path.
+    #[cfg(unix)]
+    fn shell_pool(script: &str) -> StackwalkPool {
+        let command = WorkerCommand {
+            program: Some("/bin/sh".into()),
+            args: vec!["-c".to_owned(), script.to_owned()],
+        };
+        StackwalkPool::with_command(1, command)
+    }
+
+    #[cfg(unix)]
+    async fn walk(pool: &StackwalkPool, minidump_path: &str) -> anyhow::Result<()> {
+        let request = request(minidump_path);
+        let timeout = Duration::from_millis(500);
+        pool.stackwalk(
+            request.cfi_caches,
+            request.minidump_path,
+            request.params,
+            timeout,
+        )
+        .await
+        .map(|_| ())
+    }
+
+    #[test]
+    fn test_message_roundtrip() -> anyhow::Result<()> {
+        let line = encode_message(&request("/tmp/minidump\n.dmp"))?;
+        assert_eq!(line.iter().filter(|&&b| b == b'\n').count(), 1);
+        assert_eq!(line.last(), Some(&b'\n'));
+
+        let decoded: WorkerRequest = serde_json::from_slice(&line)?;
+        assert_eq!(decoded.minidump_path, PathBuf::from("/tmp/minidump\n.dmp"));
+
+        let line = encode_message(&WorkerResponse::Err("first\nsecond".to_owned()))?;
+        assert_eq!(line.iter().filter(|&&b| b == b'\n').count(), 1);
+        match serde_json::from_slice(&line)? {
+            WorkerResponse::Err(err) => assert_eq!(err, "first\nsecond"),
+            WorkerResponse::Ok(_) => panic!("expected an error response"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve_requests() -> anyhow::Result<()> {
+        let fixture = test::fixture("linux.dmp");
+        let mut input = encode_message(&request(fixture.to_str().unwrap()))?;
+        input.extend(encode_message(&request("/does/not/exist.dmp"))?);
+
+        let mut output = Vec::new();
+        serve_requests(input.as_slice(), &mut output, None)?;
+
+        let responses = output
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(serde_json::from_slice)
+            .collect::<Result<Vec<WorkerResponse>, _>>()?;
+
+        assert_eq!(responses.len(), 2);
+        assert!(matches!(responses[0], WorkerResponse::Ok(_)));
+        assert!(matches!(responses[1], WorkerResponse::Err(_)));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_worker_crash_restart() {
+        let pool = shell_pool(
+            r#"while read line; do
+                case "$line" in
+                    *crash*) exit 1 ;;
+                    *kill*) kill -KILL $$ ;;
+                    *) echo '{"Err":"walked"}' ;;
+                esac
+            done"#,
+        );
+
+        let err = walk(&pool, "crash").await.unwrap_err();
+        assert!(err.to_string().contains("exited unexpectedly"), "{}", err);
+        assert!(pool.inner.idle.lock().is_empty());
+
+        // The crashed worker is replaced on the next request.
+        let err = walk(&pool, "fine").await.unwrap_err();
+        assert_eq!(err.to_string(), "walked");
+        assert_eq!(pool.inner.idle.lock().len(), 1);
+
+        // Workers killed by a signal, such as exceeding their CPU limit, are replaced as well.
+        let err = walk(&pool, "kill").await.unwrap_err();
+        assert!(err.to_string().contains("signal"), "{}", err);
+        assert!(pool.inner.idle.lock().is_empty());
+
+        let err = walk(&pool, "fine").await.unwrap_err();
+        assert_eq!(err.to_string(), "walked");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_worker_timeout() {
+        let pool = shell_pool(
+            r#"while read line; do
+                case "$line" in
+                    *slow*) sleep 10 ;;
+                    *) echo '{"Err":"walked"}' ;;
+                esac
+            done"#,
+        );
+
+        let err = walk(&pool, "slow").await.unwrap_err();
+        assert!(err.is::<tokio::time::error::Elapsed>(), "{}", err);
+        assert!(pool.inner.idle.lock().is_empty());
+
+        let err = walk(&pool, "fast").await.unwrap_err();
+        assert_eq!(err.to_string(), "walked");
+    }
+
+    /// Runs `test_name` in a child process of the test binary and returns the signal which
+    /// terminated it.
+    ///
+    /// The child runs the test with `SANDBOX_TEST_CHILD` set, and is expected to be killed for
+    /// exceeding a resource limit.
+    #[cfg(unix)]
+    fn run_child_test(test_name: &str) -> Option<i32> {
+        use std::os::unix::process::ExitStatusExt;
+
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(&[test_name, "--exact", "--nocapture", "--test-threads=1"])
+            .env("SANDBOX_TEST_CHILD", "1")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+
+        status.signal()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cpu_limit() {
+        if std::env::var_os("SANDBOX_TEST_CHILD").is_some() {
+            rlimit::limit_cpu_time(1).unwrap();
+            loop {
+                std::hint::spin_loop();
+            }
+        }
+
+        let signal = run_child_test("services::symbolication::sandbox::tests::test_cpu_limit");
+        assert!(
+            matches!(signal, Some(libc::SIGXCPU) | Some(libc::SIGKILL)),
+            "{:?}",
+            signal
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_memory_limit() {
+        if std::env::var_os("SANDBOX_TEST_CHILD").is_some() {
+            rlimit::limit_memory(1 << 30).unwrap();
+            let size = (4 << 30) + std::env::args().count();
+            let buf = vec![1u8; size];
+            // SAFETY: The index is in bounds.  The volatile read keeps the allocation alive.
+            unsafe { std::ptr::read_volatile(&buf[size - 1]) };
+            return;
+        }
+
+        let signal = run_child_test("services::symbolication::sandbox::tests::test_memory_limit");
+        assert_eq!(signal, Some(libc::SIGABRT));
+    }
+}
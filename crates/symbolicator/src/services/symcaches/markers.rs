@@ -2,6 +2,8 @@ use std::io::SeekFrom;
 
 use crate::services::bitcode::BcSymbolMapHandle;
 
+use super::DebugCompanion;
+
 /// This is the legacy marker that was used previously to flag a SymCache that was created
 /// using a`BcSymbolMap`.
 const LEGACY_SYMBOLMAP_MARKER: &[u8] = b"WITH_SYMBOLMAP";
@@ -13,9 +15,11 @@ const MARKERS32_MARKER: &[u8] = b"WITH_MARKERS32";
 #[derive(Clone, Debug, Default)]
 pub struct SecondarySymCacheSources {
     pub bcsymbolmap_handle: Option<BcSymbolMapHandle>,
+    pub debug_companion: Option<DebugCompanion>,
 }
 
 const MARKER_BCSYMBOLMAP: u32 = 1 << 0;
+const MARKER_DEBUG_COMPANION: u32 = 1 << 1;
 
 /// This is the markers that are being embedded into, and read from, a SymCache file.
 #[derive(Debug, Default, PartialEq)]
@@ -30,6 +34,9 @@ impl SymCacheMarkers {
         if sources.bcsymbolmap_handle.is_some() {
             markers |= MARKER_BCSYMBOLMAP;
         }
+        if sources.debug_companion.is_some() {
+            markers |= MARKER_DEBUG_COMPANION;
+        }
         Self { markers }
     }
 
@@ -99,6 +106,7 @@ mod tests {
         };
         let sources = SecondarySymCacheSources {
             bcsymbolmap_handle: Some(bcsymbolmap),
+            debug_companion: None,
         };
         let markers = SymCacheMarkers::from_sources(&sources);
 
@@ -1,5 +1,5 @@
 use std::fs::File;
-use std::io::{self, BufWriter};
+use std::io::{self, BufWriter, Seek, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
@@ -8,7 +8,7 @@ use anyhow::Error;
 use futures::future::BoxFuture;
 use sentry::{configure_scope, Hub, SentryFutureExt};
 use symbolic::common::{Arch, ByteView};
-use symbolic::debuginfo::Object;
+use symbolic::debuginfo::{Object, SymbolMap};
 use symbolic::symcache::{self, SymCache, SymCacheWriter};
 use thiserror::Error;
 
@@ -23,6 +23,7 @@ use crate::sources::{FileType, SourceConfig};
 use crate::types::{
     AllObjectCandidates, ObjectFeatures, ObjectId, ObjectType, ObjectUseInfo, Scope,
 };
+use crate::utils::checksum;
 use crate::utils::futures::{m, measure, CancelOnDrop};
 use crate::utils::sentry::ConfigureScope;
 
@@ -31,6 +32,7 @@ use self::markers::{SecondarySymCacheSources, SymCacheMarkers};
 use super::shared_cache::SharedCacheService;
 
 mod markers;
+mod symbol_map;
 
 /// The supported symcache versions.
 ///
@@ -48,11 +50,24 @@ mod markers;
 /// In case a symbolic update increased its own internal format version, bump the
 /// symcache file version as described above, and update the static assertion.
 const SYMCACHE_VERSIONS: CacheVersions = CacheVersions {
-    current: 1,
-    fallbacks: &[0],
+    current: 2,
+    fallbacks: &[1, 0],
 };
 static_assert!(symbolic::symcache::SYMCACHE_VERSION == 7);
 
+/// The first symcache version which is always written with a checksum trailer.
+///
+/// Symcaches of this or a later version without a valid checksum are corrupt, for instance
+/// because they were truncated.
+const SYMCACHE_CHECKSUM_VERSION: u32 = 2;
+
+/// Returns the symcache versions whose items carry a checksum, see [`Cache::verify`].
+pub fn checksummed_versions() -> impl Iterator<Item = u32> {
+    std::iter::once(SYMCACHE_VERSIONS.current)
+        .chain(SYMCACHE_VERSIONS.fallbacks.iter().copied())
+        .filter(|&version| version >= SYMCACHE_CHECKSUM_VERSION)
+}
+
 /// Errors happening while generating a symcache.
 #[derive(Debug, Error)]
 pub enum SymCacheError {
@@ -77,6 +92,12 @@ pub enum SymCacheError {
     #[error("failed to handle auxiliary BCSymbolMap file")]
     BcSymbolMapError(#[source] Error),
 
+    #[error("failed to parse symbol map")]
+    SymbolMap(#[source] symbolic::debuginfo::ObjectError),
+
+    #[error("failed to read debug information")]
+    DebugInfo(#[source] symbolic::debuginfo::ObjectError),
+
     #[error("symcache building took too long")]
     Timeout,
 
@@ -109,6 +130,15 @@ impl SymCacheActor {
     }
 }
 
+/// A DIF found alongside the primary object of a symcache, contributing debug information.
+///
+/// You can only have this if the object was fetched successfully.
+#[derive(Clone, Debug)]
+pub struct DebugCompanion {
+    meta: Arc<ObjectMetaHandle>,
+    handle: Arc<ObjectHandle>,
+}
+
 #[derive(Clone, Debug)]
 pub struct SymCacheFile {
     data: ByteView<'static>,
@@ -137,7 +167,7 @@ impl SymCacheFile {
         self.arch
     }
 
-    /// Returns the features of the object file this symcache was constructed from.
+    /// Returns the features of the object files this symcache was constructed from.
     pub fn features(&self) -> ObjectFeatures {
         self.features
     }
@@ -225,6 +255,7 @@ impl CacheItemRequest for FetchSymCacheInternal {
     type Error = SymCacheError;
 
     const VERSIONS: CacheVersions = SYMCACHE_VERSIONS;
+    const CHECKSUM_VERSION: Option<u32> = Some(SYMCACHE_CHECKSUM_VERSION);
 
     fn get_cache_key(&self) -> CacheKey {
         self.object_meta.cache_key()
@@ -257,7 +288,7 @@ impl CacheItemRequest for FetchSymCacheInternal {
                 // NOTE: we do *not* check for the `is_latest` version here.
                 // If the symcache is parsable, we want to use even outdated versions.
 
-                let symcache_markers = SymCacheMarkers::parse(data);
+                let symcache_markers = SymCacheMarkers::parse(checksum::strip(data));
                 SymCacheMarkers::from_sources(&self.secondary_sources) == symcache_markers
             })
             .unwrap_or(false)
@@ -282,9 +313,19 @@ impl CacheItemRequest for FetchSymCacheInternal {
             ObjectUseInfo::from_derived_status(&status, self.object_meta.status()),
         );
 
+        let mut features = self.object_meta.features();
+        if let Some(ref companion) = self.secondary_sources.debug_companion {
+            candidates.set_debug(
+                companion.meta.source_id(),
+                &companion.meta.uri(),
+                ObjectUseInfo::from_derived_status(&status, companion.meta.status()),
+            );
+            features.merge(companion.meta.features());
+        }
+
         SymCacheFile {
             data,
-            features: self.object_meta.features(),
+            features,
             status,
             arch,
             candidates,
@@ -306,7 +347,11 @@ impl SymCacheActor {
         &self,
         request: FetchSymCache,
     ) -> Result<Arc<SymCacheFile>, Arc<SymCacheError>> {
-        let FoundObject { meta, candidates } = self
+        let FoundObject {
+            meta,
+            candidates,
+            companion,
+        } = self
             .objects
             .find(FindObject {
                 filetypes: FileType::from_object_type(request.object_type),
@@ -335,7 +380,15 @@ impl SymCacheActor {
                     None => None,
                 };
 
-                let secondary_sources = SecondarySymCacheSources { bcsymbolmap_handle };
+                let debug_companion = match companion {
+                    Some(meta) => self.fetch_debug_companion(meta).await,
+                    None => None,
+                };
+
+                let secondary_sources = SecondarySymCacheSources {
+                    bcsymbolmap_handle,
+                    debug_companion,
+                };
 
                 self.symcaches
                     .compute_memoized(FetchSymCacheInternal {
@@ -357,6 +410,20 @@ impl SymCacheActor {
             })),
         }
     }
+
+    /// Fetches the object of a debug companion, returning `None` if it is not available.
+    async fn fetch_debug_companion(&self, meta: Arc<ObjectMetaHandle>) -> Option<DebugCompanion> {
+        match self.objects.fetch(meta.clone()).await {
+            Ok(handle) if handle.status() == &CacheStatus::Positive => {
+                Some(DebugCompanion { meta, handle })
+            }
+            Ok(_) => None,
+            Err(err) => {
+                tracing::debug!("Failed to fetch debug companion: {}", err);
+                None
+            }
+        }
+    }
 }
 
 /// Computes and writes the symcache.
@@ -400,14 +467,113 @@ fn write_symcache(
 
     tracing::debug!("Converting symcache for {}", object_handle.cache_key());
 
-    SymCacheWriter::write_object(&symbolic_object, &mut writer).map_err(SymCacheError::Writing)?;
+    match secondary_sources.debug_companion {
+        Some(ref companion) => {
+            let companion_object = companion
+                .handle
+                .parse()
+                .map_err(SymCacheError::ObjectParsing)?
+                .unwrap();
+            tracing::debug!(
+                "Adding debug companion {} to {}",
+                companion.handle,
+                object_handle
+            );
+            write_merged_symcache(&mut writer, &symbolic_object, &companion_object)?;
+        }
+        None => {
+            SymCacheWriter::write_object(&symbolic_object, &mut writer)
+                .map_err(SymCacheError::Writing)?;
+        }
+    }
 
     let mut file = writer.into_inner().map_err(io::Error::from)?;
 
     markers.write_to(&mut file)?;
 
     file.sync_all()?;
+    drop(file);
+
+    checksum::append_to(path)?;
+
+    Ok(())
+}
+
+/// Returns how detailed the debug information of `object` is, higher is better.
+///
+/// Breakpad symbol files contain line information, but no inline functions.
+fn debug_info_quality(object: &Object<'_>) -> u8 {
+    match object {
+        _ if !object.has_debug_info() => 0,
+        Object::Breakpad(_) => 1,
+        _ => 2,
+    }
+}
+
+/// Writes a symcache from an object and its debug companion.
+///
+/// Functions are taken from the object with more detailed debug information, preferring
+/// `object` if both are equal.  Functions of the other object are added where they do not
+/// overlap with any of those, so that neither object loses coverage.  The symbol tables of
+/// both objects are merged, so addresses not covered by any function still resolve to a symbol.
+fn write_merged_symcache<W: Write + Seek>(
+    writer: W,
+    object: &Object<'_>,
+    companion: &Object<'_>,
+) -> Result<(), SymCacheError> {
+    let (primary, secondary) = if debug_info_quality(companion) > debug_info_quality(object) {
+        (companion, object)
+    } else {
+        (object, companion)
+    };
+
+    let mut symcache_writer = SymCacheWriter::new(writer).map_err(SymCacheError::Writing)?;
+    symcache_writer.set_arch(object.arch());
+    symcache_writer.set_debug_id(object.debug_id());
+
+    let primary_session = primary.debug_session().map_err(SymCacheError::DebugInfo)?;
+    let secondary_session = secondary
+        .debug_session()
+        .map_err(SymCacheError::DebugInfo)?;
+
+    let mut functions = primary_session
+        .functions()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(SymCacheError::DebugInfo)?;
+    let mut covered: Vec<_> = functions
+        .iter()
+        .map(|function| function.address..function.end_address())
+        .collect();
+    covered.sort_by_key(|range| range.start);
+
+    for function in secondary_session.functions() {
+        let function = function.map_err(SymCacheError::DebugInfo)?;
+        let idx = covered.partition_point(|range| range.start < function.end_address());
+        let overlaps = idx
+            .checked_sub(1)
+            .map_or(false, |idx| covered[idx].end > function.address);
+        if !overlaps {
+            functions.push(function);
+        }
+    }
+
+    functions.sort_by_key(|function| function.address);
+    for function in functions {
+        symcache_writer
+            .add_function(function)
+            .map_err(SymCacheError::Writing)?;
+    }
 
+    // Symbols at the same address are deduplicated, keeping the one of `object`.
+    let mut symbols: Vec<_> = object.symbols().chain(companion.symbols()).collect();
+    symbols.sort_by_key(|symbol| symbol.address);
+    for symbol in SymbolMap::from(symbols) {
+        symcache_writer
+            .add_symbol(symbol)
+            .map_err(SymCacheError::Writing)?;
+    }
+
+    symcache_writer.finish().map_err(SymCacheError::Writing)?;
     Ok(())
 }
 
@@ -555,4 +721,139 @@ mod tests {
         assert_eq!(line_info.filename(), "Sources/Sentry/SentryMessage.m");
         assert_eq!(line_info.symbol(), "-[SentryMessage initWithFormatted:]");
     }
+
+    /// Writes a Breakpad symbol file with the first function of `object` which has inlinees.
+    ///
+    /// Returns the symbol file and the address of the first inlinee.
+    fn breakpad_with_inlined_function(object: &Object<'_>) -> (String, u64) {
+        let session = object.debug_session().unwrap();
+        let function = session
+            .functions()
+            .filter_map(Result::ok)
+            .find(|function| !function.inlinees.is_empty())
+            .expect("fixture contains inlined functions");
+
+        let breakpad = format!(
+            "MODULE mac {} {} debuginfo\nFUNC {:x} {:x} 0 {}\n",
+            object.arch().name(),
+            object.debug_id().breakpad(),
+            function.address,
+            function.size,
+            function.name.as_str(),
+        );
+        (breakpad, function.inlinees[0].address)
+    }
+
+    #[test]
+    fn test_merged_symcache_inlines() {
+        let data = fs::read(fixture(
+            "symbols/2d10c42f-591d-3265-b147-78ba0868073f.dwarf-hidden",
+        ))
+        .unwrap();
+        let dwarf = Object::parse(&data).unwrap();
+        let (breakpad, inlinee_addr) = breakpad_with_inlined_function(&dwarf);
+        let breakpad = Object::parse(breakpad.as_bytes()).unwrap();
+
+        let mut buffer = io::Cursor::new(Vec::new());
+        SymCacheWriter::write_object(&breakpad, &mut buffer).unwrap();
+        let symcache = SymCache::parse(buffer.get_ref()).unwrap();
+        assert_eq!(symcache.lookup(inlinee_addr).unwrap().count(), 1);
+
+        // Inline frames are taken from the DWARF companion of the Breakpad file.
+        let mut buffer = io::Cursor::new(Vec::new());
+        write_merged_symcache(&mut buffer, &breakpad, &dwarf).unwrap();
+        let symcache = SymCache::parse(buffer.get_ref()).unwrap();
+        assert!(symcache.lookup(inlinee_addr).unwrap().count() > 1);
+    }
+
+    #[test]
+    fn test_merged_symcache_fills_gaps() {
+        let data = fs::read(fixture(
+            "symbols/2d10c42f-591d-3265-b147-78ba0868073f.dwarf-hidden",
+        ))
+        .unwrap();
+        let dwarf = Object::parse(&data).unwrap();
+        let (mut breakpad, inlinee_addr) = breakpad_with_inlined_function(&dwarf);
+
+        // A function which is only contained in the Breakpad file.
+        let end_addr = dwarf
+            .debug_session()
+            .unwrap()
+            .functions()
+            .filter_map(Result::ok)
+            .map(|function| function.end_address())
+            .max()
+            .unwrap();
+        let breakpad_only_addr = end_addr + 0x100;
+        breakpad.push_str(&format!(
+            "FUNC {:x} 10 0 breakpad_only\n",
+            breakpad_only_addr
+        ));
+        let breakpad = Object::parse(breakpad.as_bytes()).unwrap();
+
+        let mut buffer = io::Cursor::new(Vec::new());
+        write_merged_symcache(&mut buffer, &breakpad, &dwarf).unwrap();
+        let symcache = SymCache::parse(buffer.get_ref()).unwrap();
+
+        assert!(symcache.lookup(inlinee_addr).unwrap().count() > 1);
+        let line_info = symcache
+            .lookup(breakpad_only_addr + 4)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(line_info.symbol(), "breakpad_only");
+    }
+
+    #[tokio::test]
+    async fn test_symcache_debug_companion() {
+        test::setup();
+
+        let cache_dir = test::tempdir();
+        let symbol_dir = test::tempdir();
+
+        let debug_file = fixture("symbols/2d10c42f-591d-3265-b147-78ba0868073f.dwarf-hidden");
+        let data = fs::read(&debug_file).unwrap();
+        let (breakpad, inlinee_addr) =
+            breakpad_with_inlined_function(&Object::parse(&data).unwrap());
+
+        let macho_dir = symbol_dir.path().join("2d/10c42f591d3265b14778ba0868073f/");
+        fs::create_dir_all(&macho_dir).unwrap();
+        fs::copy(debug_file, macho_dir.join("debuginfo")).unwrap();
+        fs::write(macho_dir.join("breakpad"), breakpad).unwrap();
+
+        let source = SourceConfig::Filesystem(Arc::new(FilesystemSourceConfig {
+            id: SourceId::new("local"),
+            path: symbol_dir.path().to_owned(),
+            files: CommonSourceConfig::with_layout(DirectoryLayoutType::Unified),
+        }));
+
+        let fetch_symcache = FetchSymCache {
+            object_type: ObjectType::Macho,
+            identifier: ObjectId::from(DebugId::from_uuid(
+                Uuid::parse_str("2d10c42f-591d-3265-b147-78ba0868073f").unwrap(),
+            )),
+            sources: Arc::new([source]),
+            scope: Scope::Global,
+        };
+
+        let symcache_actor = symcache_actor(cache_dir.path().to_owned(), Duration::ZERO).await;
+        let symcache_file = symcache_actor.fetch(fetch_symcache).await.unwrap();
+
+        let symcache = symcache_file.parse().unwrap().unwrap();
+        assert!(symcache.lookup(inlinee_addr).unwrap().count() > 1);
+
+        // Both the Breakpad file and its companion are reported as used for symbolication.
+        let candidates = serde_json::to_value(symcache_file.candidates()).unwrap();
+        let used: Vec<_> = candidates
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|candidate| candidate["debug"]["status"] == "ok")
+            .map(|candidate| candidate["location"].as_str().unwrap())
+            .collect();
+        assert_eq!(used.len(), 2, "{:#?}", candidates);
+        assert!(used.iter().any(|location| location.ends_with("breakpad")));
+        assert!(used.iter().any(|location| location.ends_with("debuginfo")));
+    }
 }
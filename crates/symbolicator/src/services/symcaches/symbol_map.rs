@@ -0,0 +1,215 @@
+//! In-memory symcaches for user-supplied symbol maps of dynamically generated code.
+//!
+//! Symbol maps use the format of the `perf-<pid>.map` files written by JIT compilers for `perf`.
+//! They are converted into a Breakpad symbol file with one `FUNC` record per symbol, from which
+//! the symcache is written like for any other object file.
+
+use std::fmt::Write;
+use std::io::Cursor;
+
+use symbolic::common::{Arch, ByteView};
+use symbolic::debuginfo::Object;
+use symbolic::symcache::SymCacheWriter;
+
+use crate::cache::CacheStatus;
+use crate::types::{AllObjectCandidates, ObjectFeatures, SymbolMap};
+
+use super::{SymCacheError, SymCacheFile};
+
+/// A symbol of a symbol map, with its absolute start address and size.
+#[derive(Debug, PartialEq, Eq)]
+struct MapSymbol<'a> {
+    start: u64,
+    size: u64,
+    name: &'a str,
+}
+
+/// Parses a symbol map in the `perf-<pid>.map` format.
+///
+/// Each line contains the hexadecimal start address and size of a symbol, followed by its name.
+/// The name may contain spaces.  Lines which do not follow this format, as well as empty
+/// symbols, are skipped.
+fn parse_perf_map(data: &str) -> Vec<MapSymbol<'_>> {
+    let parse_hex = |s: &str| {
+        let s = s.trim_start_matches("0x");
+        u64::from_str_radix(s, 16).ok()
+    };
+
+    data.lines()
+        .filter_map(|line| {
+            let mut parts = line.trim().splitn(3, ' ');
+            let start = parse_hex(parts.next()?)?;
+            let size = parse_hex(parts.next()?)?;
+            let name = parts.next()?.trim();
+            (size > 0 && !name.is_empty()).then(|| MapSymbol { start, size, name })
+        })
+        .collect()
+}
+
+/// The largest gap between two symbols of the same address range.
+///
+/// Symbols further apart are split into separate ranges, so that a symbol map of distant
+/// memory regions does not cover the modules loaded in between.
+const MAX_SYMBOL_GAP: u64 = 0x1000;
+
+/// Sorts `symbols` by address and splits them into contiguous ranges.
+fn symbol_ranges(mut symbols: Vec<MapSymbol<'_>>) -> Vec<Vec<MapSymbol<'_>>> {
+    symbols.sort_by_key(|symbol| symbol.start);
+
+    let mut ranges: Vec<Vec<MapSymbol<'_>>> = Vec::new();
+    let mut range_end = 0;
+    for symbol in symbols {
+        let end = symbol.start.saturating_add(symbol.size);
+        match ranges.last_mut() {
+            Some(range) if symbol.start <= range_end.saturating_add(MAX_SYMBOL_GAP) => {
+                range.push(symbol);
+                range_end = range_end.max(end);
+            }
+            _ => {
+                ranges.push(vec![symbol]);
+                range_end = end;
+            }
+        }
+    }
+
+    ranges
+}
+
+impl SymCacheFile {
+    /// Builds in-memory symcaches from a [`SymbolMap`].
+    ///
+    /// Returns one symcache for each contiguous range of symbols in the map, along with the
+    /// start address and size of the range.  The list is empty if the map does not contain
+    /// any symbols.
+    pub fn from_symbol_map(symbol_map: &SymbolMap) -> Result<Vec<(Self, u64, u64)>, SymCacheError> {
+        let name = symbol_map.name.as_deref().unwrap_or("<jit>");
+        symbol_ranges(parse_perf_map(&symbol_map.perf_map))
+            .iter()
+            .map(|symbols| Self::from_map_symbols(name, symbols))
+            .collect()
+    }
+
+    /// Builds a symcache from a non-empty, sorted range of symbols.
+    fn from_map_symbols(
+        name: &str,
+        symbols: &[MapSymbol<'_>],
+    ) -> Result<(Self, u64, u64), SymCacheError> {
+        let start = symbols[0].start;
+        let end = symbols
+            .iter()
+            .map(|symbol| symbol.start.saturating_add(symbol.size))
+            .max()
+            .unwrap_or(start);
+
+        let mut breakpad = format!(
+            "MODULE Linux unknown 000000000000000000000000000000000 {}\n",
+            name
+        );
+        for symbol in symbols {
+            writeln!(
+                breakpad,
+                "FUNC {:x} {:x} 0 {}",
+                symbol.start - start,
+                symbol.size,
+                symbol.name
+            )
+            .ok();
+        }
+
+        let object = Object::parse(breakpad.as_bytes()).map_err(SymCacheError::SymbolMap)?;
+        let mut writer = Cursor::new(Vec::new());
+        SymCacheWriter::write_object(&object, &mut writer).map_err(SymCacheError::Writing)?;
+
+        let symcache = SymCacheFile {
+            data: ByteView::from_vec(writer.into_inner()),
+            features: ObjectFeatures {
+                has_symbols: true,
+                ..ObjectFeatures::default()
+            },
+            status: CacheStatus::Positive,
+            arch: Arch::Unknown,
+            candidates: AllObjectCandidates::default(),
+        };
+
+        Ok((symcache, start, end - start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_perf_map() {
+        let data = "7f0012340000 40 jitted::foo\n\
+                    0x7f0012340040 20 Interpreter frame with spaces\n\
+                    \n\
+                    7f0012340060 0 empty\n\
+                    garbage\n";
+
+        assert_eq!(
+            parse_perf_map(data),
+            [
+                MapSymbol {
+                    start: 0x7f00_1234_0000,
+                    size: 0x40,
+                    name: "jitted::foo",
+                },
+                MapSymbol {
+                    start: 0x7f00_1234_0040,
+                    size: 0x20,
+                    name: "Interpreter frame with spaces",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_symcache_from_symbol_map() {
+        let symbol_map = SymbolMap {
+            name: Some("jit".to_owned()),
+            perf_map: "1000 40 jitted::foo\n1040 20 jitted::bar\n".to_owned(),
+        };
+
+        let mut symcaches = SymCacheFile::from_symbol_map(&symbol_map).unwrap();
+        assert_eq!(symcaches.len(), 1);
+
+        let (symcache_file, image_addr, image_size) = symcaches.remove(0);
+        assert_eq!(image_addr, 0x1000);
+        assert_eq!(image_size, 0x60);
+
+        let symcache = symcache_file.parse().unwrap().unwrap();
+        let names: Vec<_> = symcache
+            .lookup(0x48)
+            .unwrap()
+            .map(|line_info| line_info.unwrap().function_name().to_string())
+            .collect();
+        assert_eq!(names, ["jitted::bar"]);
+    }
+
+    #[test]
+    fn test_empty_symbol_map() {
+        let symbol_map = SymbolMap {
+            name: None,
+            perf_map: "not a symbol map".to_owned(),
+        };
+        assert!(SymCacheFile::from_symbol_map(&symbol_map)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_symbol_map_ranges() {
+        let symbol_map = SymbolMap {
+            name: None,
+            perf_map: "7f0000001000 40 far\n1040 20 bar\n1000 40 foo\n1800 10 baz\n".to_owned(),
+        };
+
+        let ranges: Vec<_> = SymCacheFile::from_symbol_map(&symbol_map)
+            .unwrap()
+            .into_iter()
+            .map(|(_, image_addr, image_size)| (image_addr, image_size))
+            .collect();
+        assert_eq!(ranges, [(0x1000, 0x810), (0x7f00_0000_1000, 0x40)]);
+    }
+}
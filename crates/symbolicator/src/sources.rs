@@ -428,10 +428,21 @@ pub enum FileType {
     /// A .usym file that maps source information between generated C++ code and managed
     /// C# code.
     Usym,
+    /// Portable PDB debug files of .NET assemblies.
+    #[serde(rename = "portablepdb")]
+    PortablePdb,
+    /// Minified JavaScript files, which may reference their source map.
+    MinifiedSource,
+    /// Source maps of minified JavaScript files.
+    #[serde(rename = "sourcemap")]
+    SourceMap,
 }
 
 impl FileType {
-    /// Lists all available file types.
+    /// Lists all available file types, except for Portable PDBs and JavaScript files.
+    ///
+    /// These are only looked up for .NET and JavaScript modules, respectively, see
+    /// [`from_object_type`](Self::from_object_type).
     #[inline]
     pub fn all() -> &'static [Self] {
         use FileType::*;
@@ -488,6 +499,8 @@ impl FileType {
                 FileType::ElfDebug,
             ],
             ObjectType::Wasm => &[FileType::WasmCode, FileType::WasmDebug],
+            ObjectType::PeDotnet => &[FileType::PortablePdb],
+            ObjectType::SourceMap => &[FileType::MinifiedSource, FileType::SourceMap],
             _ => Self::all(),
         }
     }
@@ -509,6 +522,9 @@ impl AsRef<str> for FileType {
             FileType::UuidMap => "uuidmap",
             FileType::BcSymbolMap => "bcsymbolmap",
             FileType::Usym => "usym",
+            FileType::PortablePdb => "portablepdb",
+            FileType::MinifiedSource => "minified_source",
+            FileType::SourceMap => "sourcemap",
         }
     }
 }
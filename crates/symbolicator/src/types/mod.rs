@@ -5,11 +5,13 @@
 //! they are part of the public API.
 
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::Infallible;
 use std::fmt;
 use std::ops::Deref;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use chrono::{DateTime, Utc};
 use serde::{de, Deserialize, Deserializer, Serialize};
@@ -92,7 +94,7 @@ impl Deref for Glob {
 /// Based on scopes, access to debug files that have been cached is determined. If a file comes from
 /// a public source, it can be used for any symbolication request. Otherwise, the symbolication
 /// request must match the scope of a file.
-#[derive(Debug, Clone, Deserialize, Serialize, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, Hash, Ord, PartialEq, PartialOrd)]
 #[serde(untagged)]
 pub enum Scope {
     #[serde(rename = "global")]
@@ -154,6 +156,288 @@ pub struct RequestOptions {
     /// [`ObjectCandidate`] struct for which extra information is returned for DIF objects.
     #[serde(default)]
     pub dif_candidates: bool,
+
+    /// Additional minidump sections to return in the response.
+    ///
+    /// Minidumps contain a lot more information than what is needed for symbolication.
+    /// Sections listed here are extracted from the minidump and returned in the
+    /// `minidump_streams` field of the response, see [`MinidumpStreams`].  This is ignored
+    /// for requests which do not process a minidump.
+    #[serde(default)]
+    pub minidump_sections: BTreeSet<MinidumpSection>,
+
+    /// Whether to return memory around stack pointers and registers of minidump threads.
+    ///
+    /// If set, windows of memory captured in the minidump are returned base64-encoded in the
+    /// `memory` field of each stacktrace.  This is ignored for requests which do not process
+    /// a minidump.
+    #[serde(default)]
+    pub stack_memory: Option<StackMemoryOptions>,
+
+    /// Whether to return the recovered registers of every minidump stack frame.
+    ///
+    /// By default, registers are only returned for the top frame of each stacktrace.  If
+    /// enabled, each frame additionally carries the registers the stack walker was able to
+    /// recover for it, e.g. callee-saved registers restored via CFI.  Registers which could
+    /// not be recovered are omitted.  This is ignored for requests which do not process a
+    /// minidump.
+    #[serde(default)]
+    pub frame_registers: bool,
+
+    /// Limits for stackwalking minidumps.
+    ///
+    /// These can raise the limits configured for the server up to their configured maximum,
+    /// which by default only allows lowering them.  This is ignored for requests which do not
+    /// process a minidump.
+    #[serde(default)]
+    pub stackwalk: StackwalkOptions,
+
+    /// How to merge stacktraces captured on the client with the ones recovered by
+    /// stackwalking.
+    ///
+    /// Some minidumps contain stacktraces that were unwound on the client, see
+    /// [`StackMergeStrategy`].  This is ignored for requests which do not process a
+    /// minidump.
+    #[serde(default)]
+    pub stack_merge: StackMergeStrategy,
+
+    /// How to demangle function names of symbolicated frames.
+    ///
+    /// The mangled name is always returned in the `symbol` field of a frame, while the
+    /// `function` field contains the name demangled according to these options.
+    #[serde(default)]
+    pub demangling: DemanglingOptions,
+
+    /// Rules to classify symbolicated frames as in-app.
+    ///
+    /// If given, every symbolicated frame is annotated with `in_app` and `system`, see
+    /// [`SymbolicatedFrame`].
+    #[serde(default)]
+    pub in_app: Option<InAppRules>,
+
+    /// Whether to return a report on the quality of the stacktraces.
+    ///
+    /// If set, the `quality` field of the response contains counters of scanned, unmapped and
+    /// unsymbolicated frames per stacktrace and for the entire request, as well as the
+    /// modules whose missing debug information files caused unsymbolicated frames.  See
+    /// [`QualityReport`].
+    #[serde(default)]
+    pub quality_report: bool,
+
+    /// Symbol maps for dynamically generated code, such as JIT-compiled functions.
+    ///
+    /// Each contiguous range of symbols in a symbol map is treated as a synthetic module,
+    /// which takes precedence over other modules at the same addresses.
+    #[serde(default)]
+    pub symbol_maps: Vec<SymbolMap>,
+
+    /// How to return the inline frames of symbolicated frames.
+    ///
+    /// A single instruction address may resolve to several frames if functions were inlined
+    /// into each other.  By default, all of them are returned in the stacktrace, see
+    /// [`InlineFrames`].
+    #[serde(default)]
+    pub inline_frames: InlineFrames,
+
+    /// Whether to symbolicate the values of registers and the crash address.
+    ///
+    /// If set, register values and the crash address pointing into a module are resolved to the
+    /// function they point into.  Pointers to data, such as vtables, are only resolved to the
+    /// module, since SymCaches do not contain data symbols.  They are returned in the
+    /// `register_symbols` of each stacktrace and the `crash_address_symbol` of the response,
+    /// see [`PointerSymbol`].
+    #[serde(default)]
+    pub symbolicate_registers: bool,
+}
+
+/// How to return the inline frames of symbolicated frames, see [`RequestOptions`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InlineFrames {
+    /// Return inline frames in the stacktrace, before the frame they were inlined into.
+    Expand,
+    /// Return inline frames in the `inlined_frames` of the frame they were inlined into.
+    Collapse,
+    /// Only return the frame the inline frames were inlined into.
+    Drop,
+}
+
+impl Default for InlineFrames {
+    fn default() -> Self {
+        InlineFrames::Expand
+    }
+}
+
+/// A map of addresses to function names for dynamically generated code.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SymbolMap {
+    /// The name of the synthetic module, which is returned as its code file.
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// The symbols in the format of `perf-<pid>.map` files.
+    ///
+    /// Each line contains the hexadecimal start address and size of a symbol, followed by
+    /// its name.
+    pub perf_map: String,
+}
+
+/// Rules deciding which frames belong to the application, see [`RequestOptions`].
+///
+/// A frame is in-app if it matches any of the rules.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct InAppRules {
+    /// Glob patterns matched against the path of the module of a frame.
+    pub packages: Vec<Glob>,
+
+    /// Glob patterns matched against the absolute source path of a frame.
+    pub paths: Vec<Glob>,
+
+    /// File names of in-app modules, compared case-insensitively.
+    pub modules: Vec<String>,
+
+    /// Prefixes of the function names of in-app frames.
+    pub function_prefixes: Vec<String>,
+}
+
+/// Options controlling how function names are demangled, see [`RequestOptions`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DemanglingOptions {
+    /// How much of the demangled name to return.
+    pub style: DemangleStyle,
+
+    /// Whether to include the return type in full demangled names.
+    pub return_type: bool,
+
+    /// Whether to strip the hash suffix from demangled Rust names.
+    pub strip_rust_hash: bool,
+}
+
+impl Default for DemanglingOptions {
+    fn default() -> Self {
+        Self {
+            style: DemangleStyle::Full,
+            return_type: false,
+            strip_rust_hash: true,
+        }
+    }
+}
+
+/// How much of a demangled function name to return, see [`DemanglingOptions`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DemangleStyle {
+    /// Do not demangle, the function name is the mangled name.
+    None,
+    /// Only the name of the function, without parameters and return type.
+    Short,
+    /// The full function signature including parameters.
+    Full,
+}
+
+/// The strategy for merging client-side stacktraces embedded in a minidump with the
+/// stacktraces recovered by server-side stackwalking.
+///
+/// Threads for which only one of the two stacktraces is available always use that one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StackMergeStrategy {
+    /// Always use the client-side stacktrace.
+    PreferClient,
+    /// Always use the server-side stacktrace.
+    PreferServer,
+    /// Choose per thread, using the server-side stacktrace only if at least 80% as many of
+    /// its frames were recovered via CFI as the client-side stacktrace has frames.
+    Auto,
+    /// Return both stacktraces, the server-side one followed by the client-side one.
+    Both,
+}
+
+impl Default for StackMergeStrategy {
+    fn default() -> Self {
+        StackMergeStrategy::PreferClient
+    }
+}
+
+/// Where the frames of a minidump stacktrace were recovered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StacktraceProvenance {
+    /// The frames were unwound on the client and embedded in the minidump.
+    Client,
+    /// The frames were unwound by stackwalking the minidump.
+    Server,
+}
+
+/// Per-request overrides for the limits of stackwalking minidumps.
+///
+/// Limits which are not given, or which exceed the server configuration, fall back to the
+/// configured values.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct StackwalkOptions {
+    /// Maximum number of stackwalking iterations.
+    pub max_iterations: Option<usize>,
+
+    /// Timeout for a single stackwalking iteration.
+    #[serde(with = "humantime_serde")]
+    pub iteration_timeout: Option<Duration>,
+
+    /// Timeout for stackwalking a minidump, including all iterations and CFI downloads.
+    #[serde(with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+
+    /// Maximum number of frames returned per thread.
+    pub max_frames: Option<usize>,
+
+    /// Whether to only return the stacktrace of the crashing or requesting thread.
+    ///
+    /// Only CFI and debug files for modules referenced by this thread are fetched, which
+    /// makes this a fast first pass for minidumps with a lot of threads.  If the minidump
+    /// does not indicate a requesting thread, all threads are returned.
+    pub requesting_thread_only: bool,
+}
+
+/// Options controlling which memory is returned with minidump stacktraces.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct StackMemoryOptions {
+    /// The size of the window around each address, in bytes.
+    pub window_size: u64,
+
+    /// The maximum number of bytes returned across all stacktraces.
+    ///
+    /// Memory of the requesting thread is returned first.
+    pub max_bytes: u64,
+}
+
+impl Default for StackMemoryOptions {
+    fn default() -> Self {
+        Self {
+            window_size: 256,
+            max_bytes: 64 * 1024,
+        }
+    }
+}
+
+/// An optional section of a minidump which can be requested in [`RequestOptions`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MinidumpSection {
+    /// The exception record of the crashing thread.
+    Exception,
+    /// The list of memory regions and their protection.
+    MemoryInfo,
+    /// The list of loaded modules as recorded in the minidump.
+    Modules,
+    /// The list of modules which were unloaded before the minidump was written.
+    UnloadedModules,
+    /// The handles which were open in the process.
+    Handles,
+    /// The `/proc` files written by Breakpad and Crashpad on Linux.
+    LinuxProc,
 }
 
 /// A map of register values.
@@ -170,15 +454,26 @@ pub struct RawFrame {
     /// [`sym_addr`](Self::sym_addr).
     ///
     /// If not defined, it defaults to [`AddrMode::Abs`]. The mode can be set to `"rel:INDEX"` to
-    /// make the address relative to the module at the given index ([`AddrMode::Rel`]).
+    /// make the address relative to the module at the given index ([`AddrMode::Rel`]), to
+    /// `"il:INDEX"` for an IL offset in a method of a .NET module ([`AddrMode::Il`]), or to
+    /// `"js:INDEX"` for a line and column in the script of a JavaScript module ([`AddrMode::Js`]).
     #[serde(default, skip_serializing_if = "is_default_value")]
     pub addr_mode: AddrMode,
 
     /// The absolute instruction address of this frame.
     ///
-    /// See [`addr_mode`](Self::addr_mode) for the exact behavior of addresses.
+    /// See [`addr_mode`](Self::addr_mode) for the exact behavior of addresses.  This is required
+    /// in requests, except for JavaScript frames which are located by [`lineno`](Self::lineno)
+    /// and [`colno`](Self::colno) instead, see [`RawStacktrace::frames`].
+    #[serde(default)]
     pub instruction_addr: HexValue,
 
+    /// The metadata token of the .NET method this frame is located in.
+    ///
+    /// This is required for frames with [`AddrMode::Il`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_id: Option<HexValue>,
+
     /// The path to the [module](RawObjectInfo) this frame is located in.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub package: Option<String>,
@@ -214,6 +509,10 @@ pub struct RawFrame {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub lineno: Option<u32>,
 
+    /// The column number within the source line, starting at `1` for the first column.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub colno: Option<u32>,
+
     /// Source context before the context line.
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub pre_context: Vec<String>,
@@ -229,6 +528,13 @@ pub struct RawFrame {
     /// Information about how the raw frame was created.
     #[serde(default, skip_serializing_if = "is_default_value")]
     pub trust: FrameTrust,
+
+    /// The registers recovered for this frame during stack walking.
+    ///
+    /// Only contains registers with known values, which for caller frames usually are the
+    /// stack pointer, instruction pointer and callee-saved registers.
+    #[serde(default, skip_serializing_if = "Registers::is_empty")]
+    pub registers: Registers,
 }
 
 /// How trustworth the instruction pointer of the frame is.
@@ -279,6 +585,34 @@ impl From<minidump_processor::FrameTrust> for FrameTrust {
     }
 }
 
+/// A [`RawFrame`] and whether its instruction address was given.
+#[derive(Deserialize)]
+struct RawFrameInput {
+    #[serde(default)]
+    instruction_addr: Option<HexValue>,
+    #[serde(flatten)]
+    frame: RawFrame,
+}
+
+/// Deserializes frames, requiring an instruction address for all but JavaScript frames.
+fn deserialize_frames<'de, D>(deserializer: D) -> Result<Vec<RawFrame>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<RawFrameInput>::deserialize(deserializer)?
+        .into_iter()
+        .map(|input| {
+            let mut frame = input.frame;
+            match (input.instruction_addr, frame.addr_mode) {
+                (Some(instruction_addr), _) => frame.instruction_addr = instruction_addr,
+                (None, AddrMode::Js(_)) => (),
+                (None, _) => return Err(de::Error::missing_field("instruction_addr")),
+            }
+            Ok(frame)
+        })
+        .collect()
+}
+
 /// A stack trace containing unsymbolicated stack frames.
 #[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
 pub struct RawStacktrace {
@@ -296,8 +630,28 @@ pub struct RawStacktrace {
 
     /// A list of unsymbolicated stack frames.
     ///
-    /// The first entry in the list is the active frame, with its callers below.
+    /// The first entry in the list is the active frame, with its callers below.  All frames need an
+    /// [`instruction_addr`](RawFrame::instruction_addr) unless they use [`AddrMode::Js`].
+    #[serde(deserialize_with = "deserialize_frames")]
     pub frames: Vec<RawFrame>,
+
+    /// Memory captured around stack pointers and registers of this thread.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub memory: Vec<MemoryWindow>,
+
+    /// Where the frames of this stacktrace were recovered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<StacktraceProvenance>,
+}
+
+/// A window of process memory captured in a minidump.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct MemoryWindow {
+    /// The address of the first byte of the window.
+    pub start_addr: HexValue,
+
+    /// The base64-encoded contents of the memory.
+    pub data: String,
 }
 
 /// Specification of a module loaded into the process.
@@ -336,6 +690,13 @@ pub struct RawObjectInfo {
     /// The size is infered from the module list if not specified.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub image_size: Option<u64>,
+
+    /// Whether the module was unloaded before the crash.
+    ///
+    /// Unloaded modules are only reported for minidumps, if frames point into the address
+    /// range the module occupied while it was loaded.
+    #[serde(default, skip_serializing_if = "is_default_value")]
+    pub unloaded: bool,
 }
 
 /// The type of an object file.
@@ -345,6 +706,8 @@ pub enum ObjectType {
     Elf,
     Macho,
     Pe,
+    PeDotnet,
+    SourceMap,
     Wasm,
     Unknown,
 }
@@ -357,6 +720,8 @@ impl FromStr for ObjectType {
             "elf" => ObjectType::Elf,
             "macho" => ObjectType::Macho,
             "pe" => ObjectType::Pe,
+            "pe_dotnet" => ObjectType::PeDotnet,
+            "sourcemap" => ObjectType::SourceMap,
             "wasm" => ObjectType::Wasm,
             _ => ObjectType::Unknown,
         })
@@ -379,6 +744,8 @@ impl fmt::Display for ObjectType {
             ObjectType::Elf => write!(f, "elf"),
             ObjectType::Macho => write!(f, "macho"),
             ObjectType::Pe => write!(f, "pe"),
+            ObjectType::PeDotnet => write!(f, "pe_dotnet"),
+            ObjectType::SourceMap => write!(f, "sourcemap"),
             ObjectType::Wasm => write!(f, "wasm"),
             ObjectType::Unknown => write!(f, "unknown"),
         }
@@ -401,6 +768,9 @@ pub enum FrameStatus {
     MissingSymbol,
     /// No debug image is specified for the address of the frame.
     UnknownImage,
+    /// The frame is located in a module which was unloaded before the crash, and could not be
+    /// symbolicated.
+    UnloadedImage,
     /// The debug file could not be retrieved from any of the sources.
     Missing,
     /// The retrieved debug file could not be processed.
@@ -428,6 +798,40 @@ pub struct SymbolicatedFrame {
     ///     this might occur within recursion, so the instruction address is not a good
     pub original_index: Option<usize>,
 
+    /// Whether this frame was symbolicated from a symbol table only.
+    ///
+    /// This is the case if no debug information file was found for the module, but its code
+    /// file contains a symbol table, such as a PE export table, ELF `.dynsym` or a Mach-O
+    /// symbol table.  Frames symbolicated from a [`SymbolMap`] are marked as well.  These
+    /// frames have no line information and the function name is less reliable, since symbol
+    /// tables often omit private functions.
+    #[serde(default, skip_serializing_if = "is_default_value")]
+    pub symbol_table_only: bool,
+
+    /// Whether this frame belongs to the application.
+    ///
+    /// Only set if the request contains [`InAppRules`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_app: Option<bool>,
+
+    /// Whether this frame is a well-known system frame, such as a thread entry point.
+    ///
+    /// Only determined if the request contains [`InAppRules`].
+    #[serde(default, skip_serializing_if = "is_default_value")]
+    pub system: bool,
+
+    /// Whether this frame is a function inlined into the frame following it.
+    ///
+    /// Inline frames share the instruction address of the frame they were inlined into.
+    #[serde(default, skip_serializing_if = "is_default_value")]
+    pub inlined: bool,
+
+    /// The functions inlined into this frame, starting with the innermost one.
+    ///
+    /// Only populated if the request collapses inline frames, see [`InlineFrames`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inlined_frames: Vec<SymbolicatedFrame>,
+
     #[serde(flatten)]
     pub raw: RawFrame,
 }
@@ -456,6 +860,51 @@ pub struct CompleteStacktrace {
 
     /// Frames of this stack trace.
     pub frames: Vec<SymbolicatedFrame>,
+
+    /// Memory captured around stack pointers and registers, only returned for minidumps.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub memory: Vec<MemoryWindow>,
+
+    /// Where the frames were recovered, only returned for minidumps containing client-side
+    /// stacktraces.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<StacktraceProvenance>,
+
+    /// The symbols the registers point into, if requested in the [`RequestOptions`].
+    ///
+    /// Only contains registers whose values point into a module.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub register_symbols: BTreeMap<String, PointerSymbol>,
+}
+
+/// The symbol a pointer, such as a register value, points into.
+///
+/// See [`RequestOptions::symbolicate_registers`].
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
+pub struct PointerSymbol {
+    /// The value of the pointer.
+    pub addr: HexValue,
+
+    /// The path to the module the pointer points into.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+
+    /// The mangled name of the symbol the pointer points into.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+
+    /// The demangled name of the symbol the pointer points into.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<String>,
+
+    /// The start address of the symbol.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sym_addr: Option<HexValue>,
+
+    /// The offset of the pointer from the start of the symbol.
+    ///
+    /// If no symbol was found, this is the offset from the start of the module instead.
+    pub offset: HexValue,
 }
 
 /// Information on a debug information file.
@@ -691,6 +1140,25 @@ pub struct CompletedSymbolicationResponse {
 
     /// A list of images, extended with status information.
     pub modules: Vec<CompleteObjectInfo>,
+
+    /// Additional sections of the minidump, if requested in the [`RequestOptions`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minidump_streams: Option<MinidumpStreams>,
+
+    /// A report on the quality of the stacktraces, if requested in the [`RequestOptions`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityReport>,
+
+    /// The symbol the crash address points into, if requested in the [`RequestOptions`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crash_address_symbol: Option<PointerSymbol>,
+
+    /// The minidump with the symbolicated stacktraces embedded.
+    ///
+    /// This is returned as binary response by the `/minidump/symbolicated` endpoint, and never
+    /// serialized.
+    #[serde(skip)]
+    pub symbolicated_minidump: Option<Arc<[u8]>>,
 }
 
 impl CompletedSymbolicationResponse {
@@ -705,6 +1173,249 @@ impl CompletedSymbolicationResponse {
     }
 }
 
+/// A report on the quality of the stacktraces in a [`CompletedSymbolicationResponse`].
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct QualityReport {
+    /// The quality of all stacktraces combined.
+    pub total: StacktraceQuality,
+
+    /// The quality of each stacktrace, in the same order as the stacktraces.
+    pub stacktraces: Vec<StacktraceQuality>,
+
+    /// Modules whose missing debug information files caused unsymbolicated frames.
+    ///
+    /// Uploading debug information files for these modules improves the stacktraces.
+    pub missing_modules: Vec<MissingModule>,
+}
+
+/// Counters describing the quality of stacktraces.
+///
+/// For a single stacktrace, the counters of traces are either `0` or `1`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct StacktraceQuality {
+    /// Stacktraces which do not end in a well known thread base.
+    pub truncated_traces: u64,
+
+    /// Stacktraces with less than 3 frames.
+    pub short_traces: u64,
+
+    /// Stacktraces with at least one scanned or unsymbolicated frame.
+    pub bad_traces: u64,
+
+    /// Frames that were found by scanning the stack.
+    ///
+    /// These are frequently wrong and lead to bad and incomplete stacktraces, which can be
+    /// improved by having more usable CFI.
+    pub scanned_frames: u64,
+
+    /// Frames that could not be symbolicated.
+    ///
+    /// These may be the result of unavailable or broken debug information.
+    pub unsymbolicated_frames: u64,
+
+    /// Unsymbolicated frames taken from the CPU context.
+    ///
+    /// This is an indication of broken contexts, or failure to extract them from minidumps.
+    pub unsymbolicated_context_frames: u64,
+
+    /// Unsymbolicated frames found by scanning the stack.
+    pub unsymbolicated_scanned_frames: u64,
+
+    /// Unsymbolicated frames found via CFI.
+    ///
+    /// These are the result of the *previous* frame being wrongly scanned.
+    pub unsymbolicated_cfi_frames: u64,
+
+    /// Frames referencing memory outside of any known module.
+    ///
+    /// These may be the result of issues in the client-side module finder, or broken
+    /// debug ids.
+    pub unmapped_frames: u64,
+
+    /// Frames symbolicated from the symbol table of a code file.
+    ///
+    /// These lack line information, which can be improved by having debug information.
+    pub symbol_table_frames: u64,
+}
+
+impl StacktraceQuality {
+    /// Adds the counters of `other` to these counters.
+    pub fn add(&mut self, other: &Self) {
+        self.truncated_traces += other.truncated_traces;
+        self.short_traces += other.short_traces;
+        self.bad_traces += other.bad_traces;
+        self.scanned_frames += other.scanned_frames;
+        self.unsymbolicated_frames += other.unsymbolicated_frames;
+        self.unsymbolicated_context_frames += other.unsymbolicated_context_frames;
+        self.unsymbolicated_scanned_frames += other.unsymbolicated_scanned_frames;
+        self.unsymbolicated_cfi_frames += other.unsymbolicated_cfi_frames;
+        self.unmapped_frames += other.unmapped_frames;
+        self.symbol_table_frames += other.symbol_table_frames;
+    }
+}
+
+/// A module which is missing debug information, see [`QualityReport`].
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MissingModule {
+    /// The index of the module in the `modules` list of the response.
+    pub module_index: usize,
+
+    /// Identifier of the debug file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debug_id: Option<String>,
+
+    /// Name of the debug file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debug_file: Option<String>,
+
+    /// Identifier of the code file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code_id: Option<String>,
+
+    /// Name of the code file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code_file: Option<String>,
+
+    /// The number of frames in this module which could not be symbolicated.
+    pub unsymbolicated_frames: u64,
+}
+
+impl MissingModule {
+    /// Creates an entry for the module at `module_index` with the given number of frames.
+    pub fn new(module_index: usize, info: &RawObjectInfo, unsymbolicated_frames: u64) -> Self {
+        Self {
+            module_index,
+            debug_id: info.debug_id.clone(),
+            debug_file: info.debug_file.clone(),
+            code_id: info.code_id.clone(),
+            code_file: info.code_file.clone(),
+            unsymbolicated_frames,
+        }
+    }
+}
+
+/// Additional sections of a minidump returned in the response.
+///
+/// Only the sections requested in [`RequestOptions::minidump_sections`] are populated, and
+/// only if they were present in the minidump.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MinidumpStreams {
+    /// The exception record of the crashing thread.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exception: Option<ExceptionRecord>,
+
+    /// The memory regions of the process.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_info: Option<Vec<MemoryRegion>>,
+
+    /// The modules loaded into the process, as recorded in the minidump.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modules: Option<Vec<RawObjectInfo>>,
+
+    /// The modules which were unloaded before the minidump was written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unloaded_modules: Option<Vec<UnloadedModule>>,
+
+    /// The handles which were open in the process.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handles: Option<Vec<HandleDescriptor>>,
+
+    /// The contents of the `/proc` files of the process on Linux.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linux_proc: Option<LinuxProcStreams>,
+}
+
+/// The exception record of a minidump.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ExceptionRecord {
+    /// The ID of the thread which caused the exception.
+    pub thread_id: u32,
+    /// The OS-specific exception code, e.g. the signal number on Linux.
+    pub code: HexValue,
+    /// The OS-specific exception flags, e.g. the signal code on Linux.
+    pub flags: HexValue,
+    /// The address where the exception occurred.
+    pub address: HexValue,
+    /// Additional exception-specific parameters.
+    pub parameters: Vec<HexValue>,
+}
+
+/// A memory region from the memory info list of a minidump.
+///
+/// The `state`, `protection` and `type` values are the raw Windows `MEMORY_BASIC_INFORMATION`
+/// flags.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MemoryRegion {
+    /// The start address of the region.
+    pub base_address: HexValue,
+    /// The start address of the allocation this region belongs to.
+    pub allocation_base: HexValue,
+    /// The memory protection when the allocation was initially created.
+    pub allocation_protection: HexValue,
+    /// The size of the region in bytes.
+    pub region_size: u64,
+    /// The state of the pages in the region, e.g. committed or reserved.
+    pub state: HexValue,
+    /// The current memory protection of the region.
+    pub protection: HexValue,
+    /// The type of pages in the region, e.g. image or mapped.
+    #[serde(rename = "type")]
+    pub ty: HexValue,
+}
+
+/// A module which was unloaded before the minidump was written.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UnloadedModule {
+    /// The path of the module's code file.
+    pub code_file: String,
+    /// The address where the module was loaded.
+    pub image_addr: HexValue,
+    /// The size of the module in memory.
+    pub image_size: u64,
+    /// The checksum of the module's image.
+    pub checksum: HexValue,
+    /// The timestamp of the module's image.
+    pub timestamp: u32,
+}
+
+/// A handle which was open in the process when the minidump was written.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HandleDescriptor {
+    /// The value of the handle.
+    pub handle: HexValue,
+    /// The name of the object type, e.g. `File` or `Event`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub type_name: Option<String>,
+    /// The name of the object, e.g. the path of a file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_name: Option<String>,
+    /// The attributes of the handle.
+    pub attributes: HexValue,
+    /// The access rights granted to the handle.
+    pub granted_access: HexValue,
+    /// The number of handles to the object.
+    pub handle_count: u32,
+    /// The number of pointers to the object.
+    pub pointer_count: u32,
+}
+
+/// The `/proc` files of a Linux process, as written by Breakpad and Crashpad.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LinuxProcStreams {
+    /// The command line arguments, from `/proc/self/cmdline`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmdline: Option<Vec<String>>,
+    /// The environment variables, from `/proc/self/environ`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environ: Option<BTreeMap<String, String>>,
+    /// The memory mappings, one line per mapping, from `/proc/self/maps`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maps: Option<Vec<String>>,
+    /// The distribution information, from `/etc/lsb-release`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lsb_release: Option<BTreeMap<String, String>>,
+}
+
 /// Information about the operating system.
 #[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct SystemInfo {
@@ -13,6 +13,11 @@ pub enum AddrMode {
     Abs,
     /// Declares an address to be relative to an indexed module.
     Rel(usize),
+    /// Declares an address to be an IL offset within a method of an indexed .NET module.
+    Il(usize),
+    /// Declares a frame to be located by line and column in the script of an indexed
+    /// JavaScript module, without an address.
+    Js(usize),
 }
 
 impl Default for AddrMode {
@@ -26,6 +31,8 @@ impl fmt::Display for AddrMode {
         match *self {
             AddrMode::Abs => write!(f, "abs"),
             AddrMode::Rel(idx) => write!(f, "rel:{}", idx),
+            AddrMode::Il(idx) => write!(f, "il:{}", idx),
+            AddrMode::Js(idx) => write!(f, "js:{}", idx),
         }
     }
 }
@@ -58,6 +65,8 @@ impl FromStr for AddrMode {
             .ok_or(ParseAddrModeError)?;
         match kind {
             "rel" => Ok(AddrMode::Rel(index)),
+            "il" => Ok(AddrMode::Il(index)),
+            "js" => Ok(AddrMode::Js(index)),
             _ => Err(ParseAddrModeError),
         }
     }
@@ -0,0 +1,116 @@
+//! Checksum trailers for cache files.
+//!
+//! Cache files can carry a SHA-256 checksum of their contents at the very end, followed by
+//! the [`CHECKSUM_MARKER`].  This allows detecting files which were truncated or otherwise
+//! corrupted after they were written, so they can be discarded and recomputed.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+use symbolic::common::ByteView;
+
+/// This marker denotes that a cache file has an appended checksum.
+const CHECKSUM_MARKER: &[u8] = b"WITH_SHA256";
+
+/// The length of the SHA-256 checksum preceding the [`CHECKSUM_MARKER`].
+const CHECKSUM_LEN: usize = 32;
+
+/// The result of verifying the checksum trailer of a cache file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// The file does not carry a checksum, e.g. because it was written by an older version or
+    /// its end was truncated.
+    Missing,
+    /// The checksum matches the contents of the file.
+    Valid,
+    /// The checksum does not match, the file is corrupted.
+    Invalid,
+}
+
+/// Splits `data` into its contents and checksum, if it has a checksum trailer.
+fn split_trailer(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let data = data.strip_suffix(CHECKSUM_MARKER)?;
+    let offset = data.len().checked_sub(CHECKSUM_LEN)?;
+    Some(data.split_at(offset))
+}
+
+/// Verifies the checksum trailer of `data`.
+pub fn verify(data: &[u8]) -> Checksum {
+    match split_trailer(data) {
+        Some((contents, checksum)) if Sha256::digest(contents).as_slice() == checksum => {
+            Checksum::Valid
+        }
+        Some(_) => Checksum::Invalid,
+        None => Checksum::Missing,
+    }
+}
+
+/// Returns the contents of `data` without the checksum trailer, if there is one.
+pub fn strip(data: &[u8]) -> &[u8] {
+    match split_trailer(data) {
+        Some((contents, _)) => contents,
+        None => data,
+    }
+}
+
+/// Appends a checksum of the file's current contents to the file at `path`.
+pub fn append_to(path: &Path) -> io::Result<()> {
+    let checksum = Sha256::digest(&ByteView::open(path)?);
+
+    let mut file = OpenOptions::new().append(true).open(path)?;
+    file.write_all(&checksum)?;
+    file.write_all(CHECKSUM_MARKER)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_roundtrip() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), b"some cache contents").unwrap();
+
+        append_to(file.path()).unwrap();
+
+        let data = std::fs::read(file.path()).unwrap();
+        assert_eq!(verify(&data), Checksum::Valid);
+        assert_eq!(strip(&data), b"some cache contents");
+    }
+
+    #[test]
+    fn test_checksum_missing() {
+        assert_eq!(verify(b"some cache contents"), Checksum::Missing);
+        assert_eq!(verify(b"WITH_SHA256"), Checksum::Missing);
+        assert_eq!(strip(b"some cache contents"), b"some cache contents");
+    }
+
+    #[test]
+    fn test_checksum_truncated() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), b"some cache contents").unwrap();
+        append_to(file.path()).unwrap();
+
+        // Truncating the file cuts off the trailer, callers need to know whether a checksum
+        // is expected to detect this.
+        let mut data = std::fs::read(file.path()).unwrap();
+        data.truncate(data.len() - 1);
+        assert_eq!(verify(&data), Checksum::Missing);
+        data.truncate(data.len() - CHECKSUM_MARKER.len());
+        assert_eq!(verify(&data), Checksum::Missing);
+    }
+
+    #[test]
+    fn test_checksum_corrupted() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), b"some cache contents").unwrap();
+        append_to(file.path()).unwrap();
+
+        let mut data = std::fs::read(file.path()).unwrap();
+        data[0] = b'S';
+        assert_eq!(verify(&data), Checksum::Invalid);
+    }
+}
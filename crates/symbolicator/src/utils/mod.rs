@@ -1,4 +1,5 @@
 pub mod addr;
+pub mod checksum;
 pub mod compression;
 pub mod futures;
 pub mod gcs;
@@ -2,6 +2,7 @@ use std::borrow::Cow;
 use std::fmt::Write;
 
 use symbolic::common::{CodeId, DebugId, Uuid};
+use url::Url;
 
 use crate::sources::{DirectoryLayout, DirectoryLayoutType, FileType, FilenameCasing};
 use crate::types::{Glob, ObjectId, ObjectType};
@@ -71,6 +72,25 @@ fn get_pdb_symstore_path(identifier: &ObjectId, ssqp_casing: bool) -> Option<Str
     Some(format!("{}/{}/{}", debug_file, debug_id, debug_file))
 }
 
+/// Portable PDBs are indexed by their GUID and a constant age of `FFFFFFFF` on symbol servers.
+fn get_portable_pdb_symstore_path(identifier: &ObjectId, ssqp_casing: bool) -> Option<String> {
+    let debug_file = identifier.debug_file_basename()?;
+    let debug_id = identifier.debug_id.as_ref()?;
+
+    let debug_file = if ssqp_casing {
+        Cow::Owned(debug_file.to_lowercase())
+    } else {
+        Cow::Borrowed(debug_file)
+    };
+    let debug_id = if ssqp_casing {
+        format!("{:x}ffffffff", debug_id.uuid().to_simple_ref())
+    } else {
+        format!("{:X}FFFFFFFF", debug_id.uuid().to_simple_ref())
+    };
+
+    Some(format!("{}/{}/{}", debug_file, debug_id, debug_file))
+}
+
 fn get_pe_symstore_path(identifier: &ObjectId, ssqp_casing: bool) -> Option<String> {
     let code_file = identifier.code_file_basename()?;
     let code_id = identifier.code_id.as_ref()?.as_str();
@@ -95,6 +115,33 @@ fn get_pe_symstore_path(identifier: &ObjectId, ssqp_casing: bool) -> Option<Stri
     Some(format!("{}/{}/{}", code_file, code_id, code_file))
 }
 
+/// JavaScript files are stored at the path of their URL, without the scheme and host.
+///
+/// The `~/` prefix, which matches any host, is stripped as well.  Paths escaping the root of the
+/// source are rejected.
+fn get_js_path(filetype: FileType, identifier: &ObjectId) -> Option<String> {
+    if identifier.object_type != ObjectType::SourceMap {
+        return None;
+    }
+
+    let url = match filetype {
+        FileType::MinifiedSource => identifier.code_file.as_ref()?,
+        FileType::SourceMap => identifier.debug_file.as_ref()?,
+        _ => return None,
+    };
+    let path = match Url::parse(url) {
+        Ok(url) => Cow::Owned(url.path().to_owned()),
+        Err(_) => Cow::Borrowed(url.strip_prefix('~').unwrap_or(url)),
+    };
+
+    let path = path.trim_start_matches('/');
+    if path.is_empty() || path.split('/').any(|segment| segment == "..") {
+        return None;
+    }
+
+    Some(path.to_owned())
+}
+
 fn get_breakpad_path(identifier: &ObjectId) -> Option<String> {
     // wasm files never get a breakpad path
     if identifier.object_type == ObjectType::Wasm {
@@ -157,6 +204,14 @@ fn get_native_paths(filetype: FileType, identifier: &ObjectId) -> Vec<String> {
         FileType::Pe => get_pe_symstore_path(identifier, false)
             .into_iter()
             .collect(),
+        FileType::PortablePdb => get_portable_pdb_symstore_path(identifier, false)
+            .into_iter()
+            .collect(),
+
+        // JavaScript files are stored at the path of their URL.
+        FileType::MinifiedSource | FileType::SourceMap => {
+            get_js_path(filetype, identifier).into_iter().collect()
+        }
 
         // Breakpad has its own layout similar to Microsoft Symbol Server
         // See: https://github.com/google/breakpad/blob/79ba6a494fb2097b39f76fe6a4b4b4f407e32a02/src/processor/simple_symbol_supplier.cc
@@ -182,7 +237,7 @@ fn get_native_paths(filetype: FileType, identifier: &ObjectId) -> Vec<String> {
                     Some(path) => path,
                     None => return vec![],
                 },
-                ObjectType::Unknown => return vec![],
+                ObjectType::PeDotnet | ObjectType::SourceMap | ObjectType::Unknown => return vec![],
             };
             primary_path.push_str(".src.zip");
             let mut rv = vec![];
@@ -257,6 +312,7 @@ fn get_symstore_path(
 
         FileType::Pdb => get_pdb_symstore_path(identifier, ssqp_casing),
         FileType::Pe => get_pe_symstore_path(identifier, ssqp_casing),
+        FileType::PortablePdb => get_portable_pdb_symstore_path(identifier, ssqp_casing),
 
         // Microsoft SymbolServer does not specify Breakpad.
         FileType::Breakpad => None,
@@ -286,6 +342,9 @@ fn get_symstore_path(
 
         // Microsoft SymbolServer does not specify Usym.
         FileType::Usym => None,
+
+        // Microsoft SymbolServer does not specify JavaScript.
+        FileType::MinifiedSource | FileType::SourceMap => None,
     }
 }
 
@@ -319,7 +378,7 @@ fn get_debuginfod_path(filetype: FileType, identifier: &ObjectId) -> Option<Stri
         FileType::MachCode | FileType::MachDebug => None,
 
         // PDB and PE are not supported
-        FileType::Pdb | FileType::Pe => None,
+        FileType::Pdb | FileType::Pe | FileType::PortablePdb => None,
 
         // WASM is not supported
         FileType::WasmDebug | FileType::WasmCode => None,
@@ -332,6 +391,7 @@ fn get_debuginfod_path(filetype: FileType, identifier: &ObjectId) -> Option<Stri
         FileType::UuidMap => None,
         FileType::BcSymbolMap => None,
         FileType::Usym => None,
+        FileType::MinifiedSource | FileType::SourceMap => None,
     }
 }
 
@@ -344,6 +404,8 @@ fn get_debuginfod_path(filetype: FileType, identifier: &ObjectId) -> Option<Stri
 fn get_search_target_object_type(filetype: FileType, identifier: &ObjectId) -> ObjectType {
     match filetype {
         FileType::Pe | FileType::Pdb => ObjectType::Pe,
+        FileType::PortablePdb => ObjectType::PeDotnet,
+        FileType::MinifiedSource | FileType::SourceMap => ObjectType::SourceMap,
         FileType::MachCode | FileType::MachDebug | FileType::UuidMap | FileType::BcSymbolMap => {
             ObjectType::Macho
         }
@@ -365,6 +427,9 @@ fn get_unified_path(filetype: FileType, identifier: &ObjectId) -> Option<String>
         FileType::UuidMap => "uuidmap",
         FileType::BcSymbolMap => "bcsymbolmap",
         FileType::Usym => "usym",
+        FileType::PortablePdb => "portablepdb",
+        FileType::MinifiedSource => "minified_source",
+        FileType::SourceMap => "sourcemap",
     };
 
     // determine the ID we use for the path
@@ -373,7 +438,9 @@ fn get_unified_path(filetype: FileType, identifier: &ObjectId) -> Option<String>
         // always.  This is done because code IDs by themselves are not reliable
         // enough for PEs and are only useful together with the file name which
         // we do not want to encode.
-        ObjectType::Pe => Cow::Owned(identifier.debug_id?.breakpad().to_string().to_lowercase()),
+        ObjectType::Pe | ObjectType::PeDotnet => {
+            Cow::Owned(identifier.debug_id?.breakpad().to_string().to_lowercase())
+        }
         // On mach we can always determine the code ID from the debug ID if the
         // code ID is unavailable.  We apply the same rule to WASM files as we
         // suggest Uuids to be used as build ids.
@@ -388,6 +455,10 @@ fn get_unified_path(filetype: FileType, identifier: &ObjectId) -> Option<String>
         // find this file at all.  See symsorter which will never use the debug ID for
         // such files.
         ObjectType::Elf => Cow::Borrowed(identifier.code_id.as_ref()?.as_str()),
+        // JavaScript files can only be found by their debug ID, as their URLs are not unique.
+        ObjectType::SourceMap => {
+            Cow::Owned(identifier.debug_id?.uuid().to_simple_ref().to_string())
+        }
         // Guess we're out of luck.
         ObjectType::Unknown => return None,
     };
@@ -432,7 +503,10 @@ pub fn get_directory_paths(
 
     // when fetching PE and PDB files we generally allow the also the
     // compressed matches (last char subtituted with an underscore)
-    if filetype == FileType::Pdb || filetype == FileType::Pe {
+    if matches!(
+        filetype,
+        FileType::Pdb | FileType::Pe | FileType::PortablePdb
+    ) {
         paths = paths
             .into_iter()
             .flat_map(|path| {
@@ -583,6 +657,20 @@ mod tests {
             debug_file: Some("/lib/x86_64-linux-gnu/libm-2.23.so".into()),
             object_type: ObjectType::Elf,
         };
+        static ref PPDB_OBJECT_ID: ObjectId = ObjectId {
+            code_id: None,
+            code_file: Some("C:\\projects\\app\\bin\\Release\\App.dll".into()),
+            debug_id: Some("0c1033f7-8632-492e-91c6-c314b72e1920-a09672e1".parse().unwrap()),
+            debug_file: Some("C:\\projects\\app\\obj\\Release\\App.pdb".into()),
+            object_type: ObjectType::PeDotnet,
+        };
+        static ref JS_OBJECT_ID: ObjectId = ObjectId {
+            code_id: None,
+            code_file: Some("https://example.com/static/app.min.js".into()),
+            debug_id: Some("c941d872-af1f-4f0c-a7ff-ad3d295fe153".parse().unwrap()),
+            debug_file: Some("~/static/app.min.js.map".into()),
+            object_type: ObjectType::SourceMap,
+        };
         static ref WASM_OBJECT_ID: ObjectId = ObjectId {
             code_id: Some("67e9247c814e392ba027dbde6748fcbf".parse().unwrap()),
             code_file: None,
@@ -607,7 +695,11 @@ mod tests {
         path_test!(FileType::Pdb, PE_OBJECT_ID, @"crash.pdb/3249D99D0C4049318610F4E4FB0B69361/crash.pdb");
         path_test!(FileType::Pe, PE_OBJECT_ID, @"crash.exe/5AB380779000/crash.exe");
         path_test!(FileType::Breakpad, PE_OBJECT_ID, @"crash.pdb/3249D99D0C4049318610F4E4FB0B69361/crash.sym");
+        path_test!(FileType::PortablePdb, PPDB_OBJECT_ID, @"App.pdb/0C1033F78632492E91C6C314B72E1920FFFFFFFF/App.pdb");
         path_test!(FileType::SourceBundle, PE_OBJECT_ID, @"crash.pdb/3249D99D0C4049318610F4E4FB0B69361/crash.src.zip");
+        path_test!(FileType::MinifiedSource, JS_OBJECT_ID, @"static/app.min.js");
+        path_test!(FileType::SourceMap, JS_OBJECT_ID, @"static/app.min.js.map");
+        path_test!(FileType::MinifiedSource, ELF_OBJECT_ID, @"");
         path_test!(FileType::MachCode, MACHO_OBJECT_ID, @"67E9/247C/814E/392B/A027/DBDE6748FCBF.app");
         path_test!(FileType::MachDebug, MACHO_OBJECT_ID, @"67E9/247C/814E/392B/A027/DBDE6748FCBF");
         path_test!(FileType::Breakpad, MACHO_OBJECT_ID, @"crash/67E9247C814E392BA027DBDE6748FCBF0/crash.sym");
@@ -639,6 +731,9 @@ mod tests {
         path_test!(FileType::Pe, PE_OBJECT_ID, @"32/49d99d0c4049318610f4e4fb0b69361/executable");
         path_test!(FileType::Breakpad, PE_OBJECT_ID, @"32/49d99d0c4049318610f4e4fb0b69361/breakpad");
         path_test!(FileType::SourceBundle, PE_OBJECT_ID, @"32/49d99d0c4049318610f4e4fb0b69361/sourcebundle");
+        path_test!(FileType::PortablePdb, PPDB_OBJECT_ID, @"0c/1033f78632492e91c6c314b72e1920a09672e1/portablepdb");
+        path_test!(FileType::MinifiedSource, JS_OBJECT_ID, @"c9/41d872af1f4f0ca7ffad3d295fe153/minified_source");
+        path_test!(FileType::SourceMap, JS_OBJECT_ID, @"c9/41d872af1f4f0ca7ffad3d295fe153/sourcemap");
         path_test!(FileType::MachCode, MACHO_OBJECT_ID, @"67/e9247c814e392ba027dbde6748fcbf/executable");
         path_test!(FileType::MachDebug, MACHO_OBJECT_ID, @"67/e9247c814e392ba027dbde6748fcbf/debuginfo");
         path_test!(FileType::WasmDebug, WASM_OBJECT_ID, @"67/e9247c814e392ba027dbde6748fcbf/debuginfo");
@@ -675,6 +770,7 @@ mod tests {
         path_test!(FileType::Pdb, PE_OBJECT_ID, @"crash.pdb/3249D99D0C4049318610F4E4FB0B69361/crash.pdb");
         path_test!(FileType::Pe, PE_OBJECT_ID, @"crash.exe/5AB380779000/crash.exe");
         path_test!(FileType::SourceBundle, PE_OBJECT_ID, @"crash.pdb/3249D99D0C4049318610F4E4FB0B69361/crash.src.zip");
+        path_test!(FileType::PortablePdb, PPDB_OBJECT_ID, @"App.pdb/0C1033F78632492E91C6C314B72E1920FFFFFFFF/App.pdb");
         path_test!(FileType::MachCode, MACHO_OBJECT_ID, @"crash/mach-uuid-67e9247c814e392ba027dbde6748fcbf/crash");
         path_test!(FileType::MachDebug, MACHO_OBJECT_ID, @"_.dwarf/mach-uuid-sym-67e9247c814e392ba027dbde6748fcbf/_.dwarf");
         path_test!(FileType::ElfCode, ELF_OBJECT_ID, @"libm-2.23.so/elf-buildid-dfb85de42daffd09640c8fe377d572de3e168920/libm-2.23.so");
@@ -698,6 +794,16 @@ mod tests {
         path_test!(FileType::ElfDebug, ELF_OBJECT_ID, @"_/_.debug/elf-buildid-sym-dfb85de42daffd09640c8fe377d572de3e168920/_.debug");
     }
 
+    #[test]
+    fn test_get_js_path_outside_root() {
+        let identifier = ObjectId {
+            code_file: Some("~/static/../../etc/passwd".into()),
+            object_type: ObjectType::SourceMap,
+            ..Default::default()
+        };
+        assert_eq!(get_js_path(FileType::MinifiedSource, &identifier), None);
+    }
+
     #[test]
     fn test_matches_path_patterns_empty() {
         assert!(matches_path_patterns(